@@ -0,0 +1,124 @@
+// Band-limited step synthesis in the style of blip_buf. Each change in the amplitude of the APU
+// output is added to the buffer as a band-limited step instead of being point sampled, which
+// removes the aliasing of frequencies above the Nyquist frequency of the output sample rate.
+// http://www.slack.net/~ant/bl-synth/
+#[cfg(not(target_arch = "wasm32"))]
+use serde_derive::{Deserialize, Serialize};
+use std::f64::consts;
+
+// The number of output samples on each side of the center of a step.
+const HALF_WIDTH: usize = 12;
+const WIDTH: usize = HALF_WIDTH * 2;
+// The number of fractional sample positions that steps are aligned to.
+const PHASE_COUNT: usize = 64;
+// The cutoff frequency of the steps as a fraction of the output sample rate.
+const CUTOFF: f64 = 0.45;
+const BUFFER_LEN: usize = 32;
+
+#[derive(Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Deserialize, Serialize))]
+pub struct BlipBuffer {
+    // The length of a clock in output samples.
+    samples_per_clock: f64,
+    // The position of the current clock in the current output sample.
+    time: f64,
+    // The impulse response of each phase. The steps are the running sum of the impulses.
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip, default = "build_kernels"))]
+    kernels: Vec<[f32; WIDTH]>,
+    // The differences between consecutive output samples, starting at the current output sample.
+    deltas: [f32; BUFFER_LEN],
+    index: usize,
+    amplitude: f32,
+    sum: f32,
+}
+
+impl BlipBuffer {
+    pub fn new(clock_rate: f64, sample_rate: f64) -> Self {
+        BlipBuffer {
+            samples_per_clock: sample_rate / clock_rate,
+            time: 0.0,
+            kernels: build_kernels(),
+            deltas: [0.0; BUFFER_LEN],
+            index: 0,
+            amplitude: 0.0,
+            sum: 0.0,
+        }
+    }
+
+    // Changes the sample rate without clearing the output.
+    pub fn set_rate(&mut self, clock_rate: f64, sample_rate: f64) {
+        self.samples_per_clock = sample_rate / clock_rate;
+    }
+
+    // Clears the output while keeping the position in the current output sample, so that a copy
+    // of a buffer produces its samples on the same clocks.
+    pub fn clear(&mut self) {
+        self.deltas = [0.0; BUFFER_LEN];
+        self.amplitude = 0.0;
+        self.sum = 0.0;
+    }
+
+    // Sets the amplitude at the current clock and advances by one clock. Returns the next output
+    // sample if the clock ends it. The output is delayed by about `HALF_WIDTH` samples.
+    pub fn step(&mut self, amplitude: f32) -> Option<f32> {
+        let delta = amplitude - self.amplitude;
+        if delta != 0.0 {
+            self.amplitude = amplitude;
+            let phase = (self.time * PHASE_COUNT as f64) as usize;
+            for (i, val) in self.kernels[phase].iter().enumerate() {
+                self.deltas[(self.index + i) % BUFFER_LEN] += delta * val;
+            }
+        }
+
+        self.time += self.samples_per_clock;
+        if self.time < 1.0 {
+            return None;
+        }
+        self.time -= 1.0;
+        self.sum += self.deltas[self.index];
+        self.deltas[self.index] = 0.0;
+        self.index = (self.index + 1) % BUFFER_LEN;
+        Some(self.sum)
+    }
+}
+
+// Builds the band-limited impulse of each phase. The kernels do not depend on the sample rate.
+fn build_kernels() -> Vec<[f32; WIDTH]> {
+    (0..PHASE_COUNT)
+        .map(|phase| {
+            let offset = phase as f64 / PHASE_COUNT as f64;
+            let mut kernel = [0.0; WIDTH];
+            let mut total = 0.0;
+            let mut impulse = [0.0; WIDTH];
+            for (i, val) in impulse.iter_mut().enumerate() {
+                // The distance from the center of the step, which is between samples
+                // `HALF_WIDTH - 1` and `HALF_WIDTH` when the offset is 0.
+                let x = i as f64 - (HALF_WIDTH - 1) as f64 - offset;
+                *val = sinc(2.0 * CUTOFF * x) * blackman(x / HALF_WIDTH as f64);
+                total += *val;
+            }
+            // Each impulse is normalized so that the steps reach the new amplitude exactly.
+            for (val, impulse) in kernel.iter_mut().zip(impulse.iter()) {
+                *val = (impulse / total) as f32;
+            }
+            kernel
+        })
+        .collect()
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (consts::PI * x).sin() / (consts::PI * x)
+    }
+}
+
+// The Blackman window, where `x` is from -1 to 1.
+fn blackman(x: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        return 0.0;
+    }
+    let x = consts::PI * (x + 1.0);
+    0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos()
+}
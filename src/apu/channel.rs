@@ -0,0 +1,80 @@
+// Per-channel settings and the optional output of each channel on its own.
+use super::blip::BlipBuffer;
+use super::filter::FirstOrderFilter;
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+pub const CHANNEL_COUNT: usize = 5;
+
+/// A channel of the APU.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub enum Channel {
+    /// The first pulse channel.
+    Pulse1,
+    /// The second pulse channel.
+    Pulse2,
+    /// The triangle channel.
+    Triangle,
+    /// The noise channel.
+    Noise,
+    /// The delta modulation channel.
+    Dmc,
+}
+
+impl Channel {
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ChannelSettings {
+    pub enabled: bool,
+    // The output of the channel is multiplied by the volume before it is mixed.
+    pub volume: f32,
+    // The stereo position from -1 for left to 1 for right.
+    pub pan: f32,
+}
+
+impl ChannelSettings {
+    // Returns the level of `output` on the left and right sides. A centered channel is at full
+    // level on both sides, so that a stereo mix with every channel centered matches the mono mix.
+    pub fn stereo_levels(&self, output: u8) -> (f32, f32) {
+        if !self.enabled {
+            return (0.0, 0.0);
+        }
+        let level = f32::from(output) * self.volume;
+        let left = (1.0 - self.pan).min(1.0);
+        let right = (1.0 + self.pan).min(1.0);
+        (level * left, level * right)
+    }
+}
+
+impl Default for ChannelSettings {
+    fn default() -> Self {
+        ChannelSettings {
+            enabled: true,
+            volume: 1.0,
+            pan: 0.0,
+        }
+    }
+}
+
+// An output that goes through its own synthesis and filters, which is either a channel mixed
+// without the other channels or a side of the stereo mix.
+pub struct ChannelOutput {
+    pub blip: BlipBuffer,
+    pub filters: Vec<Box<dyn FirstOrderFilter>>,
+    pub buffer: Vec<f32>,
+}
+
+impl ChannelOutput {
+    pub fn process(&mut self, amplitude: f32) -> Option<f32> {
+        let mut sample = self.blip.step(amplitude)?;
+        for filter in &mut self.filters {
+            sample = filter.process(sample);
+        }
+        Some(sample)
+    }
+}
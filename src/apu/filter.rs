@@ -1,7 +1,42 @@
+#[cfg(not(target_arch = "wasm32"))]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::f32::consts;
+use std::rc::Rc;
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
 
+/// A filter that is applied to each audio sample after mixing.
 pub trait FirstOrderFilter {
+    /// Filters a sample and returns the output.
     fn process(&mut self, input_sample: f32) -> f32;
+
+    /// Returns the state of the filter that is stored in save states. Filters without state
+    /// return an empty `Vec`.
+    fn state(&self) -> Vec<f32> {
+        Vec::new()
+    }
+
+    /// Restores the state returned by `state`.
+    fn load_state(&mut self, _state: &[f32]) {}
+}
+
+// Builds the filters of a custom filter chain for a sample rate.
+pub type FilterFactory = Rc<dyn Fn(f32) -> Vec<Box<dyn FirstOrderFilter>>>;
+
+/// A preset chain of filters that approximates the audio output of a console.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub enum FilterProfile {
+    /// The NES, which has a 90 Hz high-pass filter, a 440 Hz high-pass filter, and a 14 kHz
+    /// low-pass filter.
+    #[default]
+    Nes,
+    /// The Famicom, which has a 37 Hz high-pass filter and a 14 kHz low-pass filter.
+    Famicom,
+    /// A 10 Hz high-pass filter that only removes the DC offset of the APU.
+    Clean,
+    /// Filters built by the function passed to `Nes::set_custom_filters`.
+    Custom,
 }
 
 fn get_alpha(frequency: u64, sample_rate: f32) -> f32 {
@@ -10,19 +45,21 @@ fn get_alpha(frequency: u64, sample_rate: f32) -> f32 {
     rc / (rc + dt)
 }
 
+/// A first order low-pass filter.
 // https://en.wikipedia.org/wiki/Low-pass_filter
 pub struct LowPassFilter {
-    prev_input_sample: f32,
     prev_output_sample: f32,
     alpha: f32,
 }
 
 impl LowPassFilter {
+    /// Constructs a low-pass filter with a cutoff of `frequency` Hz at `sample_rate`.
     pub fn new(frequency: u64, sample_rate: f32) -> Self {
         LowPassFilter {
-            prev_input_sample: 0.0,
             prev_output_sample: 0.0,
-            alpha: get_alpha(frequency, sample_rate),
+            // The smoothing factor of a low-pass filter is the complement of the one of a
+            // high-pass filter.
+            alpha: 1.0 - get_alpha(frequency, sample_rate),
         }
     }
 }
@@ -31,12 +68,20 @@ impl FirstOrderFilter for LowPassFilter {
     fn process(&mut self, input_sample: f32) -> f32 {
         let output_sample =
             self.prev_output_sample + self.alpha * (input_sample - self.prev_output_sample);
-        self.prev_input_sample = input_sample;
         self.prev_output_sample = output_sample;
         output_sample
     }
+
+    fn state(&self) -> Vec<f32> {
+        vec![self.prev_output_sample]
+    }
+
+    fn load_state(&mut self, state: &[f32]) {
+        self.prev_output_sample = state[0];
+    }
 }
 
+/// A first order high-pass filter.
 // https://en.wikipedia.org/wiki/High-pass_filter
 pub struct HighPassFilter {
     prev_input_sample: f32,
@@ -45,6 +90,7 @@ pub struct HighPassFilter {
 }
 
 impl HighPassFilter {
+    /// Constructs a high-pass filter with a cutoff of `frequency` Hz at `sample_rate`.
     pub fn new(frequency: u64, sample_rate: f32) -> Self {
         HighPassFilter {
             prev_input_sample: 0.0,
@@ -62,4 +108,101 @@ impl FirstOrderFilter for HighPassFilter {
         self.prev_output_sample = output_sample;
         output_sample
     }
+
+    fn state(&self) -> Vec<f32> {
+        vec![self.prev_input_sample, self.prev_output_sample]
+    }
+
+    fn load_state(&mut self, state: &[f32]) {
+        self.prev_input_sample = state[0];
+        self.prev_output_sample = state[1];
+    }
+}
+
+// Builds the filters of `profile` for `sample_rate`.
+pub fn build_filters(
+    profile: FilterProfile,
+    factory: Option<&FilterFactory>,
+    sample_rate: f32,
+) -> Vec<Box<dyn FirstOrderFilter>> {
+    match profile {
+        FilterProfile::Nes => vec![
+            Box::new(HighPassFilter::new(90, sample_rate)),
+            Box::new(HighPassFilter::new(440, sample_rate)),
+            Box::new(LowPassFilter::new(14_000, sample_rate)),
+        ],
+        FilterProfile::Famicom => vec![
+            Box::new(HighPassFilter::new(37, sample_rate)),
+            Box::new(LowPassFilter::new(14_000, sample_rate)),
+        ],
+        FilterProfile::Clean => vec![Box::new(HighPassFilter::new(10, sample_rate))],
+        FilterProfile::Custom => factory.map_or_else(Vec::new, |factory| factory(sample_rate)),
+    }
+}
+
+// The filters of the audio buffer. The chain is built lazily since it depends on the sample
+// rate. Only the state of the filters is stored in save states, which is restored when the chain
+// is built with the same filters.
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Option<Vec<Box<dyn FirstOrderFilter>>>,
+    pending_states: Vec<Vec<f32>>,
+}
+
+impl FilterChain {
+    pub fn is_built(&self) -> bool {
+        self.filters.is_some()
+    }
+
+    pub fn build(&mut self, mut filters: Vec<Box<dyn FirstOrderFilter>>) {
+        let states_match = self.pending_states.len() == filters.len()
+            && filters
+                .iter()
+                .zip(self.pending_states.iter())
+                .all(|(filter, state)| filter.state().len() == state.len());
+        if states_match {
+            for (filter, state) in filters.iter_mut().zip(self.pending_states.iter()) {
+                filter.load_state(state);
+            }
+        }
+        self.pending_states.clear();
+        self.filters = Some(filters);
+    }
+
+    // Drops the filters so that they are built again with the same state.
+    pub fn rebuild(&mut self) {
+        self.pending_states = self.states();
+        self.filters = None;
+    }
+
+    fn states(&self) -> Vec<Vec<f32>> {
+        match &self.filters {
+            Some(filters) => filters.iter().map(|filter| filter.state()).collect(),
+            None => self.pending_states.clone(),
+        }
+    }
+
+    pub fn process(&mut self, mut sample: f32) -> f32 {
+        for filter in self.filters.iter_mut().flatten() {
+            sample = filter.process(sample);
+        }
+        sample
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Serialize for FilterChain {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.states().serialize(serializer)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'de> Deserialize<'de> for FilterChain {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(FilterChain {
+            filters: None,
+            pending_states: Vec::deserialize(deserializer)?,
+        })
+    }
 }
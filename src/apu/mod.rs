@@ -1,8 +1,18 @@
+mod blip;
+mod channel;
 mod filter;
 mod mixer;
-
-use self::filter::{FirstOrderFilter, HighPassFilter, LowPassFilter};
+mod recorder;
+mod ring_buffer;
+
+use self::blip::BlipBuffer;
+pub use self::channel::Channel;
+use self::channel::{ChannelOutput, ChannelSettings, CHANNEL_COUNT};
+use self::filter::{build_filters, FilterChain, FilterFactory};
+pub use self::filter::{FilterProfile, FirstOrderFilter, HighPassFilter, LowPassFilter};
 use self::mixer::Mixer;
+pub use self::recorder::{AudioFormat, AudioRecorder, AudioRecording};
+pub use self::ring_buffer::{AudioRingBuffer, RateControl};
 use crate::bus::Bus;
 use crate::cpu::Interrupt;
 #[cfg(not(target_arch = "wasm32"))]
@@ -45,23 +55,55 @@ const DMC_PERIOD_TABLE: [u16; 16] = [
     190, 160, 142, 128, 106,  84,  72,  54,
 ];
 
-const FOUR_STEP_FRAME_COUNTER_CYCLES: [u16; 4] = [7456, 7458, 7458, 7458];
-const FIVE_STEP_FRAME_COUNTER_CYCLES: [u16; 5] = [7458, 7456, 7458, 7458, 7452];
+// https://wiki.nesdev.com/w/index.php/APU_Frame_Counter
+//
+// The CPU cycles after the frame counter is reset on which each step of the sequencer happens.
+// The IRQ flag of the four-step sequence is set on the last three steps and the sequence restarts
+// after the last step.
+const FRAME_COUNTER_STEP_CYCLES: [[u16; 6]; 2] = [
+    [7457, 14913, 22371, 29828, 29829, 29830],
+    [7457, 14913, 22371, 29829, 37281, 37282],
+];
+const FRAME_COUNTER_STEP_CLOCKS: [FrameCounterClock; 6] = [
+    FrameCounterClock::Quarter,
+    FrameCounterClock::Half,
+    FrameCounterClock::Quarter,
+    FrameCounterClock::None,
+    FrameCounterClock::Half,
+    FrameCounterClock::None,
+];
 const CLOCK_FREQ: u64 = 1_789_773;
 const FRAMES_PER_SEC: u64 = 60;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Deserialize, Serialize))]
 pub enum FrameCounterMode {
     FourStep,
     FiveStep,
 }
 
+// The units that are clocked by a step of the frame counter. Half frames also clock the units of
+// quarter frames.
+#[derive(Clone, Copy, PartialEq)]
+enum FrameCounterClock {
+    None,
+    Quarter,
+    Half,
+}
+
+// Writes to the length counter registers take effect at the end of the APU cycle so that they
+// race with the frame counter like on hardware. A reload on the same cycle that the length
+// counter is clocked is ignored unless the length counter is 0, and a change to the halt flag
+// happens after the clock.
+// https://wiki.nesdev.com/w/index.php/APU_Length_Counter
 #[derive(Default)]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Deserialize, Serialize))]
 pub struct LengthCounter {
     pub enabled: bool,
     pub val: u8,
+    next_enabled: bool,
+    reload_val: u8,
+    prev_val: u8,
 }
 
 impl LengthCounter {
@@ -72,7 +114,23 @@ impl LengthCounter {
     }
 
     pub fn reload(&mut self, index: usize) {
-        self.val = LENGTH_COUNTER_TABLE[index];
+        self.reload_val = LENGTH_COUNTER_TABLE[index];
+        self.prev_val = self.val;
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.next_enabled = enabled;
+    }
+
+    // Applies the writes of the current cycle after the frame counter has been clocked.
+    pub fn apply_writes(&mut self) {
+        if self.reload_val != 0 {
+            if self.val == self.prev_val {
+                self.val = self.reload_val;
+            }
+            self.reload_val = 0;
+        }
+        self.enabled = self.next_enabled;
     }
 }
 
@@ -194,12 +252,8 @@ impl Triangle {
     }
 
     pub fn output(&self) -> u8 {
-        // Mute ultrasonic frequencies.
-        if !self.enabled
-            || self.linear_counter == 0
-            || self.length_counter.val == 0
-            || self.timer_period < 2
-        {
+        // Ultrasonic frequencies are not muted since the band-limited synthesis filters them out.
+        if !self.enabled || self.linear_counter == 0 || self.length_counter.val == 0 {
             return 0;
         }
         TRIANGLE_TABLE[self.duty_val as usize]
@@ -317,29 +371,64 @@ pub struct Apu {
     pub buffer: Vec<f32>,
     pub cycle: u64,
     sample_freq: f32,
-    sample_cycles: f32,
     pulses: [Pulse; 2],
     triangle: Triangle,
     noise: Noise,
     dmc: Dmc,
+    filters: FilterChain,
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    pub filter_profile: FilterProfile,
     #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
-    filters: Option<[Box<dyn FirstOrderFilter>; 3]>,
+    pub filter_factory: Option<FilterFactory>,
+    blip: Option<BlipBuffer>,
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    pub channel_settings: [ChannelSettings; CHANNEL_COUNT],
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    pub channel_buffers_enabled: bool,
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    channel_outputs: Option<Vec<ChannelOutput>>,
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    pub stereo_enabled: bool,
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    stereo_outputs: Option<[ChannelOutput; 2]>,
+    // The left and right samples of each sample of the audio buffer.
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    pub stereo_buffer: Vec<f32>,
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    pub ring_buffer: Option<AudioRingBuffer>,
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    pub rate_control: Option<RateControl>,
+    // The factor that the sample rate is multiplied by to keep the ring buffer near its target
+    // latency.
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    pub rate_adjustment: f64,
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    pub recorder: Option<AudioRecorder>,
     #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
     mixer: Mixer,
     frame_counter_mode: FrameCounterMode,
-    frame_counter_val: u16,
-    frame_counter_phase: u8,
+    // The number of CPU cycles since the sequence of the frame counter started.
+    frame_counter_cycle: u16,
+    frame_counter_step: usize,
+    // A write to $4017 that has not taken effect yet and the number of cycles until it does.
+    frame_counter_write: Option<u8>,
+    frame_counter_write_delay: u8,
+    // The number of cycles during which the frame counter cannot clock the units again after
+    // clocking them.
+    frame_counter_clock_delay: u8,
     irq_enabled: bool,
     irq_pending: bool,
-    last_written_byte: u8,
     #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
     bus: Option<Bus>,
 }
 
 impl Apu {
     pub fn initialize_buffer(&mut self) {
-        let buffer_size = f32::ceil(self.sample_freq / FRAMES_PER_SEC as f32);
-        self.buffer.resize(buffer_size as usize, 0.0);
+        let sample_freq = f64::from(self.sample_freq) * self.rate_adjustment;
+        let buffer_size = f64::ceil(sample_freq / FRAMES_PER_SEC as f64) as usize;
+        // The sample rate can change during a frame when it is adjusted.
+        self.buffer
+            .resize(buffer_size.max(self.buffer_index + 1), 0.0);
     }
 
     pub fn new(sample_freq: f32) -> Self {
@@ -348,19 +437,33 @@ impl Apu {
             buffer: Vec::new(),
             cycle: 0,
             sample_freq,
-            sample_cycles: CLOCK_FREQ as f32 / sample_freq,
             pulses: [Pulse::default(), Pulse::default()],
             triangle: Triangle::default(),
             noise: Noise::default(),
             dmc: Dmc::default(),
-            filters: None,
+            filters: FilterChain::default(),
+            filter_profile: FilterProfile::Nes,
+            filter_factory: None,
+            blip: None,
+            channel_settings: [ChannelSettings::default(); CHANNEL_COUNT],
+            channel_buffers_enabled: false,
+            channel_outputs: None,
+            stereo_enabled: false,
+            stereo_outputs: None,
+            stereo_buffer: Vec::new(),
+            ring_buffer: None,
+            rate_control: None,
+            rate_adjustment: 1.0,
+            recorder: None,
             mixer: Mixer::new(),
             frame_counter_mode: FrameCounterMode::FourStep,
-            frame_counter_val: FOUR_STEP_FRAME_COUNTER_CYCLES[0],
-            frame_counter_phase: 0,
+            frame_counter_cycle: 0,
+            frame_counter_step: 0,
+            frame_counter_write: None,
+            frame_counter_write_delay: 0,
+            frame_counter_clock_delay: 0,
             irq_enabled: false,
             irq_pending: false,
-            last_written_byte: 0,
             bus: None,
         }
     }
@@ -380,7 +483,12 @@ impl Apu {
     pub fn reset(&mut self) {
         self.write_register(0x4015, 0);
         self.irq_pending = false;
-        self.write_register(0x4017, self.last_written_byte);
+        // The mode of the frame counter is unchanged, but the IRQ inhibit flag is cleared.
+        let mode = match self.frame_counter_mode {
+            FrameCounterMode::FourStep => 0x00,
+            FrameCounterMode::FiveStep => 0x80,
+        };
+        self.write_register(0x4017, mode);
         // Delay after reset.
         for _ in 0..12 {
             self.step();
@@ -438,7 +546,9 @@ impl Apu {
             0x4000 | 0x4004 => {
                 let index = ((addr - 0x4000) / 4) as usize;
                 self.pulses[index].duty_cycle = val >> 6;
-                self.pulses[index].length_counter.enabled = val & 0x20 == 0;
+                self.pulses[index]
+                    .length_counter
+                    .set_enabled(val & 0x20 == 0);
                 self.pulses[index].envelope.looped = val & 0x20 != 0;
                 self.pulses[index].envelope.enabled = val & 0x10 == 0;
                 self.pulses[index].envelope.period = val & 0x0F;
@@ -472,7 +582,7 @@ impl Apu {
             }
             // Triangle
             0x4008 => {
-                self.triangle.length_counter.enabled = val & 0x80 == 0;
+                self.triangle.length_counter.set_enabled(val & 0x80 == 0);
                 self.triangle.linear_counter_enabled = val & 0x80 == 0;
                 self.triangle.linear_counter_period = val & 0x7F;
             }
@@ -492,7 +602,7 @@ impl Apu {
             }
             // Noise
             0x400C => {
-                self.noise.length_counter.enabled = val & 0x20 == 0;
+                self.noise.length_counter.set_enabled(val & 0x20 == 0);
                 self.noise.envelope.looped = val & 0x20 != 0;
                 self.noise.envelope.enabled = val & 0x10 == 0;
                 self.noise.envelope.period = val & 0x0F;
@@ -549,22 +659,14 @@ impl Apu {
                 }
             }
             0x4017 => {
-                self.last_written_byte = val;
-                // Special timings for writing to 0x4017.
-                self.frame_counter_mode = if val >> 7 == 0 {
-                    // TODO: This is not exactly right.
-                    self.frame_counter_val = 7458;
-                    FrameCounterMode::FourStep
-                } else {
-                    // TODO: Handle jitter.
-                    self.frame_counter_val = 0;
-                    FrameCounterMode::FiveStep
-                };
-                self.irq_enabled = (val >> 6) & 0x01 == 0;
+                // The write takes effect 3 cycles after a write on an even cycle and 4 cycles
+                // after a write on an odd cycle.
+                self.frame_counter_write = Some(val);
+                self.frame_counter_write_delay = if self.cycle & 0x01 == 0 { 3 } else { 4 };
+                self.irq_enabled = val & 0x40 == 0;
                 if !self.irq_enabled {
                     self.irq_pending = false;
                 }
-                self.frame_counter_phase = 0;
             }
             _ => {}
         }
@@ -577,6 +679,66 @@ impl Apu {
         self.noise.envelope.step();
     }
 
+    fn step_frame_counter(&mut self) {
+        self.frame_counter_cycle += 1;
+        let mode = self.frame_counter_mode as usize;
+        if self.frame_counter_cycle == FRAME_COUNTER_STEP_CYCLES[mode][self.frame_counter_step] {
+            if self.frame_counter_mode == FrameCounterMode::FourStep
+                && self.irq_enabled
+                && self.frame_counter_step >= 3
+            {
+                self.irq_pending = true;
+                let cpu = self.bus_mut().cpu_mut();
+                cpu.trigger_interrupt(Interrupt::IRQ);
+            }
+            let clock = FRAME_COUNTER_STEP_CLOCKS[self.frame_counter_step];
+            if clock != FrameCounterClock::None && self.frame_counter_clock_delay == 0 {
+                self.clock_frame_counter_units(clock);
+                // A write to $4017 cannot clock the units again on this cycle or the next one.
+                self.frame_counter_clock_delay = 2;
+            }
+            self.frame_counter_step += 1;
+            if self.frame_counter_step == FRAME_COUNTER_STEP_CYCLES[mode].len() {
+                self.frame_counter_step = 0;
+                self.frame_counter_cycle = 0;
+            }
+        }
+
+        if let Some(val) = self.frame_counter_write {
+            self.frame_counter_write_delay -= 1;
+            if self.frame_counter_write_delay == 0 {
+                self.frame_counter_write = None;
+                self.frame_counter_mode = if val & 0x80 == 0 {
+                    FrameCounterMode::FourStep
+                } else {
+                    FrameCounterMode::FiveStep
+                };
+                self.frame_counter_step = 0;
+                self.frame_counter_cycle = 0;
+                // Switching to the five-step sequence clocks the units immediately.
+                if self.frame_counter_mode == FrameCounterMode::FiveStep
+                    && self.frame_counter_clock_delay == 0
+                {
+                    self.clock_frame_counter_units(FrameCounterClock::Half);
+                    self.frame_counter_clock_delay = 2;
+                }
+            }
+        }
+
+        if self.frame_counter_clock_delay > 0 {
+            self.frame_counter_clock_delay -= 1;
+        }
+    }
+
+    fn clock_frame_counter_units(&mut self, clock: FrameCounterClock) {
+        self.step_envelope();
+        self.triangle.step_linear_counter();
+        if clock == FrameCounterClock::Half {
+            self.step_length_counter();
+            self.step_sweep();
+        }
+    }
+
     fn step_length_counter(&mut self) {
         for pulse in &mut self.pulses {
             pulse.length_counter.step();
@@ -624,7 +786,7 @@ impl Apu {
         let val = {
             let cpu = self.bus_mut().cpu_mut();
             cpu.stall_cycle += 4;
-            cpu.read_byte(addr)
+            cpu.memory.read_pcm_byte(addr)
         };
         self.dmc.shift_register = val;
         let (next_addr, overflow) = self.dmc.curr_addr.overflowing_add(1);
@@ -641,27 +803,41 @@ impl Apu {
         }
     }
 
-    fn process_sample(&mut self, mut sample: f32) -> f32 {
-        let sample_freq = self.sample_freq;
-        let filters = self.filters.get_or_insert_with(|| {
-            [
-                Box::new(HighPassFilter::new(90, sample_freq)),
-                Box::new(HighPassFilter::new(440, sample_freq)),
-                Box::new(LowPassFilter::new(14_000, sample_freq)),
-            ]
-        });
+    fn build_filters(&self) -> Vec<Box<dyn FirstOrderFilter>> {
+        build_filters(
+            self.filter_profile,
+            self.filter_factory.as_ref(),
+            self.sample_freq,
+        )
+    }
 
-        for filter in filters {
-            sample = filter.process(sample);
+    fn process_sample(&mut self, sample: f32) -> f32 {
+        if !self.filters.is_built() {
+            let filters = self.build_filters();
+            self.filters.build(filters);
         }
+        self.filters.process(sample)
+    }
 
-        sample
+    // Builds the filters again with the current filter profile and sample rate. The filters of
+    // the audio buffer keep their state if they are the same kind of filters.
+    pub fn rebuild_filters(&mut self) {
+        self.filters.rebuild();
+        let sample_freq = self.sample_freq;
+        let profile = self.filter_profile;
+        let factory = self.filter_factory.as_ref();
+        for output in self
+            .channel_outputs
+            .iter_mut()
+            .flatten()
+            .chain(self.stereo_outputs.iter_mut().flatten())
+        {
+            output.filters = build_filters(profile, factory, sample_freq);
+        }
     }
 
     pub fn step(&mut self) {
         self.cycle += 1;
-        let curr_cycle = self.cycle as f32;
-        let next_cycle = (self.cycle + 1) as f32;
 
         self.triangle.step();
         if self.dmc.enabled {
@@ -674,97 +850,210 @@ impl Apu {
             self.noise.step();
         }
 
-        if self.frame_counter_val > 0 {
-            self.frame_counter_val -= 1;
-        } else {
-            match self.frame_counter_mode {
-                FrameCounterMode::FourStep => {
-                    let index = self.frame_counter_phase as usize;
-                    self.frame_counter_val = FOUR_STEP_FRAME_COUNTER_CYCLES[index] - 1;
-                    match self.frame_counter_phase {
-                        0 | 2 => {
-                            // envelope
-                            self.step_envelope();
-                            self.triangle.step_linear_counter();
-                            self.frame_counter_phase += 1;
-                        }
-                        1 => {
-                            // envelope
-                            self.step_envelope();
-                            self.triangle.step_linear_counter();
-                            // length counter and sweep
-                            self.step_length_counter();
-                            self.step_sweep();
-                            self.frame_counter_phase += 1;
-                        }
-                        3 => {
-                            // envelope
-                            self.step_envelope();
-                            self.triangle.step_linear_counter();
-                            // length counter and sweep
-                            self.step_length_counter();
-                            self.step_sweep();
-                            // irq
-                            if self.irq_enabled {
-                                self.irq_pending = true;
-                                let cpu = self.bus_mut().cpu_mut();
-                                cpu.trigger_interrupt(Interrupt::IRQ);
-                            }
-                            self.frame_counter_phase = 0;
-                        }
-                        _ => panic!("[APU] Invalid frame counter phase."),
-                    }
-                }
-                FrameCounterMode::FiveStep => {
-                    let index = self.frame_counter_phase as usize;
-                    self.frame_counter_val = FIVE_STEP_FRAME_COUNTER_CYCLES[index] - 1;
-                    match self.frame_counter_phase {
-                        0 | 2 => {
-                            // envelope
-                            self.step_envelope();
-                            self.triangle.step_linear_counter();
-                            // length counter
-                            self.step_length_counter();
-                            self.step_sweep();
-                            self.frame_counter_phase += 1;
-                        }
-                        1 => {
-                            // envelope
-                            self.step_envelope();
-                            self.triangle.step_linear_counter();
-                            self.frame_counter_phase += 1;
-                        }
-                        3 => {
-                            // envelope
-                            self.step_envelope();
-                            self.triangle.step_linear_counter();
-                            self.frame_counter_phase = 0;
-                        }
-                        _ => panic!("[APU] Invalid frame counter phase."),
-                    }
-                }
+        self.step_frame_counter();
+        for pulse in &mut self.pulses {
+            pulse.length_counter.apply_writes();
+        }
+        self.triangle.length_counter.apply_writes();
+        self.noise.length_counter.apply_writes();
+
+        // The amplitude is fed to the band-limited synthesis on every cycle so that changes that
+        // happen between output samples are not lost or aliased.
+        let outputs = [
+            self.pulses[0].output(),
+            self.pulses[1].output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        ];
+        let mut levels = [0.0; CHANNEL_COUNT];
+        for (level, (output, settings)) in levels
+            .iter_mut()
+            .zip(outputs.iter().zip(self.channel_settings.iter()))
+        {
+            if settings.enabled {
+                *level = f32::from(*output) * settings.volume;
             }
         }
-
-        let curr_sample = f32::floor(curr_cycle / self.sample_cycles) as u64;
-        let next_sample = f32::floor(next_cycle as f32 / self.sample_cycles) as u64;
-        if curr_sample != next_sample {
-            let sample = self.process_sample(self.mixer.sample(
-                self.pulses[0].output(),
-                self.pulses[1].output(),
-                self.triangle.output(),
-                self.noise.output(),
-                self.dmc.output(),
+        let amplitude = self.mix(levels);
+        if self.blip.is_none() {
+            self.blip = Some(BlipBuffer::new(
+                CLOCK_FREQ as f64,
+                f64::from(self.sample_freq) * self.rate_adjustment,
             ));
-
+        }
+        self.step_channel_outputs(outputs);
+        self.step_stereo_outputs(outputs);
+        let sample = self.blip.as_mut().unwrap().step(amplitude);
+        if let Some(sample) = sample {
+            let sample = self.process_sample(sample);
             self.initialize_buffer();
             self.buffer[self.buffer_index] = sample;
             self.buffer_index += 1;
+            self.write_ring_buffer();
+            self.record_sample();
+        }
+    }
+
+    // Writes the last sample to the ring buffer and adjusts the sample rate to keep the ring
+    // buffer near its target latency. The left and right samples are written instead when stereo
+    // output is enabled.
+    fn write_ring_buffer(&mut self) {
+        let ring_buffer = match &self.ring_buffer {
+            Some(ring_buffer) => ring_buffer,
+            None => return,
+        };
+        let index = self.buffer_index - 1;
+        if self.stereo_enabled {
+            ring_buffer.push(self.stereo_buffer[index * 2]);
+            ring_buffer.push(self.stereo_buffer[index * 2 + 1]);
+        } else {
+            ring_buffer.push(self.buffer[index]);
+        }
+    }
+
+    // Sets the rate adjustment from the number of samples in the ring buffer. It is called once
+    // per frame rather than after every sample since audio callbacks read the ring buffer in large
+    // chunks, and following each jump in its length makes the pitch wobble. The adjustment stays
+    // at 1.0 while audio is recorded so that the recording is at its sample rate.
+    pub fn update_rate_adjustment(&mut self) {
+        if self.recorder.is_some() {
+            return;
+        }
+        let (rate_control, ring_buffer) = match (self.rate_control, &self.ring_buffer) {
+            (Some(rate_control), Some(ring_buffer)) => (rate_control, ring_buffer),
+            _ => return,
+        };
+        let channel_count = if self.stereo_enabled { 2 } else { 1 };
+        let adjustment = rate_control.adjustment(ring_buffer.len() / channel_count);
+        self.set_rate_adjustment(adjustment);
+    }
+
+    // Writes the last sample of the audio buffer and of each channel buffer to the recorder.
+    fn record_sample(&mut self) {
+        let recorder = match &mut self.recorder {
+            Some(recorder) => recorder,
+            None => return,
+        };
+        let index = self.buffer_index - 1;
+        recorder.write(self.buffer[index]);
+        for (channel, channel_output) in self.channel_outputs.iter().flatten().enumerate() {
+            recorder.write_channel(channel, channel_output.buffer[index]);
+        }
+    }
+
+    pub fn set_rate_adjustment(&mut self, rate_adjustment: f64) {
+        self.rate_adjustment = rate_adjustment;
+        let sample_freq = f64::from(self.sample_freq) * rate_adjustment;
+        let channel_outputs = self.channel_outputs.iter_mut().flatten();
+        let stereo_outputs = self.stereo_outputs.iter_mut().flatten();
+        let blips = channel_outputs
+            .chain(stereo_outputs)
+            .map(|output| &mut output.blip)
+            .chain(self.blip.iter_mut());
+        for blip in blips {
+            blip.set_rate(CLOCK_FREQ as f64, sample_freq);
+        }
+    }
+
+    fn mix(&self, levels: [f32; CHANNEL_COUNT]) -> f32 {
+        self.mixer
+            .sample(levels[0], levels[1], levels[2], levels[3], levels[4])
+    }
+
+    // Returns an output whose synthesis is a copy of the synthesis of the audio buffer so that
+    // their samples are produced on the same cycles.
+    fn new_output(&self) -> ChannelOutput {
+        let mut blip = self.blip.as_ref().unwrap().clone();
+        blip.clear();
+        ChannelOutput {
+            blip,
+            filters: self.build_filters(),
+            buffer: Vec::new(),
+        }
+    }
+
+    // Mixes each channel without the other channels and regardless of the channel settings. The
+    // samples are written at the same index as the samples of the audio buffer. The channels are
+    // also mixed during multi-track recording.
+    fn step_channel_outputs(&mut self, outputs: [u8; CHANNEL_COUNT]) {
+        let multi_track = self
+            .recorder
+            .as_ref()
+            .is_some_and(AudioRecorder::is_multi_track);
+        if !self.channel_buffers_enabled && !multi_track {
+            self.channel_outputs = None;
+            return;
+        }
+        if self.channel_outputs.is_none() {
+            let channel_outputs = (0..CHANNEL_COUNT).map(|_| self.new_output()).collect();
+            self.channel_outputs = Some(channel_outputs);
+        }
+
+        let buffer_len = self.buffer.len().max(self.buffer_index + 1);
+        let buffer_index = self.buffer_index;
+        let mut amplitudes = [0.0; CHANNEL_COUNT];
+        for (index, amplitude) in amplitudes.iter_mut().enumerate() {
+            let mut levels = [0.0; CHANNEL_COUNT];
+            levels[index] = f32::from(outputs[index]);
+            *amplitude = self.mix(levels);
+        }
+        let channel_outputs = self.channel_outputs.as_mut().unwrap();
+        for (channel_output, amplitude) in channel_outputs.iter_mut().zip(amplitudes.iter()) {
+            if let Some(sample) = channel_output.process(*amplitude) {
+                channel_output.buffer.resize(buffer_len, 0.0);
+                channel_output.buffer[buffer_index] = sample;
+            }
+        }
+    }
+
+    // Mixes each side of the stereo output with the pan of each channel. The samples of both
+    // sides are produced on the same cycles and are interleaved in the stereo buffer.
+    fn step_stereo_outputs(&mut self, outputs: [u8; CHANNEL_COUNT]) {
+        if !self.stereo_enabled {
+            self.stereo_outputs = None;
+            return;
+        }
+        if self.stereo_outputs.is_none() {
+            self.stereo_outputs = Some([self.new_output(), self.new_output()]);
+        }
+
+        let mut left_levels = [0.0; CHANNEL_COUNT];
+        let mut right_levels = [0.0; CHANNEL_COUNT];
+        for (index, settings) in self.channel_settings.iter().enumerate() {
+            let (left, right) = settings.stereo_levels(outputs[index]);
+            left_levels[index] = left;
+            right_levels[index] = right;
+        }
+        let amplitudes = [self.mix(left_levels), self.mix(right_levels)];
+
+        let buffer_len = self.buffer.len().max(self.buffer_index + 1) * 2;
+        let buffer_index = self.buffer_index;
+        let stereo_outputs = self.stereo_outputs.as_mut().unwrap();
+        for (side, (stereo_output, amplitude)) in
+            stereo_outputs.iter_mut().zip(amplitudes.iter()).enumerate()
+        {
+            if let Some(sample) = stereo_output.process(*amplitude) {
+                self.stereo_buffer.resize(buffer_len, 0.0);
+                self.stereo_buffer[buffer_index * 2 + side] = sample;
+            }
         }
     }
 
+    pub fn channel_buffer(&self, channel: Channel) -> Option<&[f32]> {
+        self.channel_outputs
+            .as_ref()
+            .map(|channel_outputs| channel_outputs[channel.index()].buffer.as_slice())
+    }
+
+    pub fn sample_freq(&self) -> f32 {
+        self.sample_freq
+    }
+
     pub fn set_sample_freq(&mut self, sample_freq: f32) {
         self.sample_freq = sample_freq;
-        self.sample_cycles = CLOCK_FREQ as f32 / self.sample_freq as f32;
+        // The synthesis and the filters keep their state when the sample rate changes.
+        self.set_rate_adjustment(1.0);
+        self.rebuild_filters();
     }
 }
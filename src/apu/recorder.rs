@@ -0,0 +1,167 @@
+// Records the samples of the audio buffer and optionally of each channel to WAV or raw PCM data.
+// http://soundfile.sapp.org/doc/WaveFormat/
+use super::channel::{Channel, CHANNEL_COUNT};
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+/// The format of an audio recording. The samples are mono and little-endian.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub enum AudioFormat {
+    /// A WAV file with 16-bit signed integer samples.
+    Wav16,
+    /// A WAV file with 32-bit floating point samples.
+    WavFloat,
+    /// 16-bit signed integer samples without a header.
+    RawPcm16,
+    /// 32-bit floating point samples without a header.
+    RawFloat,
+}
+
+impl AudioFormat {
+    fn bytes_per_sample(self) -> usize {
+        match self {
+            AudioFormat::Wav16 | AudioFormat::RawPcm16 => 2,
+            AudioFormat::WavFloat | AudioFormat::RawFloat => 4,
+        }
+    }
+}
+
+/// The audio recorded between `Nes::start_audio_recording` and `Nes::stop_audio_recording`.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub struct AudioRecording {
+    data: Vec<u8>,
+    channel_data: Option<Vec<Vec<u8>>>,
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+impl AudioRecording {
+    /// Returns the recording of the audio buffer in the format of the recording.
+    pub fn data(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    /// Returns the recording of `channel` mixed on its own, or `None` if the recording was not
+    /// started with multi-track recording.
+    pub fn channel_data(&self, channel: Channel) -> Option<Vec<u8>> {
+        self.channel_data
+            .as_ref()
+            .map(|channel_data| channel_data[channel.index()].clone())
+    }
+}
+
+// The encoded samples of a track.
+struct Track {
+    format: AudioFormat,
+    samples: Vec<u8>,
+}
+
+impl Track {
+    fn new(format: AudioFormat) -> Self {
+        Track {
+            format,
+            samples: Vec::new(),
+        }
+    }
+
+    fn write(&mut self, sample: f32) {
+        match self.format {
+            AudioFormat::Wav16 | AudioFormat::RawPcm16 => {
+                let sample = (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)).round() as i16;
+                self.samples.extend_from_slice(&sample.to_le_bytes());
+            }
+            AudioFormat::WavFloat | AudioFormat::RawFloat => {
+                self.samples.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+    }
+
+    fn finish(self, sample_rate: u32) -> Vec<u8> {
+        let (format_tag, fmt_len) = match self.format {
+            AudioFormat::Wav16 => (WAVE_FORMAT_PCM, 16),
+            // Formats other than PCM have an extension size and a fact chunk.
+            AudioFormat::WavFloat => (WAVE_FORMAT_IEEE_FLOAT, 18),
+            AudioFormat::RawPcm16 | AudioFormat::RawFloat => return self.samples,
+        };
+        let bytes_per_sample = self.format.bytes_per_sample() as u32;
+        let data_len = self.samples.len() as u32;
+        let fact_len = if format_tag == WAVE_FORMAT_PCM { 0 } else { 12 };
+        let riff_len = 4 + (8 + fmt_len) + fact_len + (8 + data_len);
+
+        let mut data = Vec::with_capacity(8 + riff_len as usize);
+        data.extend_from_slice(b"RIFF");
+        data.extend_from_slice(&riff_len.to_le_bytes());
+        data.extend_from_slice(b"WAVE");
+        data.extend_from_slice(b"fmt ");
+        data.extend_from_slice(&fmt_len.to_le_bytes());
+        data.extend_from_slice(&format_tag.to_le_bytes());
+        // The number of channels.
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&sample_rate.to_le_bytes());
+        data.extend_from_slice(&(sample_rate * bytes_per_sample).to_le_bytes());
+        // The number of bytes and bits of each sample.
+        data.extend_from_slice(&(bytes_per_sample as u16).to_le_bytes());
+        data.extend_from_slice(&(bytes_per_sample as u16 * 8).to_le_bytes());
+        if format_tag != WAVE_FORMAT_PCM {
+            data.extend_from_slice(&0u16.to_le_bytes());
+            data.extend_from_slice(b"fact");
+            data.extend_from_slice(&4u32.to_le_bytes());
+            data.extend_from_slice(&(data_len / bytes_per_sample).to_le_bytes());
+        }
+        data.extend_from_slice(b"data");
+        data.extend_from_slice(&data_len.to_le_bytes());
+        data.extend_from_slice(&self.samples);
+        data
+    }
+}
+
+// Records the audio buffer and, in multi-track recording, each channel mixed on its own.
+pub struct AudioRecorder {
+    sample_rate: u32,
+    track: Track,
+    channel_tracks: Option<Vec<Track>>,
+}
+
+impl AudioRecorder {
+    pub fn new(format: AudioFormat, sample_rate: f32, multi_track: bool) -> Self {
+        AudioRecorder {
+            sample_rate: sample_rate.round() as u32,
+            track: Track::new(format),
+            channel_tracks: if multi_track {
+                Some((0..CHANNEL_COUNT).map(|_| Track::new(format)).collect())
+            } else {
+                None
+            },
+        }
+    }
+
+    pub fn is_multi_track(&self) -> bool {
+        self.channel_tracks.is_some()
+    }
+
+    pub fn write(&mut self, sample: f32) {
+        self.track.write(sample);
+    }
+
+    pub fn write_channel(&mut self, channel: usize, sample: f32) {
+        if let Some(channel_tracks) = &mut self.channel_tracks {
+            channel_tracks[channel].write(sample);
+        }
+    }
+
+    pub fn finish(self) -> AudioRecording {
+        let sample_rate = self.sample_rate;
+        AudioRecording {
+            data: self.track.finish(sample_rate),
+            channel_data: self.channel_tracks.map(|channel_tracks| {
+                channel_tracks
+                    .into_iter()
+                    .map(|track| track.finish(sample_rate))
+                    .collect()
+            }),
+        }
+    }
+}
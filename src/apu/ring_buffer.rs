@@ -0,0 +1,132 @@
+// A single producer, single consumer queue of audio samples that an audio callback on another
+// thread reads from without locking.
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+struct Inner {
+    // The bits of each sample.
+    samples: Box<[AtomicU32]>,
+    // The number of samples that have been read and written. They only increase and the index of
+    // a sample in `samples` is its count modulo the capacity.
+    read_count: AtomicUsize,
+    write_count: AtomicUsize,
+}
+
+/// A lock-free ring buffer of audio samples. The emulator writes samples to the ring buffer as
+/// they are produced and an audio callback reads them, possibly on another thread. Clones of a
+/// ring buffer share the same samples.
+///
+/// There must be at most one reader at a time.
+#[derive(Clone)]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub struct AudioRingBuffer {
+    inner: Arc<Inner>,
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+impl AudioRingBuffer {
+    /// Constructs a ring buffer that holds up to `capacity` samples.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is `0`.
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(constructor))]
+    pub fn new(capacity: usize) -> Self {
+        if capacity == 0 {
+            panic!("[APU] Invalid ring buffer capacity {}.", capacity);
+        }
+        AudioRingBuffer {
+            inner: Arc::new(Inner {
+                samples: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+                read_count: AtomicUsize::new(0),
+                write_count: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Returns the maximum number of samples in the ring buffer.
+    pub fn capacity(&self) -> usize {
+        self.inner.samples.len()
+    }
+
+    /// Returns the number of samples that are waiting to be read.
+    pub fn len(&self) -> usize {
+        let read_count = self.inner.read_count.load(Ordering::Acquire);
+        let write_count = self.inner.write_count.load(Ordering::Acquire);
+        write_count.wrapping_sub(read_count)
+    }
+
+    /// Returns `true` if there are no samples waiting to be read.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes and returns the oldest sample, or `None` if the ring buffer is empty.
+    pub fn pop(&self) -> Option<f32> {
+        let read_count = self.inner.read_count.load(Ordering::Relaxed);
+        let write_count = self.inner.write_count.load(Ordering::Acquire);
+        if read_count == write_count {
+            return None;
+        }
+        let index = read_count % self.capacity();
+        let sample = f32::from_bits(self.inner.samples[index].load(Ordering::Relaxed));
+        self.inner
+            .read_count
+            .store(read_count.wrapping_add(1), Ordering::Release);
+        Some(sample)
+    }
+
+    /// Removes the oldest samples into `out` and returns the number of samples that were read,
+    /// which is less than the length of `out` if the ring buffer runs out of samples.
+    pub fn read(&self, out: &mut [f32]) -> usize {
+        let mut count = 0;
+        for val in out.iter_mut() {
+            match self.pop() {
+                Some(sample) => *val = sample,
+                None => break,
+            }
+            count += 1;
+        }
+        count
+    }
+}
+
+impl AudioRingBuffer {
+    // Adds a sample and returns `true`, or returns `false` and drops the sample if the ring buffer
+    // is full.
+    pub(crate) fn push(&self, sample: f32) -> bool {
+        let read_count = self.inner.read_count.load(Ordering::Acquire);
+        let write_count = self.inner.write_count.load(Ordering::Relaxed);
+        if write_count.wrapping_sub(read_count) == self.capacity() {
+            return false;
+        }
+        let index = write_count % self.capacity();
+        self.inner.samples[index].store(sample.to_bits(), Ordering::Relaxed);
+        self.inner
+            .write_count
+            .store(write_count.wrapping_add(1), Ordering::Release);
+        true
+    }
+}
+
+// Adjusts the sample rate to keep the number of samples in a ring buffer near a target latency.
+// The sample rate is raised when the ring buffer has fewer samples than the target and lowered
+// when it has more, in proportion to the difference up to `max_adjustment`.
+// https://docs.libretro.com/guides/ratecontrol.pdf
+#[derive(Clone, Copy)]
+pub struct RateControl {
+    pub target_latency: usize,
+    pub max_adjustment: f64,
+}
+
+impl RateControl {
+    // Returns the factor that the sample rate is multiplied by when the ring buffer has `latency`
+    // samples of each side.
+    pub fn adjustment(&self, latency: usize) -> f64 {
+        let target_latency = self.target_latency as f64;
+        let error = (target_latency - latency as f64) / target_latency;
+        1.0 + self.max_adjustment * error.clamp(-1.0, 1.0)
+    }
+}
@@ -1,4 +1,5 @@
 use crate::apu::Apu;
+use crate::cdl::CodeDataLogger;
 use crate::cpu::Cpu;
 use crate::mapper::Mapper;
 use crate::ppu::Ppu;
@@ -9,15 +10,23 @@ pub struct Bus {
     pub cpu: *mut Cpu,
     pub ppu: *mut Ppu,
     pub mapper: *mut dyn Mapper,
+    pub cdl: *mut CodeDataLogger,
 }
 
 impl Bus {
-    pub fn new(apu: &mut Apu, cpu: &mut Cpu, ppu: &mut Ppu, mapper: *mut dyn Mapper) -> Self {
+    pub fn new(
+        apu: &mut Apu,
+        cpu: &mut Cpu,
+        ppu: &mut Ppu,
+        mapper: *mut dyn Mapper,
+        cdl: &mut CodeDataLogger,
+    ) -> Self {
         Bus {
             apu: apu as *mut Apu,
             cpu: cpu as *mut Cpu,
             ppu: ppu as *mut Ppu,
             mapper,
+            cdl: cdl as *mut CodeDataLogger,
         }
     }
 
@@ -44,4 +53,8 @@ impl Bus {
     pub fn mapper_mut(&mut self) -> &mut dyn Mapper {
         unsafe { &mut (*self.mapper) }
     }
+
+    pub fn cdl_mut(&mut self) -> &mut CodeDataLogger {
+        unsafe { &mut (*self.cdl) }
+    }
 }
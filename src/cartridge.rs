@@ -4,6 +4,8 @@ use crate::ppu::MirroringMode;
 use serde_derive::{Deserialize, Serialize};
 
 const CARTRIDGE_HEADER: u32 = 0x1A53_454E;
+// Mappers with boards that may have PRG RAM when the header does not specify its size.
+const PRG_RAM_MAPPERS: [u8; 3] = [0, 1, 4];
 
 #[cfg_attr(not(target_arch = "wasm32"), derive(Deserialize, Serialize))]
 pub struct Cartridge {
@@ -50,12 +52,6 @@ impl Cartridge {
         let chr_rom_len = buffer[5] as usize * 0x2000;
         info!("[CARTRIDGE] CHR ROM length: {} bytes.", chr_rom_len);
         let mut prg_ram_len = buffer[8] as usize * 0x2000;
-        info!("[CARTRIDGE] PRG RAM length: {} bytes.", prg_ram_len);
-
-        if prg_ram_len == 0 {
-            prg_ram_len = 0x4000;
-        }
-
         let flags_6 = buffer[6];
         let flags_7 = if is_zero { buffer[7] } else { 0 };
 
@@ -84,6 +80,11 @@ impl Cartridge {
         let mapper = (flags_7 & 0xF0) | (flags_6 >> 4);
         info!("[CARTRIDGE] Mapper: {}.", mapper);
 
+        if prg_ram_len == 0 && PRG_RAM_MAPPERS.contains(&mapper) {
+            prg_ram_len = 0x4000;
+        }
+        info!("[CARTRIDGE] PRG RAM length: {} bytes.", prg_ram_len);
+
         let mirroring_mode = {
             if flags_6 & 0x08 != 0 {
                 MirroringMode::None
@@ -134,6 +135,10 @@ impl Cartridge {
         self.prg_ram.len()
     }
 
+    pub fn has_prg_ram(&self) -> bool {
+        !self.prg_ram.is_empty()
+    }
+
     pub fn read_prg_ram(&self, addr: usize) -> u8 {
         self.prg_ram[addr]
     }
@@ -0,0 +1,72 @@
+// http://www.fceux.com/web/help/fceux.html?CodeDataLogger.html
+pub const PRG_CODE: u8 = 0x01;
+pub const PRG_DATA: u8 = 0x02;
+pub const PRG_PCM_DATA: u8 = 0x40;
+pub const CHR_RENDERED: u8 = 0x01;
+pub const CHR_READ: u8 = 0x02;
+
+// Logs how each byte of PRG ROM and CHR ROM is accessed. Each byte of the log is a set of flags
+// in the same format as FCEUX's code/data logger.
+pub struct CodeDataLogger {
+    pub enabled: bool,
+    prg_rom_log: Vec<u8>,
+    chr_rom_log: Vec<u8>,
+}
+
+impl CodeDataLogger {
+    pub fn new(prg_rom_len: usize, chr_rom_len: usize) -> Self {
+        CodeDataLogger {
+            enabled: false,
+            prg_rom_log: vec![0; prg_rom_len],
+            chr_rom_log: vec![0; chr_rom_len],
+        }
+    }
+
+    pub fn log_prg_rom(&mut self, addr: usize, cpu_addr: u16, flags: u8) {
+        if !self.enabled || self.prg_rom_log.is_empty() {
+            return;
+        }
+        let len = self.prg_rom_log.len();
+        // Bits 2 and 3 record the 8K window of CPU memory that the byte was mapped to.
+        let window = ((cpu_addr >> 13) & 0x03) as u8;
+        let entry = &mut self.prg_rom_log[addr % len];
+        *entry = (*entry & !0x0C) | (window << 2) | flags;
+    }
+
+    pub fn log_chr_rom(&mut self, addr: usize, flags: u8) {
+        if !self.enabled || self.chr_rom_log.is_empty() {
+            return;
+        }
+        let len = self.chr_rom_log.len();
+        self.chr_rom_log[addr % len] |= flags;
+    }
+
+    pub fn clear(&mut self) {
+        self.prg_rom_log.iter_mut().for_each(|val| *val = 0);
+        self.chr_rom_log.iter_mut().for_each(|val| *val = 0);
+    }
+
+    // The FCEUX format is the PRG ROM log followed by the CHR ROM log.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(self.prg_rom_log.len() + self.chr_rom_log.len());
+        ret.extend_from_slice(&self.prg_rom_log);
+        ret.extend_from_slice(&self.chr_rom_log);
+        ret
+    }
+
+    pub fn load_bytes(&mut self, buffer: &[u8]) -> bool {
+        if buffer.len() != self.prg_rom_log.len() + self.chr_rom_log.len() {
+            return false;
+        }
+        let (prg_rom_log, chr_rom_log) = buffer.split_at(self.prg_rom_log.len());
+        self.prg_rom_log.copy_from_slice(prg_rom_log);
+        self.chr_rom_log.copy_from_slice(chr_rom_log);
+        true
+    }
+}
+
+impl Default for CodeDataLogger {
+    fn default() -> Self {
+        CodeDataLogger::new(0, 0)
+    }
+}
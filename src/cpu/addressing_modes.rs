@@ -1,4 +1,4 @@
-use crate::cpu::Cpu;
+use crate::cpu::{Cpu6502, Memory};
 
 pub const ABSOLUTE: usize = 1;
 pub const ABSOLUTE_X: usize = 2;
@@ -14,73 +14,75 @@ pub const ZERO_PAGE: usize = 11;
 pub const ZERO_PAGE_X: usize = 12;
 pub const ZERO_PAGE_Y: usize = 13;
 
-pub const FUNCTION_TABLE: [fn(&mut Cpu) -> (u16, bool); 14] = [
-    |_: &mut Cpu| panic!("[CPU] Invalid addressing mode."),
-    // absolute
-    |cpu: &mut Cpu| (cpu.decode_word(), false),
-    // absolute x
-    |cpu: &mut Cpu| {
-        let addr = cpu.decode_word();
-        let ret = addr.wrapping_add(cpu.r.x as u16);
-        (ret, addr & 0xFF00 != ret & 0xFF00)
-    },
-    // absolute y
-    |cpu: &mut Cpu| {
-        let addr = cpu.decode_word();
-        let ret = addr.wrapping_add(cpu.r.y as u16);
-        (ret, addr & 0xFF00 != ret & 0xFF00)
-    },
-    // accumulator
-    |_: &mut Cpu| panic!("[CPU] No address associated with accumulator mode."),
-    // immediate
-    |cpu: &mut Cpu| {
-        let ret = cpu.r.pc;
-        cpu.r.pc += 1;
-        (ret, false)
-    },
-    // implied
-    |_: &mut Cpu| panic!("[CPU] No address associated with implied mode."),
-    // indirect
-    |cpu: &mut Cpu| {
-        let addr = cpu.decode_word();
-        if addr & 0xFF == 0xFF {
-            let hi = (cpu.read_byte(addr & 0xFF00) as u16) << 8;
+impl<M: Memory> Cpu6502<M> {
+    pub(super) const FUNCTION_TABLE: [fn(&mut Self) -> (u16, bool); 14] = [
+        |_: &mut Self| panic!("[CPU] Invalid addressing mode."),
+        // absolute
+        |cpu: &mut Self| (cpu.decode_word(), false),
+        // absolute x
+        |cpu: &mut Self| {
+            let addr = cpu.decode_word();
+            let ret = addr.wrapping_add(cpu.r.x as u16);
+            (ret, addr & 0xFF00 != ret & 0xFF00)
+        },
+        // absolute y
+        |cpu: &mut Self| {
+            let addr = cpu.decode_word();
+            let ret = addr.wrapping_add(cpu.r.y as u16);
+            (ret, addr & 0xFF00 != ret & 0xFF00)
+        },
+        // accumulator
+        |_: &mut Self| panic!("[CPU] No address associated with accumulator mode."),
+        // immediate
+        |cpu: &mut Self| {
+            let ret = cpu.r.pc;
+            cpu.r.pc += 1;
+            (ret, false)
+        },
+        // implied
+        |_: &mut Self| panic!("[CPU] No address associated with implied mode."),
+        // indirect
+        |cpu: &mut Self| {
+            let addr = cpu.decode_word();
+            if addr & 0xFF == 0xFF {
+                let hi = (cpu.read_byte(addr & 0xFF00) as u16) << 8;
+                let lo = cpu.read_byte(addr) as u16;
+                (hi | lo, false)
+            } else {
+                (cpu.read_word(addr), false)
+            }
+        },
+        // indirect x
+        |cpu: &mut Self| {
+            let addr = (cpu.decode_byte()).wrapping_add(cpu.r.x) as u16;
+            // read 2-byte address without carry
+            let hi = (cpu.read_byte((addr + 1) & 0xFF) as u16) << 8;
             let lo = cpu.read_byte(addr) as u16;
             (hi | lo, false)
-        } else {
-            (cpu.read_word(addr), false)
-        }
-    },
-    // indirect x
-    |cpu: &mut Cpu| {
-        let addr = (cpu.decode_byte()).wrapping_add(cpu.r.x) as u16;
-        // read 2-byte address without carry
-        let hi = (cpu.read_byte((addr + 1) & 0xFF) as u16) << 8;
-        let lo = cpu.read_byte(addr) as u16;
-        (hi | lo, false)
-    },
-    // indirect y
-    |cpu: &mut Cpu| {
-        let addr = cpu.decode_byte() as u16;
-        // read 2-byte address without carry
-        let hi = (cpu.read_byte((addr + 1) & 0xFF) as u16) << 8;
-        let lo = cpu.read_byte(addr) as u16;
-        let addr = hi | lo;
+        },
+        // indirect y
+        |cpu: &mut Self| {
+            let addr = cpu.decode_byte() as u16;
+            // read 2-byte address without carry
+            let hi = (cpu.read_byte((addr + 1) & 0xFF) as u16) << 8;
+            let lo = cpu.read_byte(addr) as u16;
+            let addr = hi | lo;
 
-        let ret = addr.wrapping_add(cpu.r.y as u16);
-        (ret, addr & 0xFF00 != ret & 0xFF00)
-    },
-    // relative
-    |cpu: &mut Cpu| {
-        (
-            (cpu.r.pc as i16 + 1 + i16::from(cpu.decode_byte() as i8)) as u16,
-            false,
-        )
-    },
-    // zero page
-    |cpu: &mut Cpu| (cpu.decode_byte() as u16, false),
-    // zero page x
-    |cpu: &mut Cpu| (cpu.decode_byte().wrapping_add(cpu.r.x) as u16, false),
-    // zero page y
-    |cpu: &mut Cpu| (cpu.decode_byte().wrapping_add(cpu.r.y) as u16, false),
-];
+            let ret = addr.wrapping_add(cpu.r.y as u16);
+            (ret, addr & 0xFF00 != ret & 0xFF00)
+        },
+        // relative
+        |cpu: &mut Self| {
+            (
+                (cpu.r.pc as i16 + 1 + i16::from(cpu.decode_byte() as i8)) as u16,
+                false,
+            )
+        },
+        // zero page
+        |cpu: &mut Self| (cpu.decode_byte() as u16, false),
+        // zero page x
+        |cpu: &mut Self| (cpu.decode_byte().wrapping_add(cpu.r.x) as u16, false),
+        // zero page y
+        |cpu: &mut Self| (cpu.decode_byte().wrapping_add(cpu.r.y) as u16, false),
+    ];
+}
@@ -0,0 +1,184 @@
+use crate::bus::Bus;
+use crate::cdl;
+use crate::controller::Controller;
+use crate::cpu::Memory;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::BigArray;
+#[cfg(not(target_arch = "wasm32"))]
+use serde_derive::{Deserialize, Serialize};
+
+// https://wiki.nesdev.com/w/index.php/CPU_memory_map
+#[cfg_attr(not(target_arch = "wasm32"), derive(Deserialize, Serialize))]
+pub struct MemoryMap {
+    pub controllers: [Controller; 2],
+    #[cfg_attr(not(target_arch = "wasm32"), serde(with = "BigArray"))]
+    pub ram: [u8; 0x800],
+    // The last value driven on the data bus. Reads from unmapped addresses return this value.
+    open_bus: u8,
+    // Set by a write to $4014 until the CPU is halted for the OAM DMA transfer.
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    oam_dma_pending: bool,
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    bus: Option<Bus>,
+}
+
+impl MemoryMap {
+    pub fn new() -> Self {
+        MemoryMap {
+            controllers: [Controller::default(), Controller::default()],
+            ram: [0; 0x800],
+            open_bus: 0,
+            oam_dma_pending: false,
+            bus: None,
+        }
+    }
+
+    pub fn attach_bus(&mut self, bus: Bus) {
+        self.bus = Some(bus);
+    }
+
+    // Reads a DMC sample byte, which is logged as PCM data rather than as data.
+    pub fn read_pcm_byte(&mut self, addr: u16) -> u8 {
+        self.read(addr, cdl::PRG_PCM_DATA)
+    }
+
+    // Reads `addr` without side effects. Registers read as 0.
+    pub fn peek_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.ram[(addr % 0x0800) as usize],
+            0x4020..=0xFFFF => {
+                let mapper = self.bus().mapper();
+                if mapper.is_prg_mapped(addr) {
+                    mapper.read_byte(addr)
+                } else {
+                    0
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    fn bus(&self) -> &Bus {
+        self.bus.as_ref().expect("[CPU] No bus attached.")
+    }
+
+    fn bus_mut(&mut self) -> &mut Bus {
+        self.bus.as_mut().expect("[CPU] No bus attached.")
+    }
+}
+
+impl MemoryMap {
+    // `cdl_flags` is how the read is logged if `addr` is mapped to PRG ROM.
+    fn read(&mut self, addr: u16, cdl_flags: u8) -> u8 {
+        let val = match addr {
+            0x0000..=0x1FFF => self.ram[(addr % 0x0800) as usize],
+            0x2000..=0x3FFF => {
+                let ppu = self.bus_mut().ppu_mut();
+                let addr = (addr - 0x2000) % 8 + 0x2000;
+                ppu.read_register(addr)
+            }
+            // Reading $4015 does not drive the external data bus, so bit 5 is open bus and the
+            // value read is not latched.
+            0x4015 => {
+                let open_bus = self.open_bus;
+                let apu = self.bus_mut().apu_mut();
+                return apu.read_register(addr) | (open_bus & 0x20);
+            }
+            // Only the lower bits are driven by the controller ports.
+            0x4016 => (self.open_bus & 0xE0) | self.controllers[0].read_value(),
+            0x4017 => (self.open_bus & 0xE0) | self.controllers[1].read_value(),
+            // Write-only APU and I/O registers.
+            0x4000..=0x4014 => self.open_bus,
+            // CPU test mode is disabled on retail units, so these registers do not respond.
+            0x4018..=0x401F => self.open_bus,
+            0x4020..=0xFFFF => {
+                let mapper = self.bus().mapper();
+                if mapper.is_prg_mapped(addr) {
+                    let val = mapper.read_byte(addr);
+                    if let Some(prg_rom_addr) = mapper.prg_rom_address(addr) {
+                        let cdl = self.bus_mut().cdl_mut();
+                        cdl.log_prg_rom(prg_rom_addr, addr, cdl_flags);
+                    }
+                    val
+                } else {
+                    self.open_bus
+                }
+            }
+        };
+        self.open_bus = val;
+        val
+    }
+}
+
+impl Memory for MemoryMap {
+    fn read_byte(&mut self, addr: u16) -> u8 {
+        self.read(addr, cdl::PRG_DATA)
+    }
+
+    fn fetch_byte(&mut self, addr: u16) -> u8 {
+        self.read(addr, cdl::PRG_CODE)
+    }
+
+    fn nmi_line(&mut self) -> bool {
+        self.bus().ppu().nmi_line()
+    }
+
+    fn take_nmi_cancelled(&mut self) -> bool {
+        self.bus_mut().ppu_mut().take_nmi_cancelled()
+    }
+
+    fn write_byte(&mut self, addr: u16, val: u8) {
+        self.open_bus = val;
+        match addr {
+            0x0000..=0x1FFF => self.ram[(addr % 0x0800) as usize] = val,
+            0x2000..=0x3FFF => {
+                let ppu = self.bus_mut().ppu_mut();
+                let addr = (addr - 0x2000) % 8 + 0x2000;
+                ppu.log_register_write(addr, val);
+                ppu.write_register(addr, val);
+            }
+            0x4014 => {
+                let cpu_addr = u16::from(val) << 8;
+                for offset in 0..=0xFF {
+                    let cpu_addr = cpu_addr + offset;
+                    let cpu_val = self.read_byte(cpu_addr);
+                    let ppu = self.bus_mut().ppu_mut();
+                    ppu.write_register(0x2004, cpu_val);
+                }
+                self.oam_dma_pending = true;
+            }
+            0x4016 => {
+                self.controllers[0].write_strobe(val & 0x01 != 0);
+                self.controllers[1].write_strobe(val & 0x01 != 0);
+            }
+            0x4000..=0x4017 => {
+                let apu = self.bus_mut().apu_mut();
+                apu.write_register(addr, val);
+            }
+            0x4018..=0x401F => {}
+            0x4020..=0xFFFF => {
+                let mapper = self.bus_mut().mapper_mut();
+                mapper.write_byte(addr, val);
+            }
+        }
+    }
+
+    fn take_dma_cycles(&mut self, cycle: u64) -> u64 {
+        if !std::mem::take(&mut self.oam_dma_pending) {
+            return 0;
+        }
+        // The transfer takes an extra cycle to align to an even cycle when it starts on an odd
+        // cycle.
+        if cycle % 2 == 1 {
+            514
+        } else {
+            513
+        }
+    }
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        MemoryMap::new()
+    }
+}
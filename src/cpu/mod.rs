@@ -1,49 +1,117 @@
 mod addressing_modes;
+mod memory_map;
 mod opcodes;
 mod registers;
 
+pub use self::memory_map::MemoryMap;
 use self::registers::Registers;
 use crate::bus::Bus;
-use crate::controller::Controller;
-#[cfg(not(target_arch = "wasm32"))]
-use crate::BigArray;
 #[cfg(not(target_arch = "wasm32"))]
 use serde_derive::{Deserialize, Serialize};
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
 
 const STACK_START: u16 = 0x100;
 
+/// The CPU of the NES, which is a 6502 attached to the NES memory map.
+pub type Cpu = Cpu6502<MemoryMap>;
+
+/// The memory that a `Cpu6502` reads from and writes to.
+pub trait Memory {
+    /// Reads the byte at `addr`.
+    fn read_byte(&mut self, addr: u16) -> u8;
+
+    /// Writes `val` to `addr`.
+    fn write_byte(&mut self, addr: u16, val: u8);
+
+    /// Reads the byte at `addr` as part of an instruction, which is either an opcode or an
+    /// operand. Defaults to `read_byte`.
+    fn fetch_byte(&mut self, addr: u16) -> u8 {
+        self.read_byte(addr)
+    }
+
+    /// Called once for every cycle that the CPU is stepped.
+    fn tick(&mut self) {}
+
+    /// Returns the number of cycles that the CPU is halted for by a DMA transfer that was started
+    /// by the last instruction, and clears it. `cycle` is the current CPU cycle, since the length
+    /// of a transfer can depend on its alignment. Defaults to `0`.
+    fn take_dma_cycles(&mut self, _cycle: u64) -> u64 {
+        0
+    }
+
+    /// Returns the level of the NMI input line. An NMI is requested when the line goes from low to
+    /// high. Defaults to `false`.
+    fn nmi_line(&mut self) -> bool {
+        false
+    }
+
+    /// Returns `true` if an NMI that was requested by the NMI line has been withdrawn during the
+    /// last instruction, and clears it. The NMI is not handled. Defaults to `false`.
+    fn take_nmi_cancelled(&mut self) -> bool {
+        false
+    }
+}
+
+/// The variant of the 6502 that is emulated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Deserialize, Serialize))]
-pub struct Cpu {
-    pub cycle: u64,
-    pub stall_cycle: u64,
-    pub controllers: [Controller; 2],
-    #[cfg_attr(not(target_arch = "wasm32"), serde(with = "BigArray"))]
-    pub ram: [u8; 0x800],
+pub enum CpuVariant {
+    /// The Ricoh 2A03 used in the NES, which ignores the decimal mode flag.
+    Ricoh2A03,
+    /// The NMOS 6502, which performs BCD arithmetic for `ADC` and `SBC` when the decimal mode
+    /// flag is set.
+    Nmos6502,
+}
+
+/// An instruction-cycle accurate MOS 6502 with unofficial instructions that is generic over the
+/// memory that it is attached to.
+#[cfg_attr(not(target_arch = "wasm32"), derive(Deserialize, Serialize))]
+pub struct Cpu6502<M: Memory> {
+    pub(crate) cycle: u64,
+    pub(crate) stall_cycle: u64,
+    pub(crate) variant: CpuVariant,
+    // Set when a KIL opcode is executed. The CPU stops fetching instructions until it is reset.
+    pub(crate) jammed: bool,
+    // The opcode fetched on the first cycle of the current instruction. It is executed on the last
+    // cycle of the instruction, so that the final read or write lands on the right cycle.
+    opcode: Option<u8>,
     interrupt_flags: [bool; 2],
+    // The interrupt flags at the end of the previous cycle. Interrupts are polled at the end of
+    // the second to last cycle of an instruction.
+    polled_interrupt_flags: [bool; 2],
+    // The level of the NMI line on the previous cycle, used to detect its rising edge.
+    prev_nmi_line: bool,
     r: Registers,
-    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
-    bus: Option<Bus>,
+    pub(crate) memory: M,
 }
 
-impl Cpu {
-    pub fn new() -> Self {
-        Cpu {
+impl<M: Memory> Cpu6502<M> {
+    /// Constructs a new `Cpu6502` attached to `memory`.
+    pub fn new(memory: M) -> Self {
+        Cpu6502 {
             cycle: 0,
             stall_cycle: 0,
-            controllers: [Controller::default(), Controller::default()],
-            ram: [0; 0x800],
+            variant: CpuVariant::Ricoh2A03,
+            jammed: false,
+            opcode: None,
             interrupt_flags: [false; 2],
+            polled_interrupt_flags: [false; 2],
+            prev_nmi_line: false,
             r: Registers::default(),
-            bus: None,
+            memory,
         }
     }
 
+    /// Powers on the CPU by loading the program counter from the reset vector at `$FFFC`.
     pub fn initialize(&mut self) {
         self.r.pc = self.read_word(0xFFFC);
         self.r.sp = 0xFD;
         self.r.p = 0x24;
     }
 
+    /// Resets the CPU by loading the program counter from the reset vector at `$FFFC`.
     pub fn reset(&mut self) {
         self.r.pc = self.read_word(0xFFFC);
         self.r.sp -= 3;
@@ -51,42 +119,145 @@ impl Cpu {
             .set_status_flag(registers::INTERRUPT_DISABLE_MASK, true);
         self.cycle = 0;
         self.stall_cycle = 0;
+        self.jammed = false;
+        self.opcode = None;
+        self.interrupt_flags = [false; 2];
+        self.polled_interrupt_flags = [false; 2];
+    }
+
+    /// Returns a reference to the memory that the CPU is attached to.
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
+
+    /// Returns a mutable reference to the memory that the CPU is attached to.
+    pub fn memory_mut(&mut self) -> &mut M {
+        &mut self.memory
+    }
+
+    /// Returns the variant of the 6502 that is emulated.
+    pub fn variant(&self) -> CpuVariant {
+        self.variant
+    }
+
+    /// Sets the variant of the 6502 that is emulated. Defaults to `CpuVariant::Ricoh2A03`.
+    pub fn set_variant(&mut self, variant: CpuVariant) {
+        self.variant = variant;
+    }
+
+    /// Returns the number of cycles that have elapsed. An instruction is fetched on its first
+    /// cycle and executed on its last cycle.
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Returns the program counter.
+    pub fn pc(&self) -> u16 {
+        self.r.pc
+    }
+
+    /// Sets the program counter.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.r.pc = pc;
+    }
+
+    /// Returns the stack pointer.
+    pub fn sp(&self) -> u8 {
+        self.r.sp
     }
 
-    pub fn attach_bus(&mut self, bus: Bus) {
-        self.bus = Some(bus);
+    /// Returns the accumulator.
+    pub fn a(&self) -> u8 {
+        self.r.a
     }
 
-    fn bus(&self) -> &Bus {
-        self.bus.as_ref().expect("[CPU] No bus attached.")
+    /// Returns the X index register.
+    pub fn x(&self) -> u8 {
+        self.r.x
     }
 
-    fn bus_mut(&mut self) -> &mut Bus {
-        self.bus.as_mut().expect("[CPU] No bus attached.")
+    /// Returns the Y index register.
+    pub fn y(&self) -> u8 {
+        self.r.y
     }
 
+    /// Returns the processor status register.
+    pub fn p(&self) -> u8 {
+        self.r.p
+    }
+
+    /// Runs the CPU for one cycle.
     pub fn step(&mut self) {
+        self.memory.tick();
+        self.cycle += 1;
+
+        // Interrupts that are detected on the last cycle of an instruction are not handled until
+        // after the next instruction.
+        let polled_interrupt_flags = self.polled_interrupt_flags;
+        let nmi_line = self.memory.nmi_line();
+        if nmi_line && !self.prev_nmi_line {
+            self.interrupt_flags[Interrupt::NMI as usize] = true;
+        }
+        self.prev_nmi_line = nmi_line;
+        self.polled_interrupt_flags = self.interrupt_flags;
+
         if self.stall_cycle > 0 {
             self.stall_cycle -= 1;
             return;
         }
 
-        let start_cycle = self.cycle;
-        // handle any interrupts
-        for index in 0..self.interrupt_flags.len() {
-            if self.interrupt_flags[index] {
-                self.handle_interrupt(index);
-                return;
+        if let Some(opcode) = self.opcode.take() {
+            self.execute_opcode(opcode);
+            if self.memory.take_nmi_cancelled() {
+                self.interrupt_flags[Interrupt::NMI as usize] = false;
             }
+            self.stall_cycle += self.memory.take_dma_cycles(self.cycle);
+            return;
+        }
+
+        if self.jammed {
+            return;
+        }
+
+        // handle any interrupts
+        let interrupt = polled_interrupt_flags
+            .iter()
+            .zip(self.interrupt_flags.iter())
+            .position(|(polled, flag)| *polled && *flag);
+        if let Some(interrupt) = interrupt {
+            self.handle_interrupt(interrupt);
+            self.stall_cycle += 6;
+            return;
         }
 
         // print!("{:04X} ", self.r.pc);
         let opcode = self.decode_byte();
         // print!("{:02X} ", opcode);
-        self.execute_opcode(opcode);
-        self.stall_cycle += (self.cycle - start_cycle) - 1;
+        self.opcode = Some(opcode);
+        self.stall_cycle += u64::from(opcodes::CYCLE_TABLE[opcode as usize]) - 2;
+    }
+
+    /// Returns `true` if the CPU has executed a `KIL` opcode and stopped fetching instructions.
+    pub fn is_jammed(&self) -> bool {
+        self.jammed
+    }
+
+    /// Returns the address of the `KIL` opcode that jammed the CPU, or `None` if the CPU is not
+    /// jammed.
+    pub fn jammed_address(&self) -> Option<u16> {
+        if self.jammed {
+            Some(self.r.pc)
+        } else {
+            None
+        }
+    }
+
+    fn decimal_mode_enabled(&mut self) -> bool {
+        self.variant == CpuVariant::Nmos6502 && self.r.get_status_flag(registers::DECIMAL_MODE_MASK)
     }
 
+    /// Requests an interrupt. Maskable interrupts are ignored if the interrupt disable flag is
+    /// set.
     pub fn trigger_interrupt(&mut self, interrupt: Interrupt) {
         let is_disabled = self.r.get_status_flag(registers::INTERRUPT_DISABLE_MASK);
         if !is_disabled || interrupt == Interrupt::NMI {
@@ -94,7 +265,7 @@ impl Cpu {
         }
     }
 
-    pub fn handle_interrupt(&mut self, interrupt: usize) {
+    fn handle_interrupt(&mut self, interrupt: usize) {
         let val = self.r.pc;
         self.push_word(val);
         let val = self.r.p | 0x10;
@@ -108,14 +279,16 @@ impl Cpu {
     // pc related functions
     fn decode_byte(&mut self) -> u8 {
         let pc = self.r.pc;
-        let ret = self.read_byte(pc);
+        let ret = self.memory.fetch_byte(pc);
         self.r.pc += 1;
         ret
     }
 
     fn decode_word(&mut self) -> u16 {
         let pc = self.r.pc;
-        let ret = self.read_word(pc);
+        let lo = u16::from(self.memory.fetch_byte(pc));
+        let hi = u16::from(self.memory.fetch_byte(pc + 1));
+        let ret = (hi << 8) | lo;
         self.r.pc += 2;
         ret
     }
@@ -142,84 +315,28 @@ impl Cpu {
         u16::from(self.pop_byte()) | (u16::from(self.pop_byte()) << 8)
     }
 
-    // memory map related functions
+    // memory related functions
+    /// Reads the byte at `addr` from the attached memory.
     pub fn read_byte(&mut self, addr: u16) -> u8 {
-        match addr {
-            0x0000..=0x1FFF => self.ram[(addr % 0x0800) as usize],
-            0x2000..=0x3FFF => {
-                let ppu = self.bus_mut().ppu_mut();
-                let addr = (addr - 0x2000) % 8 + 0x2000;
-                ppu.read_register(addr)
-            }
-            0x4016 => self.controllers[0].read_value(),
-            0x4017 => self.controllers[1].read_value(),
-            0x4000..=0x4015 => {
-                let apu = self.bus_mut().apu_mut();
-                apu.read_register(addr)
-            }
-            0x4018..=0x401F => panic!("CPU Test Mode not implemented."),
-            0x4020..=0xFFFF => {
-                let mapper = self.bus().mapper();
-                mapper.read_byte(addr)
-            }
-        }
+        self.memory.read_byte(addr)
     }
 
+    /// Reads the little-endian word at `addr` from the attached memory.
     pub fn read_word(&mut self, addr: u16) -> u16 {
-        (u16::from(self.read_byte(addr + 1)) << 8) | u16::from(self.read_byte(addr))
+        // The low byte is read first so that the high byte is left on the data bus.
+        let lo = u16::from(self.read_byte(addr));
+        let hi = u16::from(self.read_byte(addr + 1));
+        (hi << 8) | lo
     }
 
+    /// Writes `val` to `addr` in the attached memory.
     pub fn write_byte(&mut self, addr: u16, val: u8) {
-        match addr {
-            0x0000..=0x1FFF => self.ram[(addr % 0x0800) as usize] = val,
-            0x2000..=0x3FFF => {
-                let ppu = self.bus_mut().ppu_mut();
-                let old_nmi_enabled = ppu.r.nmi_enabled;
-                let addr = (addr - 0x2000) % 8 + 0x2000;
-                ppu.write_register(addr, val);
-                let nmi_enabled_toggled = !old_nmi_enabled && ppu.r.nmi_enabled;
-
-                if nmi_enabled_toggled && ppu.r.v_blank_started {
-                    self.trigger_interrupt(Interrupt::NMI);
-                }
-            }
-            0x4014 => {
-                let cpu_addr = u16::from(val) << 8;
-                for offset in 0..=0xFF {
-                    let cpu_addr = cpu_addr + offset;
-                    let cpu_val = self.read_byte(cpu_addr);
-                    let ppu = self.bus_mut().ppu_mut();
-                    let oam_addr = ppu.r.oam_addr;
-                    ppu.primary_oam[oam_addr as usize] = cpu_val;
-                    ppu.r.oam_addr = oam_addr.wrapping_add(1);
-                }
-
-                if self.cycle % 2 == 1 {
-                    self.stall_cycle += 514;
-                } else {
-                    self.stall_cycle += 513;
-                }
-            }
-            0x4016 => {
-                self.controllers[0].write_strobe(val & 0x01 != 0);
-                self.controllers[1].write_strobe(val & 0x01 != 0);
-            }
-            0x4000..=0x4017 => {
-                let apu = self.bus_mut().apu_mut();
-                apu.write_register(addr, val);
-            }
-            0x4018..=0x401F => panic!("CPU Test Mode not implemented."),
-            0x4020..=0xFFFF => {
-                let mapper = self.bus_mut().mapper_mut();
-                mapper.write_byte(addr, val);
-            }
-        }
+        self.memory.write_byte(addr, val);
     }
 
     fn execute_opcode(&mut self, opcode: u8) {
         let addressing_mode = opcodes::ADDRESSING_MODE_TABLE[opcode as usize];
-        opcodes::INSTRUCTION_TABLE[opcode as usize](self, addressing_mode);
-        self.cycle += u64::from(opcodes::CYCLE_TABLE[opcode as usize]);
+        Self::INSTRUCTION_TABLE[opcode as usize](self, addressing_mode);
     }
 
     fn get_operand(&mut self, addressing_mode: usize) -> opcodes::Operand {
@@ -229,8 +346,16 @@ impl Cpu {
                 addr: None,
                 page_crossing: false,
             },
+            addressing_modes::IMMEDIATE => {
+                let (addr, page_crossing) = Self::FUNCTION_TABLE[addressing_mode](self);
+                opcodes::Operand {
+                    val: self.memory.fetch_byte(addr),
+                    addr: Some(addr),
+                    page_crossing,
+                }
+            }
             _ => {
-                let (addr, page_crossing) = addressing_modes::FUNCTION_TABLE[addressing_mode](self);
+                let (addr, page_crossing) = Self::FUNCTION_TABLE[addressing_mode](self);
                 opcodes::Operand {
                     val: self.read_byte(addr),
                     addr: Some(addr),
@@ -248,15 +373,24 @@ impl Cpu {
     }
 }
 
-impl Default for Cpu {
+impl Cpu {
+    pub(crate) fn attach_bus(&mut self, bus: Bus) {
+        self.memory.attach_bus(bus);
+    }
+}
+
+impl<M: Memory + Default> Default for Cpu6502<M> {
     fn default() -> Self {
-        Cpu::new()
+        Cpu6502::new(M::default())
     }
 }
 
+/// An interrupt that can be requested on a `Cpu6502`.
 #[derive(Debug, PartialEq)]
 pub enum Interrupt {
+    /// A non-maskable interrupt.
     NMI = 0,
+    /// A maskable interrupt request.
     IRQ = 1,
 }
 
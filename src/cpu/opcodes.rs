@@ -1,4 +1,4 @@
-use crate::cpu::{addressing_modes, registers, Cpu, Interrupt};
+use crate::cpu::{addressing_modes, registers, Cpu6502, Interrupt, Memory};
 
 pub struct Operand {
     pub val: u8,
@@ -6,44 +6,46 @@ pub struct Operand {
     pub page_crossing: bool,
 }
 
-#[rustfmt::skip]
-pub const INSTRUCTION_TABLE: [fn(&mut Cpu, usize) -> (); 256] = [
-    brk, ora, inv, slo, dop, ora, asl, slo, php, ora, asl, anc, top, ora, asl, slo, // 00
-    bpl, ora, inv, slo, dop, ora, asl, slo, clc, ora, nop, slo, top, ora, asl, slo, // 10
-    jsr, and, inv, rla, bit, and, rol, rla, plp, and, rol, anc, bit, and, rol, rla, // 20
-    bmi, and, inv, rla, dop, and, rol, rla, sec, and, nop, rla, top, and, rol, rla, // 30
-    rti, eor, inv, sre, dop, eor, lsr, sre, pha, eor, lsr, asr, jmp, eor, lsr, sre, // 40
-    bvc, eor, inv, sre, dop, eor, lsr, sre, cli, eor, nop, sre, top, eor, lsr, sre, // 50
-    rts, adc, inv, rra, dop, adc, ror, rra, pla, adc, ror, arr, jmp, adc, ror, rra, // 60
-    bvs, adc, inv, rra, dop, adc, ror, rra, sei, adc, nop, rra, top, adc, ror, rra, // 70
-    dop, sta, dop, aax, sty, sta, stx, aax, dey, dop, txa, xaa, sty, sta, stx, aax, // 80
-    bcc, sta, inv, axa, sty, sta, stx, aax, tya, sta, txs, tas, shy, sta, shx, axa, // 90
-    ldy, lda, ldx, lax, ldy, lda, ldx, lax, tay, lda, tax, lax, ldy, lda, ldx, lax, // A0
-    bcs, lda, inv, lax, ldy, lda, ldx, lax, clv, lda, tsx, las, ldy, lda, ldx, lax, // B0
-    cpy, cmp, dop, dcp, cpy, cmp, dec, dcp, iny, cmp, dex, axs, cpy, cmp, dec, dcp, // C0
-    bne, cmp, inv, dcp, dop, cmp, dec, dcp, cld, cmp, nop, dcp, top, cmp, dec, dcp, // D0
-    cpx, sbc, dop, isc, cpx, sbc, inc, isc, inx, sbc, nop, sbc, cpx, sbc, inc, isc, // E0
-    beq, sbc, inv, isc, dop, sbc, inc, isc, sed, sbc, nop, isc, top, sbc, inc, isc, // F0
-];
+impl<M: Memory> Cpu6502<M> {
+    #[rustfmt::skip]
+    pub(super) const INSTRUCTION_TABLE: [fn(&mut Self, usize); 256] = [
+        brk, ora, kil, slo, dop, ora, asl, slo, php, ora, asl, anc, top, ora, asl, slo, // 00
+        bpl, ora, kil, slo, dop, ora, asl, slo, clc, ora, nop, slo, top, ora, asl, slo, // 10
+        jsr, and, kil, rla, bit, and, rol, rla, plp, and, rol, anc, bit, and, rol, rla, // 20
+        bmi, and, kil, rla, dop, and, rol, rla, sec, and, nop, rla, top, and, rol, rla, // 30
+        rti, eor, kil, sre, dop, eor, lsr, sre, pha, eor, lsr, asr, jmp, eor, lsr, sre, // 40
+        bvc, eor, kil, sre, dop, eor, lsr, sre, cli, eor, nop, sre, top, eor, lsr, sre, // 50
+        rts, adc, kil, rra, dop, adc, ror, rra, pla, adc, ror, arr, jmp, adc, ror, rra, // 60
+        bvs, adc, kil, rra, dop, adc, ror, rra, sei, adc, nop, rra, top, adc, ror, rra, // 70
+        dop, sta, dop, aax, sty, sta, stx, aax, dey, dop, txa, xaa, sty, sta, stx, aax, // 80
+        bcc, sta, kil, axa, sty, sta, stx, aax, tya, sta, txs, tas, shy, sta, shx, axa, // 90
+        ldy, lda, ldx, lax, ldy, lda, ldx, lax, tay, lda, tax, lax, ldy, lda, ldx, lax, // A0
+        bcs, lda, kil, lax, ldy, lda, ldx, lax, clv, lda, tsx, las, ldy, lda, ldx, lax, // B0
+        cpy, cmp, dop, dcp, cpy, cmp, dec, dcp, iny, cmp, dex, axs, cpy, cmp, dec, dcp, // C0
+        bne, cmp, kil, dcp, dop, cmp, dec, dcp, cld, cmp, nop, dcp, top, cmp, dec, dcp, // D0
+        cpx, sbc, dop, isc, cpx, sbc, inc, isc, inx, sbc, nop, sbc, cpx, sbc, inc, isc, // E0
+        beq, sbc, kil, isc, dop, sbc, inc, isc, sed, sbc, nop, isc, top, sbc, inc, isc, // F0
+    ];
+}
 
 #[rustfmt::skip]
 pub const CYCLE_TABLE: [u8; 256] = [
-    7, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6, // 00
-    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 10
-    6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6, // 20
-    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 30
-    6, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6, // 40
-    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 50
-    6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6, // 60
-    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 70
+    7, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6, // 00
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 10
+    6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6, // 20
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 30
+    6, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6, // 40
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 50
+    6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6, // 60
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 70
     2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4, // 80
-    2, 6, 0, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5, // 90
+    2, 6, 2, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5, // 90
     2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4, // A0
-    2, 5, 0, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4, // B0
+    2, 5, 2, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4, // B0
     2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6, // C0
-    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // D0
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // D0
     2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6, // E0
-    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // F0
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // F0
 ];
 
 #[rustfmt::skip]
@@ -66,24 +68,23 @@ pub const ADDRESSING_MODE_TABLE: [usize; 256] = [
     10,  9,  0,  9, 12, 12, 12, 12,  6,  3,  6,  3,  2,  2,  2,  2, // F0
 ];
 
-fn inv(cpu: &mut Cpu, _addressing_mode: usize) {
-    let addr = cpu.r.pc - 1;
-    panic!("[CPU] Invalid opcode: {:#04x}.", cpu.read_byte(addr));
-}
-
-fn aax(cpu: &mut Cpu, addressing_mode: usize) {
-    let (addr, _page_break) = addressing_modes::FUNCTION_TABLE[addressing_mode](cpu);
+fn aax<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
+    let (addr, _page_break) = Cpu6502::<M>::FUNCTION_TABLE[addressing_mode](cpu);
 
     let res = cpu.r.x & cpu.r.a;
     cpu.write_byte(addr, res);
 }
 
-fn adc_impl(cpu: &mut Cpu, operand: &Operand) {
+fn adc_impl<M: Memory>(cpu: &mut Cpu6502<M>, operand: &Operand) {
     let carry = if cpu.r.p & registers::CARRY_MASK == 0 {
         0
     } else {
         1
     };
+    if cpu.decimal_mode_enabled() {
+        adc_decimal_impl(cpu, operand, carry);
+        return;
+    }
     let (res, is_overflow_1) = cpu.r.a.overflowing_add(operand.val);
     let (res, is_overflow_2) = res.overflowing_add(carry);
     let overflow = !(operand.val ^ cpu.r.a) & (res ^ cpu.r.a) & 0x80 != 0;
@@ -94,16 +95,39 @@ fn adc_impl(cpu: &mut Cpu, operand: &Operand) {
     cpu.r.a = res;
 }
 
-fn adc(cpu: &mut Cpu, addressing_mode: usize) {
+// http://www.6502.org/tutorials/decimal_mode.html#A
+fn adc_decimal_impl<M: Memory>(cpu: &mut Cpu6502<M>, operand: &Operand, carry: u8) {
+    let a = u16::from(cpu.r.a);
+    let val = u16::from(operand.val);
+    let mut lo = (a & 0x0F) + (val & 0x0F) + u16::from(carry);
+    if lo >= 0x0A {
+        lo = ((lo + 0x06) & 0x0F) + 0x10;
+    }
+    let mut res = (a & 0xF0) + (val & 0xF0) + lo;
+    // The negative and overflow flags are computed before the high nibble is adjusted, and the
+    // zero flag is computed from the binary result.
+    let overflow = !(a ^ val) & (a ^ res) & 0x80 != 0;
+    cpu.r.update_negative_flag(res as u8);
+    cpu.r
+        .update_zero_flag(cpu.r.a.wrapping_add(operand.val).wrapping_add(carry));
+    if res >= 0xA0 {
+        res += 0x60;
+    }
+    cpu.r.set_status_flag(registers::CARRY_MASK, res >= 0x100);
+    cpu.r.set_status_flag(registers::OVERFLOW_MASK, overflow);
+    cpu.r.a = res as u8;
+}
+
+fn adc<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
     if operand.page_crossing {
-        cpu.cycle += 1;
+        cpu.stall_cycle += 1;
     }
 
     adc_impl(cpu, &operand);
 }
 
-fn anc(cpu: &mut Cpu, addressing_mode: usize) {
+fn anc<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
 
     and_impl(cpu, &operand);
@@ -112,22 +136,22 @@ fn anc(cpu: &mut Cpu, addressing_mode: usize) {
         .set_status_flag(registers::CARRY_MASK, res & 0x80 != 0);
 }
 
-fn and_impl(cpu: &mut Cpu, operand: &Operand) {
+fn and_impl<M: Memory>(cpu: &mut Cpu6502<M>, operand: &Operand) {
     cpu.r.a &= operand.val;
     let res = cpu.r.a;
     cpu.r.update_nz_flags(res);
 }
 
-fn and(cpu: &mut Cpu, addressing_mode: usize) {
+fn and<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
     if operand.page_crossing {
-        cpu.cycle += 1;
+        cpu.stall_cycle += 1;
     }
 
     and_impl(cpu, &operand);
 }
 
-fn arr(cpu: &mut Cpu, addressing_mode: usize) {
+fn arr<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let mut operand = cpu.get_operand(addressing_mode);
 
     and_impl(cpu, &operand);
@@ -148,7 +172,7 @@ fn arr(cpu: &mut Cpu, addressing_mode: usize) {
     cpu.write_operand(&operand);
 }
 
-fn asl_impl(cpu: &mut Cpu, operand: &mut Operand) {
+fn asl_impl<M: Memory>(cpu: &mut Cpu6502<M>, operand: &mut Operand) {
     let res = operand.val << 1;
     cpu.r.update_nz_flags(res);
     cpu.r
@@ -158,26 +182,27 @@ fn asl_impl(cpu: &mut Cpu, operand: &mut Operand) {
     cpu.write_operand(operand);
 }
 
-fn asl(cpu: &mut Cpu, addressing_mode: usize) {
+fn asl<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let mut operand = cpu.get_operand(addressing_mode);
 
     asl_impl(cpu, &mut operand);
 }
 
-fn asr(cpu: &mut Cpu, addressing_mode: usize) {
+fn asr<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
 
     and_impl(cpu, &operand);
     lsr(cpu, addressing_modes::ACCUMULATOR);
 }
 
-fn axa(cpu: &mut Cpu, addressing_mode: usize) {
-    let (addr, _page_crossing) = addressing_modes::FUNCTION_TABLE[addressing_mode](cpu);
+fn axa<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
+    let (addr, page_crossing) = Cpu6502::<M>::FUNCTION_TABLE[addressing_mode](cpu);
+    dummy_read_impl(cpu, addressing_mode, addr, page_crossing);
     let res = cpu.r.a & cpu.r.x & ((addr >> 8) as u8 + 1);
     cpu.write_byte(addr, res);
 }
 
-fn axs(cpu: &mut Cpu, addressing_mode: usize) {
+fn axs<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
     let (res, underflow) = (cpu.r.a & cpu.r.x).overflowing_sub(operand.val);
     cpu.r.x = res;
@@ -185,33 +210,33 @@ fn axs(cpu: &mut Cpu, addressing_mode: usize) {
     cpu.r.update_nz_flags(res);
 }
 
-fn branch_impl(cpu: &mut Cpu, cond: bool, addressing_mode: usize) {
-    let (addr, _page_break) = addressing_modes::FUNCTION_TABLE[addressing_mode](cpu);
+fn branch_impl<M: Memory>(cpu: &mut Cpu6502<M>, cond: bool, addressing_mode: usize) {
+    let (addr, _page_break) = Cpu6502::<M>::FUNCTION_TABLE[addressing_mode](cpu);
     if cond {
-        cpu.cycle += 1;
+        cpu.stall_cycle += 1;
         if cpu.r.pc & 0xFF00 != addr & 0xFF00 {
-            cpu.cycle += 1;
+            cpu.stall_cycle += 1;
         }
         cpu.r.pc = addr;
     }
 }
 
-fn bcc(cpu: &mut Cpu, addressing_mode: usize) {
+fn bcc<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let cond = !cpu.r.get_status_flag(registers::CARRY_MASK);
     branch_impl(cpu, cond, addressing_mode);
 }
 
-fn bcs(cpu: &mut Cpu, addressing_mode: usize) {
+fn bcs<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let cond = cpu.r.get_status_flag(registers::CARRY_MASK);
     branch_impl(cpu, cond, addressing_mode);
 }
 
-fn beq(cpu: &mut Cpu, addressing_mode: usize) {
+fn beq<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let cond = cpu.r.get_status_flag(registers::ZERO_MASK);
     branch_impl(cpu, cond, addressing_mode);
 }
 
-fn bit(cpu: &mut Cpu, addressing_mode: usize) {
+fn bit<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
     cpu.r.set_status_flag(
         registers::NEGATIVE_MASK,
@@ -226,90 +251,90 @@ fn bit(cpu: &mut Cpu, addressing_mode: usize) {
     cpu.r.update_zero_flag(res);
 }
 
-fn bmi(cpu: &mut Cpu, addressing_mode: usize) {
+fn bmi<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let cond = cpu.r.get_status_flag(registers::NEGATIVE_MASK);
     branch_impl(cpu, cond, addressing_mode);
 }
 
-fn bne(cpu: &mut Cpu, addressing_mode: usize) {
+fn bne<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let cond = !cpu.r.get_status_flag(registers::ZERO_MASK);
     branch_impl(cpu, cond, addressing_mode);
 }
 
-fn bpl(cpu: &mut Cpu, addressing_mode: usize) {
+fn bpl<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let cond = !cpu.r.get_status_flag(registers::NEGATIVE_MASK);
     branch_impl(cpu, cond, addressing_mode);
 }
 
-fn brk(cpu: &mut Cpu, _addressing_mode: usize) {
+fn brk<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     cpu.r.pc += 1;
     cpu.handle_interrupt(Interrupt::IRQ as usize);
 }
 
-fn bvc(cpu: &mut Cpu, addressing_mode: usize) {
+fn bvc<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let cond = !cpu.r.get_status_flag(registers::OVERFLOW_MASK);
     branch_impl(cpu, cond, addressing_mode);
 }
 
-fn bvs(cpu: &mut Cpu, addressing_mode: usize) {
+fn bvs<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let cond = cpu.r.get_status_flag(registers::OVERFLOW_MASK);
     branch_impl(cpu, cond, addressing_mode);
 }
 
-fn clc(cpu: &mut Cpu, _addressing_mode: usize) {
+fn clc<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     cpu.r.set_status_flag(registers::CARRY_MASK, false);
 }
 
-fn cld(cpu: &mut Cpu, _addressing_mode: usize) {
+fn cld<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     cpu.r.set_status_flag(registers::DECIMAL_MODE_MASK, false);
 }
 
-fn cli(cpu: &mut Cpu, _addressing_mode: usize) {
+fn cli<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     cpu.r
         .set_status_flag(registers::INTERRUPT_DISABLE_MASK, false);
 }
 
-fn clv(cpu: &mut Cpu, _addressing_mode: usize) {
+fn clv<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     cpu.r.set_status_flag(registers::OVERFLOW_MASK, false);
 }
 
-fn cmp_impl(cpu: &mut Cpu, operand: &Operand) {
+fn cmp_impl<M: Memory>(cpu: &mut Cpu6502<M>, operand: &Operand) {
     let (diff, underflow) = cpu.r.a.overflowing_sub(operand.val);
     cpu.r.set_status_flag(registers::CARRY_MASK, !underflow);
     cpu.r.update_nz_flags(diff);
 }
 
-fn cmp(cpu: &mut Cpu, addressing_mode: usize) {
+fn cmp<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
     if operand.page_crossing {
-        cpu.cycle += 1;
+        cpu.stall_cycle += 1;
     }
 
     cmp_impl(cpu, &operand);
 }
 
-fn cpx(cpu: &mut Cpu, addressing_mode: usize) {
+fn cpx<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
     let (diff, underflow) = cpu.r.x.overflowing_sub(operand.val);
     cpu.r.set_status_flag(registers::CARRY_MASK, !underflow);
     cpu.r.update_nz_flags(diff);
 }
 
-fn cpy(cpu: &mut Cpu, addressing_mode: usize) {
+fn cpy<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
     let (diff, underflow) = cpu.r.y.overflowing_sub(operand.val);
     cpu.r.set_status_flag(registers::CARRY_MASK, !underflow);
     cpu.r.update_nz_flags(diff);
 }
 
-fn dcp(cpu: &mut Cpu, addressing_mode: usize) {
+fn dcp<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let mut operand = cpu.get_operand(addressing_mode);
 
     dec_impl(cpu, &mut operand);
     cmp_impl(cpu, &operand);
 }
 
-fn dec_impl(cpu: &mut Cpu, operand: &mut Operand) {
+fn dec_impl<M: Memory>(cpu: &mut Cpu6502<M>, operand: &mut Operand) {
     let res = operand.val.wrapping_sub(1);
     cpu.r.update_nz_flags(res);
 
@@ -317,44 +342,65 @@ fn dec_impl(cpu: &mut Cpu, operand: &mut Operand) {
     cpu.write_operand(operand);
 }
 
-fn dec(cpu: &mut Cpu, addressing_mode: usize) {
+fn dec<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let mut operand = cpu.get_operand(addressing_mode);
 
     dec_impl(cpu, &mut operand);
 }
 
-fn dex(cpu: &mut Cpu, _addressing_mode: usize) {
+fn dex<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     let res = cpu.r.x.wrapping_sub(1);
     cpu.r.update_nz_flags(res);
     cpu.r.x = res;
 }
 
-fn dey(cpu: &mut Cpu, _addressing_mode: usize) {
+fn dey<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     let res = cpu.r.y.wrapping_sub(1);
     cpu.r.update_nz_flags(res);
     cpu.r.y = res;
 }
 
-fn dop(cpu: &mut Cpu, addressing_mode: usize) {
-    addressing_modes::FUNCTION_TABLE[addressing_mode](cpu);
+fn dop<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
+    Cpu6502::<M>::FUNCTION_TABLE[addressing_mode](cpu);
+}
+
+// Indexed stores read the effective address before the carry from the index is added to the high
+// byte. The read has side effects on registers such as $2007.
+fn dummy_read_impl<M: Memory>(
+    cpu: &mut Cpu6502<M>,
+    addressing_mode: usize,
+    addr: u16,
+    page_crossing: bool,
+) {
+    if let addressing_modes::ABSOLUTE_X
+    | addressing_modes::ABSOLUTE_Y
+    | addressing_modes::INDIRECT_Y = addressing_mode
+    {
+        let addr = if page_crossing {
+            addr.wrapping_sub(0x100)
+        } else {
+            addr
+        };
+        cpu.read_byte(addr);
+    }
 }
 
-fn eor_impl(cpu: &mut Cpu, operand: &Operand) {
+fn eor_impl<M: Memory>(cpu: &mut Cpu6502<M>, operand: &Operand) {
     cpu.r.a ^= operand.val;
     let res = cpu.r.a;
     cpu.r.update_nz_flags(res);
 }
 
-fn eor(cpu: &mut Cpu, addressing_mode: usize) {
+fn eor<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
     if operand.page_crossing {
-        cpu.cycle += 1;
+        cpu.stall_cycle += 1;
     }
 
     eor_impl(cpu, &operand);
 }
 
-fn inc_impl(cpu: &mut Cpu, operand: &mut Operand) {
+fn inc_impl<M: Memory>(cpu: &mut Cpu6502<M>, operand: &mut Operand) {
     let res = operand.val.wrapping_add(1);
     cpu.r.update_nz_flags(res);
 
@@ -362,47 +408,52 @@ fn inc_impl(cpu: &mut Cpu, operand: &mut Operand) {
     cpu.write_operand(operand);
 }
 
-fn inc(cpu: &mut Cpu, addressing_mode: usize) {
+fn inc<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let mut operand = cpu.get_operand(addressing_mode);
 
     inc_impl(cpu, &mut operand);
 }
 
-fn inx(cpu: &mut Cpu, _addressing_mode: usize) {
+fn inx<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     let res = cpu.r.x.wrapping_add(1);
     cpu.r.update_nz_flags(res);
     cpu.r.x = res;
 }
 
-fn iny(cpu: &mut Cpu, _addressing_mode: usize) {
+fn iny<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     let res = cpu.r.y.wrapping_add(1);
     cpu.r.update_nz_flags(res);
     cpu.r.y = res;
 }
 
-fn isc(cpu: &mut Cpu, addressing_mode: usize) {
+fn isc<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let mut operand = cpu.get_operand(addressing_mode);
 
     inc_impl(cpu, &mut operand);
     sbc_impl(cpu, &operand);
 }
 
-fn jmp(cpu: &mut Cpu, addressing_mode: usize) {
-    let (addr, _page_break) = addressing_modes::FUNCTION_TABLE[addressing_mode](cpu);
+fn jmp<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
+    let (addr, _page_break) = Cpu6502::<M>::FUNCTION_TABLE[addressing_mode](cpu);
     cpu.r.pc = addr;
 }
 
-fn jsr(cpu: &mut Cpu, addressing_mode: usize) {
-    let (addr, _page_break) = addressing_modes::FUNCTION_TABLE[addressing_mode](cpu);
+fn jsr<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
+    let (addr, _page_break) = Cpu6502::<M>::FUNCTION_TABLE[addressing_mode](cpu);
     let ret = cpu.r.pc - 1;
     cpu.r.pc = addr;
     cpu.push_word(ret);
 }
 
-fn las(cpu: &mut Cpu, addressing_mode: usize) {
+fn kil<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
+    cpu.r.pc -= 1;
+    cpu.jammed = true;
+}
+
+fn las<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
     if operand.page_crossing {
-        cpu.cycle += 1;
+        cpu.stall_cycle += 1;
     }
 
     let res = operand.val & cpu.r.sp;
@@ -412,55 +463,55 @@ fn las(cpu: &mut Cpu, addressing_mode: usize) {
     cpu.r.update_nz_flags(res);
 }
 
-fn lax(cpu: &mut Cpu, addressing_mode: usize) {
+fn lax<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
     if operand.page_crossing {
-        cpu.cycle += 1;
+        cpu.stall_cycle += 1;
     }
 
     lda_impl(cpu, &operand);
     ldx_impl(cpu, &operand);
 }
 
-fn lda_impl(cpu: &mut Cpu, operand: &Operand) {
+fn lda_impl<M: Memory>(cpu: &mut Cpu6502<M>, operand: &Operand) {
     cpu.r.a = operand.val;
     cpu.r.update_nz_flags(operand.val);
 }
 
-fn lda(cpu: &mut Cpu, addressing_mode: usize) {
+fn lda<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
     if operand.page_crossing {
-        cpu.cycle += 1;
+        cpu.stall_cycle += 1;
     }
 
     lda_impl(cpu, &operand);
 }
 
-fn ldx_impl(cpu: &mut Cpu, operand: &Operand) {
+fn ldx_impl<M: Memory>(cpu: &mut Cpu6502<M>, operand: &Operand) {
     cpu.r.x = operand.val;
     cpu.r.update_nz_flags(operand.val);
 }
 
-fn ldx(cpu: &mut Cpu, addressing_mode: usize) {
+fn ldx<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
     if operand.page_crossing {
-        cpu.cycle += 1;
+        cpu.stall_cycle += 1;
     }
 
     ldx_impl(cpu, &operand);
 }
 
-fn ldy(cpu: &mut Cpu, addressing_mode: usize) {
+fn ldy<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
     if operand.page_crossing {
-        cpu.cycle += 1;
+        cpu.stall_cycle += 1;
     }
 
     cpu.r.y = operand.val;
     cpu.r.update_nz_flags(operand.val);
 }
 
-fn lsr_impl(cpu: &mut Cpu, operand: &mut Operand) {
+fn lsr_impl<M: Memory>(cpu: &mut Cpu6502<M>, operand: &mut Operand) {
     let res = operand.val >> 1;
     cpu.r.update_nz_flags(res);
     cpu.r
@@ -470,58 +521,58 @@ fn lsr_impl(cpu: &mut Cpu, operand: &mut Operand) {
     cpu.write_operand(operand);
 }
 
-fn lsr(cpu: &mut Cpu, addressing_mode: usize) {
+fn lsr<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let mut operand = cpu.get_operand(addressing_mode);
 
     lsr_impl(cpu, &mut operand);
 }
 
-fn nop(_cpu: &mut Cpu, _addressing_mode: usize) {}
+fn nop<M: Memory>(_cpu: &mut Cpu6502<M>, _addressing_mode: usize) {}
 
-fn ora_impl(cpu: &mut Cpu, operand: &Operand) {
+fn ora_impl<M: Memory>(cpu: &mut Cpu6502<M>, operand: &Operand) {
     cpu.r.a |= operand.val;
     let res = cpu.r.a;
     cpu.r.update_nz_flags(res);
 }
 
-fn ora(cpu: &mut Cpu, addressing_mode: usize) {
+fn ora<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
     if operand.page_crossing {
-        cpu.cycle += 1;
+        cpu.stall_cycle += 1;
     }
 
     ora_impl(cpu, &operand);
 }
 
-fn pha(cpu: &mut Cpu, _addressing_mode: usize) {
+fn pha<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     let res = cpu.r.a;
     cpu.push_byte(res);
 }
 
-fn php(cpu: &mut Cpu, _addressing_mode: usize) {
+fn php<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     let res = cpu.r.p | 0x10;
     cpu.push_byte(res);
 }
 
-fn pla(cpu: &mut Cpu, _addressing_mode: usize) {
+fn pla<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     let res = cpu.pop_byte();
     cpu.r.a = res;
     cpu.r.update_nz_flags(res);
 }
 
-fn plp(cpu: &mut Cpu, _addressing_mode: usize) {
+fn plp<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     let res = (cpu.pop_byte() & !0x30) | (cpu.r.p & 0x30);
     cpu.r.p = res;
 }
 
-fn rla(cpu: &mut Cpu, addressing_mode: usize) {
+fn rla<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let mut operand = cpu.get_operand(addressing_mode);
 
     rol_impl(cpu, &mut operand);
     and_impl(cpu, &operand);
 }
 
-fn rol_impl(cpu: &mut Cpu, operand: &mut Operand) {
+fn rol_impl<M: Memory>(cpu: &mut Cpu6502<M>, operand: &mut Operand) {
     let mut res = operand.val << 1;
     res |= if cpu.r.get_status_flag(registers::CARRY_MASK) {
         1
@@ -536,13 +587,13 @@ fn rol_impl(cpu: &mut Cpu, operand: &mut Operand) {
     cpu.write_operand(operand);
 }
 
-fn rol(cpu: &mut Cpu, addressing_mode: usize) {
+fn rol<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let mut operand = cpu.get_operand(addressing_mode);
 
     rol_impl(cpu, &mut operand);
 }
 
-fn ror_impl(cpu: &mut Cpu, operand: &mut Operand) {
+fn ror_impl<M: Memory>(cpu: &mut Cpu6502<M>, operand: &mut Operand) {
     let mut res = operand.val >> 1;
     res |= if cpu.r.get_status_flag(registers::CARRY_MASK) {
         0x80
@@ -557,29 +608,29 @@ fn ror_impl(cpu: &mut Cpu, operand: &mut Operand) {
     cpu.write_operand(operand);
 }
 
-fn ror(cpu: &mut Cpu, addressing_mode: usize) {
+fn ror<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let mut operand = cpu.get_operand(addressing_mode);
 
     ror_impl(cpu, &mut operand);
 }
 
-fn rti(cpu: &mut Cpu, _addressing_mode: usize) {
+fn rti<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     plp(cpu, addressing_modes::IMPLIED);
     cpu.r.pc = cpu.pop_word();
 }
 
-fn rts(cpu: &mut Cpu, _addressing_mode: usize) {
+fn rts<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     cpu.r.pc = cpu.pop_word() + 1;
 }
 
-fn rra(cpu: &mut Cpu, addressing_mode: usize) {
+fn rra<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let mut operand = cpu.get_operand(addressing_mode);
 
     ror_impl(cpu, &mut operand);
     adc_impl(cpu, &operand);
 }
 
-fn sbc_impl(cpu: &mut Cpu, operand: &Operand) {
+fn sbc_impl<M: Memory>(cpu: &mut Cpu6502<M>, operand: &Operand) {
     let carry = if cpu.r.p & registers::CARRY_MASK == 0 {
         1
     } else {
@@ -592,33 +643,54 @@ fn sbc_impl(cpu: &mut Cpu, operand: &Operand) {
     cpu.r
         .set_status_flag(registers::CARRY_MASK, !is_underflow_1 && !is_underflow_2);
     cpu.r.set_status_flag(registers::OVERFLOW_MASK, underflow);
-    cpu.r.a = res;
+    cpu.r.a = if cpu.decimal_mode_enabled() {
+        sbc_decimal_result(cpu.r.a, operand.val, carry)
+    } else {
+        res
+    };
+}
+
+// http://www.6502.org/tutorials/decimal_mode.html#A
+// All flags are identical to binary mode, so only the result needs to be adjusted.
+fn sbc_decimal_result(a: u8, val: u8, borrow: u8) -> u8 {
+    let a = i16::from(a);
+    let val = i16::from(val);
+    let mut lo = (a & 0x0F) - (val & 0x0F) - i16::from(borrow);
+    if lo < 0 {
+        lo = ((lo - 0x06) & 0x0F) - 0x10;
+    }
+    let mut res = (a & 0xF0) - (val & 0xF0) + lo;
+    if res < 0 {
+        res -= 0x60;
+    }
+    res as u8
 }
 
-fn sbc(cpu: &mut Cpu, addressing_mode: usize) {
+fn sbc<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
     if operand.page_crossing {
-        cpu.cycle += 1;
+        cpu.stall_cycle += 1;
     }
 
     sbc_impl(cpu, &operand);
 }
 
-fn sec(cpu: &mut Cpu, _addressing_mode: usize) {
+fn sec<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     cpu.r.set_status_flag(registers::CARRY_MASK, true);
 }
 
-fn sed(cpu: &mut Cpu, _addressing_mode: usize) {
+fn sed<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     cpu.r.set_status_flag(registers::DECIMAL_MODE_MASK, true);
 }
 
-fn sei(cpu: &mut Cpu, _addressing_mode: usize) {
+fn sei<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     cpu.r
         .set_status_flag(registers::INTERRUPT_DISABLE_MASK, true);
 }
 
-fn shx(cpu: &mut Cpu, addressing_mode: usize) {
-    let (addr, page_break) = addressing_modes::FUNCTION_TABLE[addressing_mode](cpu);
+fn shx<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
+    let (addr, page_break) = Cpu6502::<M>::FUNCTION_TABLE[addressing_mode](cpu);
+    dummy_read_impl(cpu, addressing_mode, addr, page_break);
     let res = cpu.r.x & ((addr >> 8) as u8 + 1);
 
     if !page_break {
@@ -626,8 +698,9 @@ fn shx(cpu: &mut Cpu, addressing_mode: usize) {
     }
 }
 
-fn shy(cpu: &mut Cpu, addressing_mode: usize) {
-    let (addr, page_break) = addressing_modes::FUNCTION_TABLE[addressing_mode](cpu);
+fn shy<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
+    let (addr, page_break) = Cpu6502::<M>::FUNCTION_TABLE[addressing_mode](cpu);
+    dummy_read_impl(cpu, addressing_mode, addr, page_break);
     let res = cpu.r.y & ((addr >> 8) as u8 + 1);
 
     if !page_break {
@@ -635,89 +708,91 @@ fn shy(cpu: &mut Cpu, addressing_mode: usize) {
     }
 }
 
-fn slo(cpu: &mut Cpu, addressing_mode: usize) {
+fn slo<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let mut operand = cpu.get_operand(addressing_mode);
 
     asl_impl(cpu, &mut operand);
     ora_impl(cpu, &operand);
 }
 
-fn sta(cpu: &mut Cpu, addressing_mode: usize) {
-    let (addr, _page_break) = addressing_modes::FUNCTION_TABLE[addressing_mode](cpu);
+fn sta<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
+    let (addr, page_break) = Cpu6502::<M>::FUNCTION_TABLE[addressing_mode](cpu);
+    dummy_read_impl(cpu, addressing_mode, addr, page_break);
     let res = cpu.r.a;
     cpu.write_byte(addr, res);
 }
 
-fn stx(cpu: &mut Cpu, addressing_mode: usize) {
-    let (addr, _page_break) = addressing_modes::FUNCTION_TABLE[addressing_mode](cpu);
+fn stx<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
+    let (addr, _page_break) = Cpu6502::<M>::FUNCTION_TABLE[addressing_mode](cpu);
     let res = cpu.r.x;
     cpu.write_byte(addr, res);
 }
 
-fn sty(cpu: &mut Cpu, addressing_mode: usize) {
-    let (addr, _page_break) = addressing_modes::FUNCTION_TABLE[addressing_mode](cpu);
+fn sty<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
+    let (addr, _page_break) = Cpu6502::<M>::FUNCTION_TABLE[addressing_mode](cpu);
     let res = cpu.r.y;
     cpu.write_byte(addr, res);
 }
 
-fn sre(cpu: &mut Cpu, addressing_mode: usize) {
+fn sre<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let mut operand = cpu.get_operand(addressing_mode);
 
     lsr_impl(cpu, &mut operand);
     eor_impl(cpu, &operand);
 }
 
-fn tas(cpu: &mut Cpu, addressing_mode: usize) {
-    let (addr, _page_crossing) = addressing_modes::FUNCTION_TABLE[addressing_mode](cpu);
+fn tas<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
+    let (addr, page_crossing) = Cpu6502::<M>::FUNCTION_TABLE[addressing_mode](cpu);
+    dummy_read_impl(cpu, addressing_mode, addr, page_crossing);
     let mut res = cpu.r.a & cpu.r.x;
     cpu.r.sp = res;
     res &= (addr >> 8) as u8 + 1;
     cpu.write_byte(addr, res);
 }
 
-fn tax(cpu: &mut Cpu, _addressing_mode: usize) {
+fn tax<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     let res = cpu.r.a;
     cpu.r.update_nz_flags(res);
     cpu.r.x = res;
 }
 
-fn tay(cpu: &mut Cpu, _addressing_mode: usize) {
+fn tay<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     let res = cpu.r.a;
     cpu.r.update_nz_flags(res);
     cpu.r.y = res;
 }
 
-fn top(cpu: &mut Cpu, addressing_mode: usize) {
-    let (_addr, page_crossing) = addressing_modes::FUNCTION_TABLE[addressing_mode](cpu);
+fn top<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
+    let (_addr, page_crossing) = Cpu6502::<M>::FUNCTION_TABLE[addressing_mode](cpu);
     if page_crossing {
-        cpu.cycle += 1;
+        cpu.stall_cycle += 1;
     }
 }
 
-fn tsx(cpu: &mut Cpu, _addressing_mode: usize) {
+fn tsx<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     let res = cpu.r.sp;
     cpu.r.update_nz_flags(res);
     cpu.r.x = res;
 }
 
-fn txa(cpu: &mut Cpu, _addressing_mode: usize) {
+fn txa<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     let res = cpu.r.x;
     cpu.r.update_nz_flags(res);
     cpu.r.a = res;
 }
 
-fn txs(cpu: &mut Cpu, _addressing_mode: usize) {
+fn txs<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     let res = cpu.r.x;
     cpu.r.sp = res;
 }
 
-fn tya(cpu: &mut Cpu, _addressing_mode: usize) {
+fn tya<M: Memory>(cpu: &mut Cpu6502<M>, _addressing_mode: usize) {
     let res = cpu.r.y;
     cpu.r.update_nz_flags(res);
     cpu.r.a = res;
 }
 
-fn xaa(cpu: &mut Cpu, addressing_mode: usize) {
+fn xaa<M: Memory>(cpu: &mut Cpu6502<M>, addressing_mode: usize) {
     let operand = cpu.get_operand(addressing_mode);
     let res = cpu.r.x & operand.val;
     cpu.r.a = res;
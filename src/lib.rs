@@ -171,24 +171,38 @@ cfg_if! {
             }
         }
 
-        big_array! { 0x100, 0x800, 0x2000 }
+        big_array! { 0x40, 0x100, 0x800, 0x2000 }
     }
 }
 
 mod apu;
 mod bus;
 mod cartridge;
+mod cdl;
 mod controller;
 mod cpu;
 mod mapper;
 mod ppu;
-
-use crate::apu::Apu;
+mod symbols;
+mod video;
+
+pub use crate::apu::{
+    AudioFormat, AudioRecording, AudioRingBuffer, Channel, FilterProfile, FirstOrderFilter,
+    HighPassFilter, LowPassFilter,
+};
+pub use crate::cpu::{Cpu6502, CpuVariant, Interrupt, Memory};
+pub use crate::ppu::{MirroringMode, PixelFormat, RegisterWrite, ScanlineState, SpriteInfo};
+pub use crate::video::scale::{scale_image, Scaler};
+
+use crate::apu::{Apu, AudioRecorder, RateControl};
 use crate::bus::Bus;
 use crate::cartridge::Cartridge;
+use crate::cdl::CodeDataLogger;
 use crate::cpu::Cpu;
 use crate::mapper::Mapper;
-use crate::ppu::{Ppu, COLORS};
+use crate::ppu::{Overscan, Palette, Ppu};
+use crate::symbols::{Location, SymbolTable};
+use crate::video::{HdPack, NtscFilter, NTSC_HEIGHT, NTSC_WIDTH};
 #[cfg(all(target_arch = "wasm32", console_error_panic_hook))]
 use console_error_panic_hook::set_once;
 #[cfg(target_arch = "wasm32")]
@@ -201,6 +215,13 @@ pub struct Nes {
     cpu: Cpu,
     ppu: Ppu,
     mapper: Option<*mut dyn Mapper>,
+    cdl: CodeDataLogger,
+    symbols: SymbolTable,
+    pixel_format: PixelFormat,
+    image_output_enabled: bool,
+    overscan: Overscan,
+    ntsc: NtscFilter,
+    hd_pack: Option<HdPack>,
 }
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
@@ -211,20 +232,35 @@ impl Nes {
         set_once();
 
         let apu = Apu::new(sample_freq);
-        let cpu = Cpu::new();
+        let cpu = Cpu::default();
         let ppu = Ppu::new();
         let mapper = None;
+        let cdl = CodeDataLogger::default();
+        let symbols = SymbolTable::new();
 
         Nes {
             apu,
             cpu,
             ppu,
             mapper,
+            cdl,
+            symbols,
+            pixel_format: PixelFormat::Rgba8888,
+            image_output_enabled: true,
+            overscan: Overscan::default(),
+            ntsc: NtscFilter::default(),
+            hd_pack: None,
         }
     }
 
     fn attach_bus(&mut self, mapper: *mut dyn Mapper) {
-        let mut bus = Bus::new(&mut self.apu, &mut self.cpu, &mut self.ppu, mapper);
+        let mut bus = Bus::new(
+            &mut self.apu,
+            &mut self.cpu,
+            &mut self.ppu,
+            mapper,
+            &mut self.cdl,
+        );
         self.apu.attach_bus(bus.clone());
         self.cpu.attach_bus(bus.clone());
         self.ppu.attach_bus(bus.clone());
@@ -242,6 +278,15 @@ impl Nes {
         }
 
         let cartridge = Cartridge::from_buffer(buffer);
+        // CHR RAM is not logged since its contents are not part of the ROM.
+        let chr_rom_len = if cartridge.is_chr_ram {
+            0
+        } else {
+            cartridge.chr_rom_len()
+        };
+        let enabled = self.cdl.enabled;
+        self.cdl = CodeDataLogger::new(cartridge.prg_rom_len(), chr_rom_len);
+        self.cdl.enabled = enabled;
         let mapper = Box::into_raw(mapper::from_cartridge(cartridge));
         self.attach_bus(mapper);
         self.apu.initialize();
@@ -266,10 +311,24 @@ impl Nes {
     /// Panics if there is no ROM loaded.
     pub fn step_frame(&mut self) {
         self.apu.buffer_index = 0;
+        self.apu.update_rate_adjustment();
         let frame = self.ppu.frame;
         while self.ppu.frame == frame {
             self.step();
         }
+        if self.image_output_enabled {
+            self.ppu.render_image(self.pixel_format, self.overscan);
+        }
+        if self.ntsc.enabled {
+            self.ntsc.render(
+                &self.ppu.index_buffer,
+                self.ppu.frame_phase,
+                self.pixel_format,
+            );
+        }
+        if let Some(hd_pack) = self.hd_pack.as_mut() {
+            hd_pack.render(&self.ppu, self.cpu.memory(), self.pixel_format);
+        }
     }
 
     /// Resets the emulator.
@@ -281,13 +340,291 @@ impl Nes {
         self.ppu.reset();
     }
 
-    /// Returns a `*const u8` to the image buffer. The image buffer is an array of bytes of size
-    /// 256x240x4. Each pixel is represented by four bytes (ABGR) and the pixels are listed in
-    /// row-major order.
+    /// Returns a `*const u8` to the image buffer. The image buffer contains `image_width()` by
+    /// `image_height()` pixels in the selected pixel format, which defaults to
+    /// `PixelFormat::Rgba8888`, and the pixels are listed in row-major order. The image buffer is
+    /// converted from the index buffer at the end of each frame.
     pub fn image_buffer(&self) -> *const u8 {
         self.ppu.buffer.as_ptr()
     }
 
+    /// Returns the length of the image buffer in bytes, which depends on the pixel format and the
+    /// overscan.
+    pub fn image_buffer_len(&self) -> usize {
+        self.image_width() * self.image_height() * self.pixel_format.bytes_per_pixel()
+    }
+
+    /// Returns the width of the image buffer in pixels, which is 256 minus the pixels cropped from
+    /// the left and right edges.
+    pub fn image_width(&self) -> usize {
+        self.overscan.width()
+    }
+
+    /// Returns the height of the image buffer in pixels, which is 240 minus the pixels cropped
+    /// from the top and bottom edges.
+    pub fn image_height(&self) -> usize {
+        self.overscan.height()
+    }
+
+    /// Crops the given number of pixels from each edge of the image buffer. NTSC TVs typically
+    /// hide about 8 lines at the top and bottom of the frame. The image buffer is cropped at the
+    /// end of the next frame. Defaults to no cropping.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the whole width or height of the frame is cropped.
+    pub fn set_overscan(&mut self, top: usize, bottom: usize, left: usize, right: usize) {
+        if top + bottom >= 240 || left + right >= 256 {
+            panic!("[NES] Overscan crops the whole frame.");
+        }
+        self.overscan = Overscan {
+            top,
+            bottom,
+            left,
+            right,
+        };
+    }
+
+    /// Masks the left 8 pixels of each scanline with the backdrop color regardless of PPUMASK,
+    /// which hides the artifacts that many games leave there. This does not affect sprite 0 hits.
+    /// Disabled by default.
+    pub fn set_left_column_masked(&mut self, masked: bool) {
+        self.ppu.left_column_masked = masked;
+    }
+
+    /// Returns `true` if the left 8 pixels of each scanline are masked regardless of PPUMASK.
+    pub fn left_column_masked(&self) -> bool {
+        self.ppu.left_column_masked
+    }
+
+    /// Removes the limit of 8 sprites per scanline so that every sprite in range of a scanline is
+    /// drawn, which reduces flicker in games that cycle sprites. The sprite overflow flag and
+    /// sprite 0 hits still behave as on hardware. Disabled by default.
+    pub fn set_sprite_limit_removed(&mut self, removed: bool) {
+        self.ppu.sprite_limit_removed = removed;
+    }
+
+    /// Returns `true` if the limit of 8 sprites per scanline is removed.
+    pub fn sprite_limit_removed(&self) -> bool {
+        self.ppu.sprite_limit_removed
+    }
+
+    /// Hides the background regardless of PPUMASK. This does not affect sprite 0 hits. Disabled by
+    /// default.
+    pub fn set_background_hidden(&mut self, hidden: bool) {
+        self.ppu.background_hidden = hidden;
+    }
+
+    /// Returns `true` if the background is hidden regardless of PPUMASK.
+    pub fn background_hidden(&self) -> bool {
+        self.ppu.background_hidden
+    }
+
+    /// Hides sprites regardless of PPUMASK. This does not affect sprite 0 hits. Disabled by
+    /// default.
+    pub fn set_sprites_hidden(&mut self, hidden: bool) {
+        self.ppu.sprites_hidden = hidden;
+    }
+
+    /// Returns `true` if sprites are hidden regardless of PPUMASK.
+    pub fn sprites_hidden(&self) -> bool {
+        self.ppu.sprites_hidden
+    }
+
+    /// Sets the pixel format of the image buffer. The image buffer is converted to the new format
+    /// at the end of the next frame. Defaults to `PixelFormat::Rgba8888`.
+    pub fn set_pixel_format(&mut self, format: PixelFormat) {
+        self.pixel_format = format;
+    }
+
+    /// Returns the pixel format of the image buffer.
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
+    /// Returns a `*const u16` to the index buffer. The index buffer is an array of 256x240 values
+    /// in row-major order. Each value is the 6 bit palette index of the pixel in the low bits and
+    /// the 3 color emphasis bits of PPUMASK in the high bits, which is an index into the colors
+    /// array.
+    pub fn index_buffer(&self) -> *const u16 {
+        self.ppu.index_buffer.as_ptr()
+    }
+
+    /// Enables or disables converting the index buffer to the image buffer at the end of each
+    /// frame. This can be disabled when only the index buffer is used. Enabled by default.
+    pub fn set_image_output_enabled(&mut self, enabled: bool) {
+        self.image_output_enabled = enabled;
+    }
+
+    /// Returns `true` if the image buffer is updated at the end of each frame.
+    pub fn image_output_enabled(&self) -> bool {
+        self.image_output_enabled
+    }
+
+    /// Enables or disables the NTSC filter, which simulates the composite video signal of the PPU
+    /// to reproduce the artifact colors and dot crawl of a TV. When enabled, the index buffer is
+    /// filtered to the NTSC image buffer at the end of each frame. Disabled by default.
+    pub fn set_ntsc_filter_enabled(&mut self, enabled: bool) {
+        self.ntsc.enabled = enabled;
+    }
+
+    /// Returns `true` if the NTSC filter is enabled.
+    pub fn ntsc_filter_enabled(&self) -> bool {
+        self.ntsc.enabled
+    }
+
+    /// Returns a `*const u8` to the NTSC image buffer. The NTSC image buffer contains
+    /// `ntsc_image_width()` by 240 pixels in the selected pixel format, and the pixels are listed
+    /// in row-major order.
+    pub fn ntsc_image_buffer(&self) -> *const u8 {
+        self.ntsc.buffer.as_ptr()
+    }
+
+    /// Returns the length of the NTSC image buffer in bytes, which depends on the pixel format.
+    pub fn ntsc_image_buffer_len(&self) -> usize {
+        NTSC_WIDTH * NTSC_HEIGHT * self.pixel_format.bytes_per_pixel()
+    }
+
+    /// Returns the width of the NTSC image in pixels, which is about 2.35 pixels for each dot.
+    pub fn ntsc_image_width(&self) -> usize {
+        NTSC_WIDTH
+    }
+
+    /// Shifts the hue of the NTSC filter by `hue` degrees. Defaults to 0.
+    pub fn set_ntsc_hue(&mut self, hue: f32) {
+        self.ntsc.set_hue(hue);
+    }
+
+    /// Returns the hue shift of the NTSC filter in degrees.
+    pub fn ntsc_hue(&self) -> f32 {
+        self.ntsc.hue()
+    }
+
+    /// Scales the saturation of the NTSC filter, where 0 is greyscale. Defaults to 1.
+    pub fn set_ntsc_saturation(&mut self, saturation: f32) {
+        self.ntsc.set_saturation(saturation);
+    }
+
+    /// Returns the saturation of the NTSC filter.
+    pub fn ntsc_saturation(&self) -> f32 {
+        self.ntsc.saturation()
+    }
+
+    /// Sets the sharpness of the NTSC filter in the range [0, 1]. At 0, luma is blurred as much as
+    /// chroma. Defaults to 0.
+    pub fn set_ntsc_sharpness(&mut self, sharpness: f32) {
+        self.ntsc.set_sharpness(sharpness);
+    }
+
+    /// Returns the sharpness of the NTSC filter.
+    pub fn ntsc_sharpness(&self) -> f32 {
+        self.ntsc.sharpness()
+    }
+
+    /// Enables or disables merging fields in the NTSC filter. When enabled, each frame is blended
+    /// with the other phase of the color subcarrier, which removes dot crawl. Disabled by
+    /// default.
+    pub fn set_ntsc_merge_fields(&mut self, merge_fields: bool) {
+        self.ntsc.merge_fields = merge_fields;
+    }
+
+    /// Returns `true` if the NTSC filter merges fields.
+    pub fn ntsc_merge_fields(&self) -> bool {
+        self.ntsc.merge_fields
+    }
+
+    /// Loads the rules of an HD pack in the Mesen format from the contents of its `hires.txt`,
+    /// replacing the current HD pack. Tiles are replaced by rules that match their CHR ROM tile
+    /// index or CHR data and their palette, optionally guarded by conditions, and background
+    /// images are drawn behind the tiles. Lines that are malformed or not supported are skipped.
+    /// Returns the number of tile rules loaded.
+    ///
+    /// The images of the pack are loaded separately with `load_hd_pack_image`. When an HD pack is
+    /// loaded, each frame is rendered to the HD image buffer at the scale of the pack.
+    pub fn load_hd_pack(&mut self, contents: &str) -> usize {
+        let hd_pack = HdPack::parse(contents);
+        let rule_count = hd_pack.rule_count();
+        self.hd_pack = Some(hd_pack);
+        self.ppu.pixel_sources.set_enabled(true);
+        rule_count
+    }
+
+    /// Removes the current HD pack.
+    pub fn clear_hd_pack(&mut self) {
+        self.hd_pack = None;
+        self.ppu.pixel_sources.set_enabled(false);
+    }
+
+    /// Returns `true` if an HD pack is loaded.
+    pub fn hd_pack_loaded(&self) -> bool {
+        self.hd_pack.is_some()
+    }
+
+    /// Returns the number of images that the HD pack refers to, or `0` if no HD pack is loaded.
+    pub fn hd_pack_image_count(&self) -> usize {
+        self.hd_pack.as_ref().map_or(0, HdPack::image_count)
+    }
+
+    /// Returns the file name of image `index` of the HD pack, relative to the directory of
+    /// `hires.txt`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no HD pack is loaded or if `index` is not less than `hd_pack_image_count()`.
+    pub fn hd_pack_image_name(&self, index: usize) -> String {
+        self.hd_pack().image_name(index).to_owned()
+    }
+
+    /// Loads image `index` of the HD pack from `width` by `height` pixels in the RGBA8888 format
+    /// in row-major order. Rules that refer to images that are not loaded are ignored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no HD pack is loaded, if `index` is not less than `hd_pack_image_count()`, or if
+    /// `pixels` is not `width * height * 4` bytes.
+    pub fn load_hd_pack_image(&mut self, index: usize, width: usize, height: usize, pixels: &[u8]) {
+        self.hd_pack_mut().load_image(index, width, height, pixels);
+    }
+
+    /// Returns a `*const u8` to the HD image buffer. The HD image buffer contains
+    /// `hd_image_width()` by `hd_image_height()` pixels in the selected pixel format, and the
+    /// pixels are listed in row-major order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no HD pack is loaded.
+    pub fn hd_image_buffer(&self) -> *const u8 {
+        self.hd_pack().buffer.as_ptr()
+    }
+
+    /// Returns the length of the HD image buffer in bytes, which depends on the pixel format.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no HD pack is loaded.
+    pub fn hd_image_buffer_len(&self) -> usize {
+        self.hd_image_width() * self.hd_image_height() * self.pixel_format.bytes_per_pixel()
+    }
+
+    /// Returns the width of the HD image in pixels, which is 256 times the scale of the HD pack.
+    /// The scale is from 1 to 10.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no HD pack is loaded.
+    pub fn hd_image_width(&self) -> usize {
+        self.hd_pack().width()
+    }
+
+    /// Returns the height of the HD image in pixels, which is 240 times the scale of the HD pack.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no HD pack is loaded.
+    pub fn hd_image_height(&self) -> usize {
+        self.hd_pack().height()
+    }
+
     /// Returns a `*const f32` to the audio buffer. The audio buffer contains samples for one frame.
     /// Note that the samples is down-sampled to `sample_freq`.
     pub fn audio_buffer(&self) -> *const f32 {
@@ -299,9 +636,205 @@ impl Nes {
         self.apu.buffer_index
     }
 
+    /// Mutes or unmutes `channel` in the audio buffer. Channels are enabled by default.
+    pub fn set_channel_enabled(&mut self, channel: Channel, enabled: bool) {
+        self.apu.channel_settings[channel.index()].enabled = enabled;
+    }
+
+    /// Returns `true` if `channel` is not muted in the audio buffer.
+    pub fn channel_enabled(&self, channel: Channel) -> bool {
+        self.apu.channel_settings[channel.index()].enabled
+    }
+
+    /// Sets the volume of `channel` in the audio buffer. The output of the channel is multiplied
+    /// by `volume` before it is mixed with the other channels. Defaults to `1.0`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `volume` is negative or not finite.
+    pub fn set_channel_volume(&mut self, channel: Channel, volume: f32) {
+        if !volume.is_finite() || volume < 0.0 {
+            panic!("[NES] Invalid channel volume {}.", volume);
+        }
+        self.apu.channel_settings[channel.index()].volume = volume;
+    }
+
+    /// Returns the volume of `channel` in the audio buffer.
+    pub fn channel_volume(&self, channel: Channel) -> f32 {
+        self.apu.channel_settings[channel.index()].volume
+    }
+
+    /// Enables a buffer for each channel that contains the channel mixed without the other
+    /// channels. The channel buffers have the same length as the audio buffer and are not
+    /// affected by the channel enabled and volume settings. Disabled by default.
+    pub fn set_channel_buffers_enabled(&mut self, enabled: bool) {
+        self.apu.channel_buffers_enabled = enabled;
+    }
+
+    /// Returns `true` if the channel buffers are enabled.
+    pub fn channel_buffers_enabled(&self) -> bool {
+        self.apu.channel_buffers_enabled
+    }
+
+    /// Returns a `*const f32` to the buffer of `channel`. The length of the buffer is
+    /// `audio_buffer_len`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the channel buffers are not enabled or no frame has been run since they were
+    /// enabled.
+    pub fn channel_buffer(&self, channel: Channel) -> *const f32 {
+        self.apu
+            .channel_buffer(channel)
+            .expect("[NES] Channel buffers are not enabled.")
+            .as_ptr()
+    }
+
+    /// Enables the stereo audio buffer, which contains the left and right samples of each sample
+    /// of the audio buffer with the channels panned by `set_channel_pan`. The audio buffer is still
+    /// filled with the mono mix. Disabled by default.
+    pub fn set_stereo_enabled(&mut self, enabled: bool) {
+        self.apu.stereo_enabled = enabled;
+    }
+
+    /// Returns `true` if the stereo audio buffer is enabled.
+    pub fn stereo_enabled(&self) -> bool {
+        self.apu.stereo_enabled
+    }
+
+    /// Sets the stereo position of `channel` from `-1.0` for left to `1.0` for right. A centered
+    /// channel is at full volume on both sides and a channel panned to one side fades out of the
+    /// other side. Defaults to `0.0`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pan` is not between `-1.0` and `1.0`.
+    pub fn set_channel_pan(&mut self, channel: Channel, pan: f32) {
+        if !(-1.0..=1.0).contains(&pan) {
+            panic!("[NES] Invalid channel pan {}.", pan);
+        }
+        self.apu.channel_settings[channel.index()].pan = pan;
+    }
+
+    /// Returns the stereo position of `channel`.
+    pub fn channel_pan(&self, channel: Channel) -> f32 {
+        self.apu.channel_settings[channel.index()].pan
+    }
+
+    /// Returns a `*const f32` to the stereo audio buffer. The left and right samples are
+    /// interleaved and the length of the buffer is twice `audio_buffer_len`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stereo audio buffer is not enabled.
+    pub fn stereo_audio_buffer(&self) -> *const f32 {
+        if !self.apu.stereo_enabled {
+            panic!("[NES] Stereo output is not enabled.");
+        }
+        self.apu.stereo_buffer.as_ptr()
+    }
+
+    /// Writes each sample to `ring_buffer` as it is produced, in addition to the audio buffer. The
+    /// interleaved left and right samples are written when the stereo audio buffer is enabled.
+    /// Samples are dropped when the ring buffer is full.
+    pub fn attach_audio_ring_buffer(&mut self, ring_buffer: &AudioRingBuffer) {
+        self.apu.ring_buffer = Some(ring_buffer.clone());
+    }
+
+    /// Stops writing samples to the attached ring buffer.
+    pub fn detach_audio_ring_buffer(&mut self) {
+        self.apu.ring_buffer = None;
+    }
+
+    /// Enables dynamic rate control, which adjusts the sample rate by up to `max_adjustment` to
+    /// keep the attached ring buffer at `target_latency` samples of each side. This keeps audio in
+    /// sync with video without underruns when the emulator is timed by video. The adjustment is
+    /// updated at the start of each frame. A `max_adjustment` of `0.005` is usually inaudible.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `target_latency` is `0` or `max_adjustment` is not between `0.0` and `0.1`.
+    pub fn set_dynamic_rate_control(&mut self, target_latency: usize, max_adjustment: f32) {
+        if target_latency == 0 || !(0.0..=0.1).contains(&max_adjustment) {
+            panic!(
+                "[NES] Invalid target latency {} or maximum adjustment {}.",
+                target_latency, max_adjustment,
+            );
+        }
+        self.apu.rate_control = Some(RateControl {
+            target_latency,
+            max_adjustment: f64::from(max_adjustment),
+        });
+    }
+
+    /// Disables dynamic rate control and restores the sample rate.
+    pub fn disable_dynamic_rate_control(&mut self) {
+        self.apu.rate_control = None;
+        self.apu.set_rate_adjustment(1.0);
+    }
+
+    /// Returns the factor that dynamic rate control multiplies the sample rate by.
+    pub fn rate_adjustment(&self) -> f32 {
+        self.apu.rate_adjustment as f32
+    }
+
+    /// Sets the filters that are applied to the audio buffer, the stereo audio buffer, and the
+    /// channel buffers. Defaults to `FilterProfile::Nes`. `FilterProfile::Custom` has no filters
+    /// unless they are set by `set_custom_filters`.
+    pub fn set_filter_profile(&mut self, profile: FilterProfile) {
+        self.apu.filter_profile = profile;
+        self.apu.rebuild_filters();
+    }
+
+    /// Returns the filter profile.
+    pub fn filter_profile(&self) -> FilterProfile {
+        self.apu.filter_profile
+    }
+
+    /// Starts recording the audio buffer in `format` at the current sample rate, which should not
+    /// be changed until the recording is stopped. Dynamic rate control is paused during the
+    /// recording, so the sample rate is not adjusted. With `multi_track`, each channel mixed
+    /// without the other channels is also recorded to its own track. A recording that is already
+    /// in progress is discarded.
+    pub fn start_audio_recording(&mut self, format: AudioFormat, multi_track: bool) {
+        let sample_freq = self.apu.sample_freq();
+        self.apu.recorder = Some(AudioRecorder::new(format, sample_freq, multi_track));
+        self.apu.set_rate_adjustment(1.0);
+    }
+
+    /// Returns `true` if audio is being recorded.
+    pub fn is_recording_audio(&self) -> bool {
+        self.apu.recorder.is_some()
+    }
+
+    /// Stops recording audio and returns the recording, or `None` if audio is not being recorded.
+    pub fn stop_audio_recording(&mut self) -> Option<AudioRecording> {
+        self.apu.recorder.take().map(AudioRecorder::finish)
+    }
+
     /// Returns a `*const u32` to the colors used by the emulator. The colors are formatted as RGB.
+    /// There are 512 colors, where the index of a color is the palette index in the low 6 bits
+    /// and the color emphasis bits of PPUMASK in the high 3 bits.
     pub fn colors(&self) -> *const u32 {
-        COLORS.as_ptr()
+        self.ppu.palette.as_ptr()
+    }
+
+    /// Loads a `.pal` palette file, which is a list of RGB triples with either 64 or 512 entries.
+    /// The emphasized colors of a 64 entry palette are generated from the base colors. Returns
+    /// `false` and leaves the current palette unchanged if the palette has a different size.
+    pub fn load_palette(&mut self, pal_data: &[u8]) -> bool {
+        match Palette::from_bytes(pal_data) {
+            Some(palette) => {
+                self.ppu.palette = palette;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Restores the default palette.
+    pub fn reset_palette(&mut self) {
+        self.ppu.palette = Palette::default();
     }
 
     /// Returns a `*const u8` to the palettes used by the emulator. Each value represents an index
@@ -336,6 +869,98 @@ impl Nes {
         self.ppu.primary_oam.as_ptr()
     }
 
+    /// Returns an image of pattern table `table`, which is either `0` or `1`, as a 16x16 grid of
+    /// tiles colored with palette `palette`. Palettes `0` to `3` are the background palettes and
+    /// `4` to `7` are the sprite palettes. The image is 128x128 pixels in the RGBA8888 format.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no ROM loaded or if `table` or `palette` is out of range.
+    pub fn pattern_table_image(&self, table: usize, palette: usize) -> Vec<u8> {
+        self.ppu.render_pattern_table(table, palette)
+    }
+
+    /// Returns an image of the four nametables as they are laid out in PPU memory, drawn with the
+    /// background pattern table and attribute tables. When `show_scroll` is `true`, the outline of
+    /// the 256x240 window that the next frame is scrolled to is highlighted. The image is 512x480
+    /// pixels in the RGBA8888 format.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no ROM loaded.
+    pub fn nametable_image(&self, show_scroll: bool) -> Vec<u8> {
+        self.ppu.render_nametables(show_scroll)
+    }
+
+    /// Returns an image of the 64 sprites in OAM as an 8x8 grid of 8x16 cells in OAM order, drawn
+    /// with their palettes and flips. 8x8 sprites fill the top half of their cell and transparent
+    /// pixels have an alpha of `0`. The image is 64x128 pixels in the RGBA8888 format.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no ROM loaded.
+    pub fn sprite_sheet_image(&self) -> Vec<u8> {
+        self.ppu.render_sprites()
+    }
+
+    /// Returns the position, tile, palette, priority, and flips of sprite `index` in OAM.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than `64`.
+    pub fn sprite_info(&self, index: usize) -> SpriteInfo {
+        self.ppu.sprite_info(index)
+    }
+
+    /// Returns an image of the 32 entries of palette RAM as 16x16 swatches. The top row is the
+    /// background palettes and the bottom row is the sprite palettes. The image is 256x32 pixels in
+    /// the RGBA8888 format.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no ROM loaded.
+    pub fn palette_image(&self) -> Vec<u8> {
+        self.ppu.render_palettes()
+    }
+
+    /// Sets if the state of the PPU at the start of each scanline and the CPU writes to the PPU
+    /// registers are logged. The log of a frame can be queried after `step_frame`.
+    pub fn set_event_log_enabled(&mut self, enabled: bool) {
+        self.ppu.event_log.clear();
+        self.ppu.event_log.enabled = enabled;
+    }
+
+    /// Returns `true` if the PPU event log is enabled.
+    pub fn event_log_enabled(&self) -> bool {
+        self.ppu.event_log.enabled
+    }
+
+    /// Returns the scroll registers, PPUCTRL, PPUMASK, CHR banks, and mirroring mode at the start
+    /// of `scanline` in the last frame. Scanline `261` is the pre-render scanline. Scanlines that
+    /// were not logged return the default state.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `scanline` is not less than `262`.
+    pub fn scanline_state(&self, scanline: usize) -> ScanlineState {
+        self.ppu.event_log.scanline_state(scanline)
+    }
+
+    /// Returns the number of CPU writes to the PPU registers in the last frame.
+    pub fn register_write_count(&self) -> usize {
+        self.ppu.event_log.register_writes().len()
+    }
+
+    /// Returns write `index` to the PPU registers in the last frame, in the order that the writes
+    /// happened.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than `register_write_count()`.
+    pub fn register_write(&self, index: usize) -> RegisterWrite {
+        self.ppu.event_log.register_writes()[index]
+    }
+
     /// Returns `true` is tall sprites are enabled.
     pub fn tall_sprites_enabled(&self) -> bool {
         self.ppu.r.sprite_size.1 == 16
@@ -353,55 +978,173 @@ impl Nes {
 
     /// Presses the button at `button_index` for the controller at `controller_index`.
     pub fn press_button(&mut self, controller_index: usize, button_index: u8) {
-        self.cpu.controllers[controller_index].press_button(button_index);
+        self.cpu.memory.controllers[controller_index].press_button(button_index);
     }
 
     /// Releases the button at `button_index` for the controller at `controller_index`.
     pub fn release_button(&mut self, controller_index: usize, button_index: u8) {
-        self.cpu.controllers[controller_index].release_button(button_index);
+        self.cpu.memory.controllers[controller_index].release_button(button_index);
     }
 
     /// Sets the sample frequency of the audio processing unit (APU).
     pub fn set_sample_freq(&mut self, sample_freq: f32) {
         self.apu.set_sample_freq(sample_freq);
     }
-}
 
-#[cfg(not(target_arch = "wasm32"))]
-impl Nes {
-    /// Saves the battery backed data of the emulator as a buffer of bytes. It is possible that
-    /// the emulator has no battery backed data. In this case, `None` is returned.
-    ///
-    /// # Panics
-    ///
-    /// Panics if there is no ROM loaded.
-    pub fn save(&self) -> bincode::Result<Option<Vec<u8>>> {
-        let mapper = unsafe { &mut (*self.mapper.expect("[NES] No ROM loaded.")) };
-        mapper.save()
+    /// Sets the variant of the 6502 that is emulated. Defaults to `CpuVariant::Ricoh2A03`.
+    pub fn set_cpu_variant(&mut self, variant: CpuVariant) {
+        self.cpu.set_variant(variant);
     }
 
-    /// Loads the battery backed data of the emulator.
-    ///
-    /// # Panics
-    ///
-    /// Panics if there is no ROM loaded.
-    pub fn load(&mut self, save_data: &[u8]) -> bincode::Result<()> {
-        let mapper = unsafe { &mut (*self.mapper.expect("[NES] No ROM loaded.")) };
-        mapper.load(save_data)
+    /// Returns `true` if the CPU has executed a `KIL` opcode and stopped fetching instructions.
+    /// The CPU stays jammed until the emulator is reset.
+    pub fn is_jammed(&self) -> bool {
+        self.cpu.is_jammed()
     }
 
-    /// Saves the state of the emulator as a buffer of bytes.
-    ///
-    /// # Panics
-    ///
-    /// Panics if there is no ROM loaded.
-    pub fn save_state(&self) -> bincode::Result<Vec<u8>> {
-        let mapper = unsafe { &mut (*self.mapper.expect("[NES] No ROM loaded.")) };
-        let (mapper_data, save_data) = mapper.save_state()?;
-        bincode::serialize(&(&self.apu, &self.cpu, &self.ppu, mapper_data, save_data))
+    /// Returns the address of the `KIL` opcode that jammed the CPU, or `None` if the CPU is not
+    /// jammed.
+    pub fn jammed_address(&self) -> Option<u16> {
+        self.cpu.jammed_address()
     }
 
-    /// Loads a state of the emulator.
+    /// Enables or disables the code/data logger. When enabled, every byte of PRG ROM that is
+    /// executed or read and every byte of CHR ROM that is rendered or read through `$2007` is
+    /// logged. Disabled by default.
+    pub fn set_cdl_enabled(&mut self, enabled: bool) {
+        self.cdl.enabled = enabled;
+    }
+
+    /// Returns `true` if the code/data logger is enabled.
+    pub fn cdl_enabled(&self) -> bool {
+        self.cdl.enabled
+    }
+
+    /// Clears the code/data log.
+    pub fn clear_cdl(&mut self) {
+        self.cdl.clear();
+    }
+
+    /// Returns the code/data log in the FCEUX `.cdl` format, which is one byte of flags for each
+    /// byte of PRG ROM followed by one byte of flags for each byte of CHR ROM.
+    pub fn cdl_data(&self) -> Vec<u8> {
+        self.cdl.to_bytes()
+    }
+
+    /// Loads a code/data log in the FCEUX `.cdl` format so that logging continues from it.
+    /// Returns `false` and leaves the current log unchanged if the size of the log does not match
+    /// the loaded ROM.
+    pub fn load_cdl(&mut self, cdl_data: &[u8]) -> bool {
+        self.cdl.load_bytes(cdl_data)
+    }
+
+    /// Loads the labels of a ca65/ld65 debug information file (`.dbg`). Returns the number of
+    /// labels loaded.
+    pub fn load_dbg_symbols(&mut self, contents: &str) -> usize {
+        self.symbols.load_dbg(contents)
+    }
+
+    /// Loads an FCEUX name list (`.nl`). `bank` is the 16K PRG ROM bank that the name list
+    /// describes (`game.nes.0.nl` is bank `0`), or `None` for the RAM name list
+    /// (`game.nes.ram.nl`). Returns the number of labels loaded.
+    pub fn load_nl_symbols(&mut self, contents: &str, bank: Option<usize>) -> usize {
+        self.symbols.load_nl(contents, bank)
+    }
+
+    /// Removes all loaded labels.
+    pub fn clear_symbols(&mut self) {
+        self.symbols.clear();
+    }
+
+    /// Returns the label at the CPU address `addr`. Addresses in PRG ROM are resolved through the
+    /// current bank mapping of the mapper.
+    pub fn symbol_at(&self, addr: u16) -> Option<String> {
+        let addr = if addr < 0x2000 { addr % 0x0800 } else { addr };
+        self.mapper()
+            .and_then(|mapper| mapper.prg_rom_address(addr))
+            .and_then(|prg_rom_addr| self.symbols.label(Location::PrgRom(prg_rom_addr)))
+            .or_else(|| self.symbols.label(Location::Cpu(addr)))
+            .map(str::to_owned)
+    }
+
+    /// Returns the CPU address of `label`. Labels in PRG ROM are resolved through the current
+    /// bank mapping of the mapper, so `None` is returned if the bank of the label is not mapped.
+    pub fn symbol_address(&self, label: &str) -> Option<u16> {
+        match self.symbols.location(label)? {
+            Location::Cpu(addr) => Some(addr),
+            Location::PrgRom(prg_rom_addr) => {
+                let mapper = self.mapper()?;
+                [0x8000, 0xA000, 0xC000, 0xE000].iter().find_map(|&window| {
+                    let start = mapper.prg_rom_address(window)?;
+                    if start <= prg_rom_addr && prg_rom_addr < start + 0x2000 {
+                        Some(window + (prg_rom_addr - start) as u16)
+                    } else {
+                        None
+                    }
+                })
+            }
+        }
+    }
+
+    fn mapper(&self) -> Option<&dyn Mapper> {
+        self.mapper.map(|mapper| unsafe { &*mapper })
+    }
+
+    fn hd_pack(&self) -> &HdPack {
+        self.hd_pack.as_ref().expect("[NES] No HD pack loaded.")
+    }
+
+    fn hd_pack_mut(&mut self) -> &mut HdPack {
+        self.hd_pack.as_mut().expect("[NES] No HD pack loaded.")
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Nes {
+    /// Saves the battery backed data of the emulator as a buffer of bytes. It is possible that
+    /// the emulator has no battery backed data. In this case, `None` is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no ROM loaded.
+    pub fn save(&self) -> bincode::Result<Option<Vec<u8>>> {
+        let mapper = unsafe { &mut (*self.mapper.expect("[NES] No ROM loaded.")) };
+        mapper.save()
+    }
+
+    /// Loads the battery backed data of the emulator.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no ROM loaded.
+    pub fn load(&mut self, save_data: &[u8]) -> bincode::Result<()> {
+        let mapper = unsafe { &mut (*self.mapper.expect("[NES] No ROM loaded.")) };
+        mapper.load(save_data)
+    }
+
+    /// Saves the state of the emulator as a buffer of bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no ROM loaded.
+    pub fn save_state(&self) -> bincode::Result<Vec<u8>> {
+        let mapper = unsafe { &mut (*self.mapper.expect("[NES] No ROM loaded.")) };
+        let (mapper_data, save_data) = mapper.save_state()?;
+        bincode::serialize(&(&self.apu, &self.cpu, &self.ppu, mapper_data, save_data))
+    }
+
+    /// Replaces the audio filters with the filters returned by `factory`, which is called with
+    /// the sample rate whenever the filters are built. This sets the filter profile to
+    /// `FilterProfile::Custom`. The state of the filters is stored in save states.
+    pub fn set_custom_filters<F>(&mut self, factory: F)
+    where
+        F: Fn(f32) -> Vec<Box<dyn FirstOrderFilter>> + 'static,
+    {
+        self.apu.filter_factory = Some(std::rc::Rc::new(factory));
+        self.set_filter_profile(FilterProfile::Custom);
+    }
+
+    /// Loads a state of the emulator.
     ///
     /// # Panics
     ///
@@ -410,8 +1153,46 @@ impl Nes {
         let (apu, cpu, ppu, mapper_data, save_data): (Apu, Cpu, Ppu, Vec<u8>, Vec<u8>) =
             bincode::deserialize(save_state_data)?;
         self.cpu = cpu;
+        // The sample rate and the audio options are settings of the emulator rather than part of
+        // its state.
+        let sample_freq = self.apu.sample_freq();
+        let filter_profile = self.apu.filter_profile;
+        let filter_factory = self.apu.filter_factory.take();
+        let channel_settings = self.apu.channel_settings;
+        let channel_buffers_enabled = self.apu.channel_buffers_enabled;
+        let stereo_enabled = self.apu.stereo_enabled;
+        let ring_buffer = self.apu.ring_buffer.take();
+        let rate_control = self.apu.rate_control;
+        let rate_adjustment = self.apu.rate_adjustment;
+        let recorder = self.apu.recorder.take();
         self.apu = apu;
+        self.apu.filter_profile = filter_profile;
+        self.apu.filter_factory = filter_factory;
+        self.apu.set_sample_freq(sample_freq);
+        self.apu.channel_settings = channel_settings;
+        self.apu.channel_buffers_enabled = channel_buffers_enabled;
+        self.apu.stereo_enabled = stereo_enabled;
+        self.apu.ring_buffer = ring_buffer;
+        self.apu.rate_control = rate_control;
+        self.apu.set_rate_adjustment(rate_adjustment);
+        self.apu.recorder = recorder;
+        // The palette and the rendering options are settings of the emulator rather than part of
+        // its state.
+        let palette = std::mem::take(&mut self.ppu.palette);
+        let left_column_masked = self.ppu.left_column_masked;
+        let sprite_limit_removed = self.ppu.sprite_limit_removed;
+        let background_hidden = self.ppu.background_hidden;
+        let sprites_hidden = self.ppu.sprites_hidden;
+        let event_log_enabled = self.ppu.event_log.enabled;
+        let pixel_sources = std::mem::take(&mut self.ppu.pixel_sources);
         self.ppu = ppu;
+        self.ppu.palette = palette;
+        self.ppu.left_column_masked = left_column_masked;
+        self.ppu.sprite_limit_removed = sprite_limit_removed;
+        self.ppu.background_hidden = background_hidden;
+        self.ppu.sprites_hidden = sprites_hidden;
+        self.ppu.event_log.enabled = event_log_enabled;
+        self.ppu.pixel_sources = pixel_sources;
         let mapper = unsafe { &mut (*self.mapper.expect("[NES] No ROM loaded.")) };
         mapper.load_state(&mapper_data, &save_data)?;
         self.attach_bus(mapper);
@@ -439,6 +1220,21 @@ impl Drop for Nes {
 mod tests {
     use crate::Nes;
 
+    // The number of frames between a test requesting a reset and the reset.
+    const RESET_DELAY_FRAMES: usize = 6;
+
+    // Loads the ROM at `path` and runs it for `frames` frames. The emulator is boxed before the ROM
+    // is loaded since its components hold pointers to each other and must not move afterwards.
+    fn run_rom(path: &str, frames: usize) -> Box<Nes> {
+        let buffer = std::fs::read(path).expect("Expected test rom to exist.");
+        let mut nes = Box::new(Nes::default());
+        nes.load_rom(&buffer);
+        for _ in 0..frames {
+            nes.step_frame();
+        }
+        nes
+    }
+
     fn run_text_test(nes: &mut Nes) {
         // Run until test status is running by polling $6000.
         let mut addr = 0x6000;
@@ -448,10 +1244,22 @@ mod tests {
             byte = nes.cpu.read_byte(addr);
         }
 
-        // Run until test status is finished by polling $6000.
+        // Run until test status is finished by polling $6000. Tests that need another reset
+        // set the status to $81 and are reset after a delay.
         byte = nes.cpu.read_byte(addr);
-        while byte == 0x80 {
-            nes.step_frame();
+        while byte == 0x80 || byte == 0x81 {
+            if byte == 0x81 {
+                for _ in 0..RESET_DELAY_FRAMES {
+                    nes.step_frame();
+                }
+                nes.reset();
+                // The status stays $81 until the test starts running again.
+                while nes.cpu.read_byte(addr) == 0x81 {
+                    nes.step_frame();
+                }
+            } else {
+                nes.step_frame();
+            }
             byte = nes.cpu.read_byte(addr);
         }
 
@@ -468,6 +1276,17 @@ mod tests {
         assert!(String::from_utf8_lossy(&output).contains("Passed"));
     }
 
+    // Builds an NROM image that starts executing `program` at $8000.
+    fn program_rom(program: &[u8]) -> Vec<u8> {
+        let mut buffer = vec![0; 0x10 + 0x4000 + 0x2000];
+        buffer[0..4].copy_from_slice(b"NES\x1A");
+        buffer[4] = 1;
+        buffer[5] = 1;
+        buffer[0x10..0x10 + program.len()].copy_from_slice(program);
+        buffer[0x10 + 0x3FFD] = 0x80;
+        buffer
+    }
+
     // Test output is at $6004.
     macro_rules! text_tests {
         ($($test_name:ident: $path:expr$(,)*)*) => {
@@ -550,11 +1369,165 @@ mod tests {
 
             graphical_tests!(
                 test_01_branch_basics: (test_path("01-branch_basics.nes"), 13, 0xDB8E_7124_029B_C022),
-                test_02_backward_branch: (test_path("02-backward_branch.nes"), 15, 0xDF84_2558_1C2B_C9A7),
+                test_02_backward_branch: (test_path("02-backward_branch.nes"), 16, 0xDF84_2558_1C2B_C9A7),
                 test_03_forward_branch: (test_path("03-forward_branch.nes"), 15, 0x528E_9396_828A_8125),
             );
         }
 
+        mod cpu_6502 {
+            use crate::{Cpu6502, Memory};
+
+            struct FlatMemory {
+                bytes: Vec<u8>,
+                reads: Vec<u16>,
+                ticks: u64,
+                // Writes to $4014 halt the CPU for 100 cycles.
+                dma_pending: bool,
+            }
+
+            impl FlatMemory {
+                fn new(program: &[u8]) -> Self {
+                    let mut bytes = vec![0; 0x10000];
+                    bytes[0x0400..0x0400 + program.len()].copy_from_slice(program);
+                    bytes[0xFFFC] = 0x00;
+                    bytes[0xFFFD] = 0x04;
+                    FlatMemory {
+                        bytes,
+                        reads: Vec::new(),
+                        ticks: 0,
+                        dma_pending: false,
+                    }
+                }
+            }
+
+            impl Memory for FlatMemory {
+                fn read_byte(&mut self, addr: u16) -> u8 {
+                    self.reads.push(addr);
+                    self.bytes[addr as usize]
+                }
+
+                fn write_byte(&mut self, addr: u16, val: u8) {
+                    self.bytes[addr as usize] = val;
+                    self.dma_pending |= addr == 0x4014;
+                }
+
+                fn tick(&mut self) {
+                    self.ticks += 1;
+                }
+
+                fn take_dma_cycles(&mut self, _cycle: u64) -> u64 {
+                    if std::mem::take(&mut self.dma_pending) {
+                        100
+                    } else {
+                        0
+                    }
+                }
+            }
+
+            fn run(memory: FlatMemory) -> Cpu6502<FlatMemory> {
+                let mut cpu = Cpu6502::new(memory);
+                cpu.initialize();
+                while !cpu.is_jammed() {
+                    cpu.step();
+                }
+                cpu
+            }
+
+            #[test]
+            fn test_flat_memory() {
+                // LDX #$05; DEX; STX $10; BNE -5; KIL
+                let mut memory = FlatMemory::new(&[0xA2, 0x05, 0xCA, 0x86, 0x10, 0xD0, 0xFB, 0x02]);
+                memory.bytes[0x10] = 0xFF;
+                let cpu = run(memory);
+
+                assert_eq!(cpu.jammed_address(), Some(0x0407));
+                assert_eq!(cpu.x(), 0);
+                assert_eq!(cpu.memory().bytes[0x10], 0);
+                assert_eq!(cpu.memory().ticks, 43);
+            }
+
+            #[test]
+            fn test_dma_cycles() {
+                // STA $4013; KIL
+                let without_dma = run(FlatMemory::new(&[0x8D, 0x13, 0x40, 0x02]));
+                // STA $4014; KIL
+                let with_dma = run(FlatMemory::new(&[0x8D, 0x14, 0x40, 0x02]));
+                assert_eq!(with_dma.memory().ticks, without_dma.memory().ticks + 100);
+            }
+
+            #[test]
+            fn test_indexed_store_dummy_reads() {
+                // LDY #$07; STA $2000,Y; LDX #$FF; STA $20F0,X; STA $2100; KIL
+                let cpu = run(FlatMemory::new(&[
+                    0xA0, 0x07, 0x99, 0x00, 0x20, 0xA2, 0xFF, 0x9D, 0xF0, 0x20, 0x8D, 0x00, 0x21,
+                    0x02,
+                ]));
+                // Indexed stores read the address before the carry is added to the high byte.
+                let reads: Vec<u16> = cpu
+                    .memory()
+                    .reads
+                    .iter()
+                    .copied()
+                    .filter(|addr| (0x2000..0x3000).contains(addr))
+                    .collect();
+                assert_eq!(reads, [0x2007, 0x20EF]);
+            }
+        }
+
+        mod jam {
+            use crate::tests::program_rom;
+            use crate::Nes;
+
+            #[test]
+            fn test_kil() {
+                // LDA #$01; KIL
+                let buffer = program_rom(&[0xA9, 0x01, 0x02]);
+                let mut nes = Nes::default();
+                nes.load_rom(&buffer);
+                nes.step_frame();
+                assert!(nes.is_jammed());
+                assert_eq!(nes.jammed_address(), Some(0x8002));
+
+                nes.reset();
+                assert!(!nes.is_jammed());
+                assert_eq!(nes.jammed_address(), None);
+            }
+        }
+
+        mod variant {
+            use crate::tests::program_rom;
+            use crate::{CpuVariant, Nes};
+
+            // SED; CLC; LDA #$19; ADC #$28; STA $00; SEC; LDA #$50; SBC #$01; STA $01; KIL
+            const PROGRAM: [u8; 16] = [
+                0xF8, 0x18, 0xA9, 0x19, 0x69, 0x28, 0x85, 0x00, 0x38, 0xA9, 0x50, 0xE9, 0x01, 0x85,
+                0x01, 0x02,
+            ];
+
+            fn run_program(variant: CpuVariant) -> Box<Nes> {
+                let mut nes = Box::new(Nes::default());
+                nes.set_cpu_variant(variant);
+                nes.load_rom(&program_rom(&PROGRAM));
+                nes.step_frame();
+                assert!(nes.is_jammed());
+                nes
+            }
+
+            #[test]
+            fn test_ricoh_2a03_ignores_decimal_mode() {
+                let nes = run_program(CpuVariant::Ricoh2A03);
+                assert_eq!(nes.cpu.memory.ram[0x00], 0x41);
+                assert_eq!(nes.cpu.memory.ram[0x01], 0x4F);
+            }
+
+            #[test]
+            fn test_nmos_6502_decimal_mode() {
+                let nes = run_program(CpuVariant::Nmos6502);
+                assert_eq!(nes.cpu.memory.ram[0x00], 0x47);
+                assert_eq!(nes.cpu.memory.ram[0x01], 0x49);
+            }
+        }
+
         mod reset {
             fn test_path(file_name: &str) -> String {
                 format!("./tests/cpu/reset/{}", file_name)
@@ -581,6 +1554,16 @@ mod tests {
             );
         }
 
+        mod exec_space {
+            fn test_path(file_name: &str) -> String {
+                format!("./tests/cpu/exec_space/{}", file_name)
+            }
+
+            text_tests!(
+                test_apu: test_path("apu.nes"),
+            );
+        }
+
         mod instr_misc {
             fn test_path(file_name: &str) -> String {
                 format!("./tests/cpu/instr_misc/{}", file_name)
@@ -622,6 +1605,28 @@ mod tests {
 
         text_tests!(
             test_oam_read: test_path("oam_read.nes"),
+            test_oam_stress: test_path("oam_stress.nes"),
+            test_open_bus: test_path("open_bus.nes"),
+        );
+
+        #[test]
+        fn test_read_buffer() {
+            use crate::tests::run_text_test;
+            use crate::Nes;
+
+            // The ROM reports its result at $6000, but CNROM boards have no PRG RAM, so 8 KiB of
+            // PRG RAM is added through byte 8 of the header.
+            let mut buffer =
+                std::fs::read(test_path("read_buffer.nes")).expect("Expected test rom to exist.");
+            buffer[8] = 1;
+            let mut nes = Nes::default();
+            nes.load_rom(&buffer);
+            run_text_test(&mut nes);
+        }
+
+        graphical_tests!(
+            test_full_palette: (test_path("full_palette.nes"), 30, 0x0B27_B112_3987_1EEB),
+            test_palette: (test_path("palette.nes"), 60, 0x06A2_D848_B2FB_5335),
         );
 
         mod general {
@@ -632,7 +1637,8 @@ mod tests {
             graphical_tests!(
                 test_palette_ram: (test_path("palette_ram.nes"), 18, 0x657D_9167_290E_F938),
                 test_power_up_palette: (test_path("power_up_palette.nes"), 18, 0x657D_9167_290E_F938),
-                test_sprite_ram: (test_path("sprite_ram.nes"), 18, 0x657D_9167_290E_F938),
+                test_sprite_ram: (test_path("sprite_ram.nes"), 20, 0x657D_9167_290E_F938),
+                test_vbl_clear_time: (test_path("vbl_clear_time.nes"), 24, 0x657D_9167_290E_F938),
                 test_vram_access: (test_path("vram_access.nes"), 18, 0x657D_9167_290E_F938),
             );
         }
@@ -651,6 +1657,7 @@ mod tests {
                 test_06_right_edge: test_path("06-right_edge.nes"),
                 test_07_screen_bottom: test_path("07-screen_bottom.nes"),
                 test_08_double_height: test_path("08-double_height.nes"),
+                test_09_timing: test_path("09-timing.nes"),
                 test_10_timing_order: test_path("10-timing_order.nes"),
             );
         }
@@ -663,6 +1670,8 @@ mod tests {
             text_tests!(
                 test_01_basics: test_path("01-basics.nes"),
                 test_02_details: test_path("02-details.nes"),
+                test_03_timing: test_path("03-timing.nes"),
+                test_04_obscure: test_path("04-obscure.nes"),
                 test_05_emulator: test_path("05-emulator.nes"),
             );
         }
@@ -674,12 +1683,48 @@ mod tests {
 
             text_tests!(
                 test_01_vbl_basics: test_path("01-vbl_basics.nes"),
+                test_02_vbl_set_time: test_path("02-vbl_set_time.nes"),
                 test_03_clear_time: test_path("03-vbl_clear_time.nes"),
+                test_04_nmi_control: test_path("04-nmi_control.nes"),
+                test_05_nmi_timing: test_path("05-nmi_timing.nes"),
+                test_06_suppression: test_path("06-suppression.nes"),
+                test_07_nmi_on_timing: test_path("07-nmi_on_timing.nes"),
+                test_08_nmi_off_timing: test_path("08-nmi_off_timing.nes"),
+                test_09_even_odd_frames: test_path("09-even_odd_frames.nes"),
+                test_10_even_odd_timing: test_path("10-even_odd_timing.nes"),
             );
         }
     }
 
     mod apu {
+        use crate::Nes;
+
+        // Plays pulse 1 at 50% duty and full volume with a timer period of 8, which is about
+        // 12.4 kHz.
+        #[rustfmt::skip]
+        const HIGH_PULSE_PROGRAM: [u8; 28] = [
+            0xA9, 0x40, 0x8D, 0x17, 0x40, // LDA #$40, STA $4017
+            0xA9, 0x01, 0x8D, 0x15, 0x40, // LDA #$01, STA $4015
+            0xA9, 0xBF, 0x8D, 0x00, 0x40, // LDA #$BF, STA $4000
+            0xA9, 0x08, 0x8D, 0x02, 0x40, // LDA #$08, STA $4002
+            0xA9, 0x00, 0x8D, 0x03, 0x40, // LDA #$00, STA $4003
+            0x4C, 0x19, 0x80,             // JMP $8019
+        ];
+
+        fn record(nes: &mut Nes, frames: usize) -> Vec<f32> {
+            let mut samples = Vec::new();
+            for _ in 0..frames {
+                nes.step_frame();
+                samples.extend_from_slice(&nes.apu.buffer[..nes.audio_buffer_len()]);
+            }
+            samples
+        }
+
+        fn rms(samples: &[f32]) -> f32 {
+            let sum: f32 = samples.iter().map(|sample| sample * sample).sum();
+            (sum / samples.len() as f32).sqrt()
+        }
+
         mod reset {
             fn test_path(file_name: &str) -> String {
                 format!("./tests/apu/reset/{}", file_name)
@@ -688,8 +1733,10 @@ mod tests {
             reset_text_tests!(
                 test_4015_cleared: (test_path("4015_cleared.nes"), 10),
                 test_4017_timing: (test_path("4017_timing.nes"), 18),
+                test_4017_written: (test_path("4017_written.nes"), 17),
                 test_irq_flag_cleared: (test_path("irq_flag_cleared.nes"), 10),
                 test_len_ctrs_enabled: (test_path("len_ctrs_enabled.nes"), 13),
+                test_works_immediately: (test_path("works_immediately.nes"), 17),
             );
         }
 
@@ -702,8 +1749,1558 @@ mod tests {
                 test_01_len_ctr: test_path("01-len_ctr.nes"),
                 test_02_len_table: test_path("02-len_table.nes"),
                 test_03_irq_flag: test_path("03-irq_flag.nes"),
+                test_04_jitter: test_path("04-jitter.nes"),
+                test_05_len_timing: test_path("05-len_timing.nes"),
+                test_06_irq_flag_timing: test_path("06-irq_flag_timing.nes"),
                 test_07_dmc_basics: test_path("07-dmc_basics.nes"),
             );
         }
+
+        mod synthesis {
+            use super::{record, HIGH_PULSE_PROGRAM};
+            use crate::tests::program_rom;
+            use crate::Nes;
+            use std::f64::consts;
+
+            const CLOCK_FREQ: f64 = 1_789_773.0;
+
+            // Returns the magnitude of the frequency `freq` in `samples`.
+            fn magnitude(samples: &[f32], sample_freq: f64, freq: f64) -> f64 {
+                let (mut re, mut im) = (0.0, 0.0);
+                for (index, sample) in samples.iter().enumerate() {
+                    let angle = 2.0 * consts::PI * freq * index as f64 / sample_freq;
+                    re += f64::from(*sample) * angle.cos();
+                    im += f64::from(*sample) * angle.sin();
+                }
+                (re * re + im * im).sqrt() / samples.len() as f64
+            }
+
+            #[test]
+            fn test_sample_count() {
+                for sample_freq in &[22_050.0, 44_100.0, 48_000.0, 96_000.0] {
+                    let mut nes = Nes::new(*sample_freq);
+                    nes.load_rom(&program_rom(&HIGH_PULSE_PROGRAM));
+                    let start_cycle = nes.apu.cycle;
+                    let count = record(&mut nes, 120).len() as f64;
+                    let expected =
+                        (nes.apu.cycle - start_cycle) as f64 * f64::from(*sample_freq) / CLOCK_FREQ;
+                    assert!((count - expected).abs() <= 1.0);
+                }
+            }
+
+            #[test]
+            fn test_high_pitched_pulse_not_aliased() {
+                for sample_freq in &[44_100.0, 48_000.0] {
+                    let mut nes = Nes::new(*sample_freq);
+                    nes.load_rom(&program_rom(&HIGH_PULSE_PROGRAM));
+                    record(&mut nes, 10);
+                    let samples = record(&mut nes, 30);
+
+                    let sample_freq = f64::from(*sample_freq);
+                    let freq = CLOCK_FREQ / 16.0 / 9.0;
+                    // The third harmonic is above the Nyquist frequency and is reflected below it
+                    // when the output is point sampled.
+                    let alias_freq = (sample_freq - 3.0 * freq).abs();
+                    let fundamental = magnitude(&samples, sample_freq, freq);
+                    let alias = magnitude(&samples, sample_freq, alias_freq);
+                    assert!(fundamental > 0.01);
+                    assert!(alias < fundamental / 100.0);
+                }
+            }
+        }
+
+        mod channels {
+            use super::{record, rms, HIGH_PULSE_PROGRAM};
+            use crate::tests::program_rom;
+            use crate::{Channel, Nes};
+            use std::slice;
+
+            #[test]
+            fn test_channel_muted() {
+                let mut nes = Nes::default();
+                nes.load_rom(&program_rom(&HIGH_PULSE_PROGRAM));
+                assert!(rms(&record(&mut nes, 10)) > 0.01);
+
+                nes.set_channel_enabled(Channel::Pulse2, false);
+                assert!(rms(&record(&mut nes, 10)) > 0.01);
+
+                nes.set_channel_enabled(Channel::Pulse1, false);
+                assert!(!nes.channel_enabled(Channel::Pulse1));
+                record(&mut nes, 1);
+                assert!(rms(&record(&mut nes, 10)) < 0.0001);
+            }
+
+            #[test]
+            fn test_channel_volume() {
+                let mut nes = Nes::default();
+                nes.load_rom(&program_rom(&HIGH_PULSE_PROGRAM));
+                record(&mut nes, 10);
+                let full = rms(&record(&mut nes, 10));
+
+                nes.set_channel_volume(Channel::Pulse1, 0.5);
+                record(&mut nes, 1);
+                let half = rms(&record(&mut nes, 10));
+                // The pulse mixer is close to linear at low levels.
+                assert!(half < full * 0.6 && half > full * 0.4);
+            }
+
+            #[test]
+            #[should_panic(expected = "[NES] Invalid channel volume")]
+            fn test_invalid_channel_volume() {
+                let mut nes = Nes::default();
+                nes.set_channel_volume(Channel::Noise, -1.0);
+            }
+
+            #[test]
+            fn test_channel_buffers() {
+                let mut nes = Nes::default();
+                nes.set_channel_buffers_enabled(true);
+                nes.load_rom(&program_rom(&HIGH_PULSE_PROGRAM));
+                let channel_buffer = |nes: &Nes, channel| unsafe {
+                    slice::from_raw_parts(nes.channel_buffer(channel), nes.audio_buffer_len())
+                        .to_vec()
+                };
+                for _ in 0..10 {
+                    nes.step_frame();
+                    let audio_buffer = nes.apu.buffer[..nes.audio_buffer_len()].to_vec();
+                    assert_eq!(channel_buffer(&nes, Channel::Pulse1), audio_buffer);
+                    for channel in &[
+                        Channel::Pulse2,
+                        Channel::Triangle,
+                        Channel::Noise,
+                        Channel::Dmc,
+                    ] {
+                        assert!(channel_buffer(&nes, *channel)
+                            .iter()
+                            .all(|sample| *sample == 0.0));
+                    }
+                }
+
+                // Muting a channel does not change its channel buffer.
+                nes.set_channel_enabled(Channel::Pulse1, false);
+                record(&mut nes, 1);
+                nes.step_frame();
+                assert!(rms(&nes.apu.buffer[..nes.audio_buffer_len()]) < 0.0001);
+                assert!(rms(&channel_buffer(&nes, Channel::Pulse1)) > 0.01);
+            }
+        }
+
+        mod stereo {
+            use super::{rms, HIGH_PULSE_PROGRAM};
+            use crate::tests::program_rom;
+            use crate::{Channel, Nes};
+            use std::slice;
+
+            // Returns the mono and the left and right samples of the next frame.
+            fn run_frame(nes: &mut Nes) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+                nes.step_frame();
+                let len = nes.audio_buffer_len();
+                let stereo = unsafe { slice::from_raw_parts(nes.stereo_audio_buffer(), len * 2) };
+                let left = stereo.iter().step_by(2).copied().collect();
+                let right = stereo.iter().skip(1).step_by(2).copied().collect();
+                (nes.apu.buffer[..len].to_vec(), left, right)
+            }
+
+            #[test]
+            fn test_centered() {
+                let mut nes = Nes::default();
+                nes.set_stereo_enabled(true);
+                nes.load_rom(&program_rom(&HIGH_PULSE_PROGRAM));
+                for _ in 0..10 {
+                    let (mono, left, right) = run_frame(&mut nes);
+                    assert!(rms(&mono) > 0.0);
+                    assert_eq!(left, mono);
+                    assert_eq!(right, mono);
+                }
+            }
+
+            #[test]
+            fn test_panned() {
+                let mut nes = Nes::default();
+                nes.set_stereo_enabled(true);
+                nes.set_channel_pan(Channel::Pulse1, -1.0);
+                nes.load_rom(&program_rom(&HIGH_PULSE_PROGRAM));
+                for _ in 0..10 {
+                    let (mono, left, right) = run_frame(&mut nes);
+                    assert_eq!(left, mono);
+                    assert!(right.iter().all(|sample| *sample == 0.0));
+                }
+
+                // The right side starts from silence, so it matches the mono mix once the filters
+                // settle.
+                nes.set_channel_pan(Channel::Pulse1, 0.5);
+                for _ in 0..10 {
+                    run_frame(&mut nes);
+                }
+                let (mono, left, right) = run_frame(&mut nes);
+                for (right, mono) in right.iter().zip(mono.iter()) {
+                    assert!((right - mono).abs() < 0.0001);
+                }
+                assert!(rms(&left) < rms(&mono) * 0.6 && rms(&left) > rms(&mono) * 0.4);
+            }
+
+            #[test]
+            #[should_panic(expected = "[NES] Invalid channel pan")]
+            fn test_invalid_pan() {
+                let mut nes = Nes::default();
+                nes.set_channel_pan(Channel::Triangle, 1.5);
+            }
+        }
+
+        mod ring_buffer {
+            use super::{record, HIGH_PULSE_PROGRAM};
+            use crate::tests::program_rom;
+            use crate::{AudioRingBuffer, Nes};
+            use std::thread;
+
+            #[test]
+            fn test_wrap_around() {
+                let ring_buffer = AudioRingBuffer::new(4);
+                for sample in 1..=4 {
+                    assert!(ring_buffer.push(sample as f32));
+                }
+                assert!(!ring_buffer.push(5.0));
+                assert_eq!(ring_buffer.len(), 4);
+                assert_eq!(ring_buffer.pop(), Some(1.0));
+                assert_eq!(ring_buffer.pop(), Some(2.0));
+                assert!(ring_buffer.push(5.0));
+                assert!(ring_buffer.push(6.0));
+
+                let mut out = [0.0; 8];
+                assert_eq!(ring_buffer.read(&mut out), 4);
+                assert_eq!(out[..4], [3.0, 4.0, 5.0, 6.0]);
+                assert!(ring_buffer.is_empty());
+                assert_eq!(ring_buffer.pop(), None);
+            }
+
+            #[test]
+            fn test_threads() {
+                let ring_buffer = AudioRingBuffer::new(64);
+                let reader = ring_buffer.clone();
+                let handle = thread::spawn(move || {
+                    let mut samples = Vec::new();
+                    while samples.len() < 10_000 {
+                        if let Some(sample) = reader.pop() {
+                            samples.push(sample);
+                        }
+                    }
+                    samples
+                });
+                for sample in 0..10_000 {
+                    while !ring_buffer.push(sample as f32) {}
+                }
+                let samples = handle.join().unwrap();
+                assert!(samples
+                    .iter()
+                    .enumerate()
+                    .all(|(index, sample)| *sample == index as f32));
+            }
+
+            #[test]
+            fn test_audio_written() {
+                let mut nes = Nes::default();
+                let ring_buffer = AudioRingBuffer::new(4096);
+                nes.attach_audio_ring_buffer(&ring_buffer);
+                nes.load_rom(&program_rom(&HIGH_PULSE_PROGRAM));
+                ring_buffer.read(&mut [0.0; 4096]);
+
+                let samples = record(&mut nes, 2);
+                let mut out = vec![0.0; 4096];
+                assert_eq!(ring_buffer.read(&mut out), samples.len());
+                assert_eq!(out[..samples.len()], samples[..]);
+
+                nes.set_stereo_enabled(true);
+                nes.step_frame();
+                assert_eq!(ring_buffer.len(), nes.audio_buffer_len() * 2);
+
+                nes.detach_audio_ring_buffer();
+                ring_buffer.read(&mut out);
+                nes.step_frame();
+                assert!(ring_buffer.is_empty());
+            }
+
+            #[test]
+            fn test_dynamic_rate_control() {
+                let mut nes = Nes::default();
+                let ring_buffer = AudioRingBuffer::new(8192);
+                nes.attach_audio_ring_buffer(&ring_buffer);
+                nes.set_dynamic_rate_control(1024, 0.005);
+                nes.load_rom(&program_rom(&HIGH_PULSE_PROGRAM));
+                // Returns the number of samples that are produced at 44.1 kHz since `cycle`.
+                let nominal_count =
+                    |nes: &Nes, cycle: u64| (nes.apu.cycle - cycle) as f64 * 44_100.0 / 1_789_773.0;
+
+                // The ring buffer is never read, so it is above the target latency.
+                let start_cycle = nes.apu.cycle;
+                let count = record(&mut nes, 30).len() as f64;
+                assert_eq!(nes.rate_adjustment(), 0.995);
+                assert!(count < nominal_count(&nes, start_cycle) * 0.997);
+
+                // The adjustment is only updated at the start of a frame, so emptying the ring
+                // buffer in the middle of a frame does not change it.
+                nes.step_frame();
+                ring_buffer.read(&mut [0.0; 8192]);
+                nes.apu.buffer_index = 0;
+                for _ in 0..10_000 {
+                    nes.step();
+                }
+                assert_eq!(nes.rate_adjustment(), 0.995);
+
+                // The ring buffer is emptied after each frame, so it is below the target latency.
+                let start_cycle = nes.apu.cycle;
+                let mut count = 0;
+                for _ in 0..30 {
+                    ring_buffer.read(&mut [0.0; 8192]);
+                    nes.step_frame();
+                    count += nes.audio_buffer_len();
+                }
+                assert!(nes.rate_adjustment() > 1.0);
+                assert!(count as f64 > nominal_count(&nes, start_cycle) + 1.0);
+
+                nes.disable_dynamic_rate_control();
+                assert_eq!(nes.rate_adjustment(), 1.0);
+            }
+        }
+
+        mod filters {
+            use super::{record, HIGH_PULSE_PROGRAM};
+            use crate::tests::program_rom;
+            use crate::{FilterProfile, FirstOrderFilter, HighPassFilter, LowPassFilter, Nes};
+            use std::cell::RefCell;
+            use std::f32::consts;
+            use std::rc::Rc;
+            use std::thread;
+
+            struct Gain(f32);
+
+            impl FirstOrderFilter for Gain {
+                fn process(&mut self, input_sample: f32) -> f32 {
+                    input_sample * self.0
+                }
+            }
+
+            fn mean(samples: &[f32]) -> f32 {
+                samples.iter().sum::<f32>() / samples.len() as f32
+            }
+
+            fn run_profile(profile: FilterProfile) -> Vec<f32> {
+                let mut nes = Nes::default();
+                nes.set_filter_profile(profile);
+                nes.load_rom(&program_rom(&HIGH_PULSE_PROGRAM));
+                record(&mut nes, 10);
+                record(&mut nes, 10)
+            }
+
+            // Returns the ratio of the output amplitude to the input amplitude of a sine wave at
+            // `freq` once the filter has settled.
+            fn gain(mut filter: impl FirstOrderFilter, freq: f32) -> f32 {
+                let sample_freq = 44_100.0;
+                let mut peak: f32 = 0.0;
+                for index in 0..44_100 {
+                    let angle = 2.0 * consts::PI * freq * index as f32 / sample_freq;
+                    let output = filter.process(angle.sin());
+                    if index >= 22_050 {
+                        peak = peak.max(output.abs());
+                    }
+                }
+                peak
+            }
+
+            #[test]
+            fn test_cutoff() {
+                // A first order filter attenuates its cutoff frequency by about 3 dB.
+                let low_pass = gain(LowPassFilter::new(1_000, 44_100.0), 1_000.0);
+                assert!(low_pass > 0.65 && low_pass < 0.75);
+                assert!(gain(LowPassFilter::new(1_000, 44_100.0), 100.0) > 0.95);
+                assert!(gain(LowPassFilter::new(1_000, 44_100.0), 10_000.0) < 0.2);
+
+                let high_pass = gain(HighPassFilter::new(1_000, 44_100.0), 1_000.0);
+                assert!(high_pass > 0.65 && high_pass < 0.75);
+                assert!(gain(HighPassFilter::new(1_000, 44_100.0), 100.0) < 0.2);
+                assert!(gain(HighPassFilter::new(1_000, 44_100.0), 10_000.0) > 0.9);
+            }
+
+            #[test]
+            fn test_profiles() {
+                // Without filters, the output of the APU has a DC offset.
+                let unfiltered = run_profile(FilterProfile::Custom);
+                assert!(mean(&unfiltered) > 0.05);
+                for profile in &[
+                    FilterProfile::Nes,
+                    FilterProfile::Famicom,
+                    FilterProfile::Clean,
+                ] {
+                    assert!(mean(&run_profile(*profile)).abs() < 0.001);
+                }
+                assert_ne!(
+                    run_profile(FilterProfile::Nes),
+                    run_profile(FilterProfile::Famicom),
+                );
+            }
+
+            #[test]
+            fn test_custom_filters() {
+                let sample_rates = Rc::new(RefCell::new(Vec::new()));
+                let mut nes = Nes::default();
+                {
+                    let sample_rates = Rc::clone(&sample_rates);
+                    nes.set_custom_filters(move |sample_rate| {
+                        sample_rates.borrow_mut().push(sample_rate);
+                        vec![Box::new(Gain(0.5))]
+                    });
+                }
+                assert_eq!(nes.filter_profile(), FilterProfile::Custom);
+                nes.load_rom(&program_rom(&HIGH_PULSE_PROGRAM));
+                record(&mut nes, 10);
+                let samples = record(&mut nes, 10);
+                let unfiltered = run_profile(FilterProfile::Custom);
+                for (sample, unfiltered) in samples.iter().zip(unfiltered.iter()) {
+                    assert_eq!(*sample, unfiltered * 0.5);
+                }
+
+                nes.set_sample_freq(48_000.0);
+                nes.step_frame();
+                assert_eq!(*sample_rates.borrow(), [44_100.0, 48_000.0]);
+            }
+
+            #[test]
+            fn test_save_state() {
+                // Save states of the PPU are larger than the default stack of test threads.
+                let handle = thread::Builder::new()
+                    .stack_size(16 * 1024 * 1024)
+                    .spawn(|| {
+                        let mut nes = Nes::default();
+                        nes.load_rom(&program_rom(&HIGH_PULSE_PROGRAM));
+                        record(&mut nes, 10);
+                        let state = nes.save_state().unwrap();
+                        let samples = record(&mut nes, 2);
+
+                        // The filters continue from their saved state.
+                        nes.load_state(&state).unwrap();
+                        assert_eq!(record(&mut nes, 2), samples);
+                    })
+                    .unwrap();
+                handle.join().unwrap();
+            }
+        }
+
+        mod recording {
+            use super::{record, HIGH_PULSE_PROGRAM};
+            use crate::tests::program_rom;
+            use crate::{AudioFormat, AudioRingBuffer, Channel, Nes};
+            use std::convert::TryInto;
+
+            fn read_u16(data: &[u8], index: usize) -> u16 {
+                u16::from_le_bytes(data[index..index + 2].try_into().unwrap())
+            }
+
+            fn read_u32(data: &[u8], index: usize) -> u32 {
+                u32::from_le_bytes(data[index..index + 4].try_into().unwrap())
+            }
+
+            fn decode_pcm_16(data: &[u8]) -> Vec<f32> {
+                data.chunks(2)
+                    .map(|bytes| f32::from(i16::from_le_bytes([bytes[0], bytes[1]])) / 32767.0)
+                    .collect()
+            }
+
+            fn decode_float(data: &[u8]) -> Vec<f32> {
+                data.chunks(4)
+                    .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+                    .collect()
+            }
+
+            fn assert_close(actual: &[f32], expected: &[f32]) {
+                assert_eq!(actual.len(), expected.len());
+                for (actual, expected) in actual.iter().zip(expected.iter()) {
+                    assert!((actual - expected).abs() <= 1.0 / 32767.0);
+                }
+            }
+
+            #[test]
+            fn test_wav_16() {
+                let mut nes = Nes::new(48_000.0);
+                nes.load_rom(&program_rom(&HIGH_PULSE_PROGRAM));
+                record(&mut nes, 5);
+                nes.start_audio_recording(AudioFormat::Wav16, false);
+                assert!(nes.is_recording_audio());
+                let samples = record(&mut nes, 10);
+                let recording = nes.stop_audio_recording().unwrap();
+                assert!(!nes.is_recording_audio());
+                assert!(recording.channel_data(Channel::Pulse1).is_none());
+
+                let data = recording.data();
+                assert_eq!(&data[0..4], b"RIFF");
+                assert_eq!(read_u32(&data, 4) as usize, data.len() - 8);
+                assert_eq!(&data[8..16], b"WAVEfmt ");
+                assert_eq!(read_u32(&data, 16), 16);
+                assert_eq!(read_u16(&data, 20), 1);
+                assert_eq!(read_u16(&data, 22), 1);
+                assert_eq!(read_u32(&data, 24), 48_000);
+                assert_eq!(read_u32(&data, 28), 96_000);
+                assert_eq!(read_u16(&data, 32), 2);
+                assert_eq!(read_u16(&data, 34), 16);
+                assert_eq!(&data[36..40], b"data");
+                assert_eq!(read_u32(&data, 40) as usize, samples.len() * 2);
+                assert_close(&decode_pcm_16(&data[44..]), &samples);
+            }
+
+            #[test]
+            fn test_wav_float() {
+                let mut nes = Nes::new(44_100.0);
+                nes.load_rom(&program_rom(&HIGH_PULSE_PROGRAM));
+                nes.start_audio_recording(AudioFormat::WavFloat, false);
+                let samples = record(&mut nes, 10);
+                let data = nes.stop_audio_recording().unwrap().data();
+
+                assert_eq!(read_u32(&data, 4) as usize, data.len() - 8);
+                assert_eq!(read_u32(&data, 16), 18);
+                assert_eq!(read_u16(&data, 20), 3);
+                assert_eq!(read_u32(&data, 24), 44_100);
+                assert_eq!(read_u32(&data, 28), 176_400);
+                assert_eq!(read_u16(&data, 32), 4);
+                assert_eq!(read_u16(&data, 34), 32);
+                assert_eq!(&data[38..42], b"fact");
+                assert_eq!(read_u32(&data, 46) as usize, samples.len());
+                assert_eq!(&data[50..54], b"data");
+                assert_eq!(read_u32(&data, 54) as usize, samples.len() * 4);
+                assert_eq!(decode_float(&data[58..]), samples);
+            }
+
+            #[test]
+            fn test_raw_pcm() {
+                let mut nes = Nes::default();
+                nes.load_rom(&program_rom(&HIGH_PULSE_PROGRAM));
+                nes.start_audio_recording(AudioFormat::RawPcm16, false);
+                let samples = record(&mut nes, 10);
+                let data = nes.stop_audio_recording().unwrap().data();
+                assert_close(&decode_pcm_16(&data), &samples);
+
+                nes.start_audio_recording(AudioFormat::RawFloat, false);
+                let samples = record(&mut nes, 10);
+                let data = nes.stop_audio_recording().unwrap().data();
+                assert_eq!(decode_float(&data), samples);
+
+                assert!(nes.stop_audio_recording().is_none());
+            }
+
+            #[test]
+            fn test_multi_track() {
+                let mut nes = Nes::default();
+                nes.load_rom(&program_rom(&HIGH_PULSE_PROGRAM));
+                nes.start_audio_recording(AudioFormat::RawFloat, true);
+                let samples = record(&mut nes, 10);
+                let recording = nes.stop_audio_recording().unwrap();
+
+                // Only the first pulse channel is playing, so its track matches the mix.
+                let pulse_1 = decode_float(&recording.channel_data(Channel::Pulse1).unwrap());
+                assert_eq!(pulse_1, samples);
+                for channel in &[
+                    Channel::Pulse2,
+                    Channel::Triangle,
+                    Channel::Noise,
+                    Channel::Dmc,
+                ] {
+                    let track = decode_float(&recording.channel_data(*channel).unwrap());
+                    assert_eq!(track.len(), samples.len());
+                    assert!(track.iter().all(|sample| sample.abs() < 0.0001));
+                }
+            }
+
+            #[test]
+            fn test_rate_control_paused() {
+                let mut nes = Nes::default();
+                let ring_buffer = AudioRingBuffer::new(8192);
+                nes.attach_audio_ring_buffer(&ring_buffer);
+                nes.set_dynamic_rate_control(1024, 0.005);
+                nes.load_rom(&program_rom(&HIGH_PULSE_PROGRAM));
+                // The ring buffer is never read, so it is above the target latency.
+                record(&mut nes, 10);
+                assert_eq!(nes.rate_adjustment(), 0.995);
+
+                nes.start_audio_recording(AudioFormat::RawFloat, false);
+                assert_eq!(nes.rate_adjustment(), 1.0);
+                let start_cycle = nes.apu.cycle;
+                let samples = record(&mut nes, 30);
+                assert_eq!(nes.rate_adjustment(), 1.0);
+                let nominal_count = (nes.apu.cycle - start_cycle) as f64 * 44_100.0 / 1_789_773.0;
+                assert!((samples.len() as f64 - nominal_count).abs() <= 1.0);
+
+                nes.stop_audio_recording();
+                record(&mut nes, 1);
+                assert_eq!(nes.rate_adjustment(), 0.995);
+            }
+        }
+    }
+
+    mod cdl {
+        use crate::cdl::{self, CodeDataLogger};
+        use crate::tests::program_rom;
+        use crate::Nes;
+
+        #[test]
+        fn test_prg_rom_log() {
+            // LDA $8010; KIL
+            let buffer = program_rom(&[0xAD, 0x10, 0x80, 0x02]);
+            let mut nes = Nes::default();
+            nes.set_cdl_enabled(true);
+            nes.load_rom(&buffer);
+            nes.step_frame();
+
+            let cdl_data = nes.cdl_data();
+            assert_eq!(cdl_data.len(), 0x4000 + 0x2000);
+            assert_eq!(&cdl_data[0x0000..0x0004], &[0x01, 0x01, 0x01, 0x01]);
+            assert_eq!(cdl_data[0x0010], 0x02);
+            // The reset vector is read through the mirror at $C000-$FFFF.
+            assert_eq!(&cdl_data[0x3FFC..0x3FFE], &[0x0E, 0x0E]);
+            assert!(cdl_data[0x4000..].iter().all(|val| *val == 0));
+
+            nes.clear_cdl();
+            assert!(nes.cdl_data().iter().all(|val| *val == 0));
+            assert!(nes.load_cdl(&cdl_data));
+            assert_eq!(nes.cdl_data(), cdl_data);
+            assert!(!nes.load_cdl(&cdl_data[1..]));
+        }
+
+        #[test]
+        fn test_chr_rom_log() {
+            #[rustfmt::skip]
+            let program = [
+                0xA9, 0x00, 0x8D, 0x06, 0x20, // LDA #$00; STA $2006
+                0xA9, 0x10, 0x8D, 0x06, 0x20, // LDA #$10; STA $2006
+                0xAD, 0x07, 0x20,             // LDA $2007
+                0xAD, 0x07, 0x20,             // LDA $2007
+                0xA9, 0x08, 0x8D, 0x01, 0x20, // LDA #$08; STA $2001
+                0x4C, 0x15, 0x80,             // JMP $8015
+            ];
+            let mut nes = Nes::default();
+            nes.set_cdl_enabled(true);
+            nes.load_rom(&program_rom(&program));
+            for _ in 0..3 {
+                nes.step_frame();
+            }
+
+            let chr_rom_log = &nes.cdl_data()[0x4000..];
+            // The background is tile 0, which is rendered from $0000-$000F.
+            assert!(chr_rom_log[0x0000..0x0010]
+                .iter()
+                .all(|val| *val == cdl::CHR_RENDERED));
+            // Each $2007 read fetches the byte at the VRAM address into the read buffer.
+            assert_eq!(chr_rom_log[0x0010], cdl::CHR_READ);
+            assert_eq!(chr_rom_log[0x0011], cdl::CHR_READ);
+            assert_eq!(chr_rom_log[0x0012], 0);
+        }
+
+        #[test]
+        fn test_pcm_data_log() {
+            #[rustfmt::skip]
+            let program = [
+                0xA9, 0x0F, 0x8D, 0x10, 0x40, // LDA #$0F; STA $4010
+                0xA9, 0x40, 0x8D, 0x12, 0x40, // LDA #$40; STA $4012
+                0xA9, 0x00, 0x8D, 0x13, 0x40, // LDA #$00; STA $4013
+                0xA9, 0x10, 0x8D, 0x15, 0x40, // LDA #$10; STA $4015
+                0x4C, 0x14, 0x80,             // JMP $8014
+            ];
+            let mut nes = Nes::default();
+            nes.set_cdl_enabled(true);
+            nes.load_rom(&program_rom(&program));
+            nes.step_frame();
+
+            // The 1 byte sample at $D000 is logged with the window of $C000-$DFFF.
+            let cdl_data = nes.cdl_data();
+            assert_eq!(cdl_data[0x1000], cdl::PRG_PCM_DATA | 0x08);
+            assert_eq!(cdl_data[0x1001], 0);
+        }
+
+        #[test]
+        fn test_round_trip() {
+            let mut logger = CodeDataLogger::new(0x10, 0x08);
+            logger.enabled = true;
+            logger.log_prg_rom(0x01, 0x8001, cdl::PRG_CODE);
+            logger.log_prg_rom(0x02, 0xE002, cdl::PRG_DATA);
+            logger.log_chr_rom(0x03, cdl::CHR_RENDERED | cdl::CHR_READ);
+            let bytes = logger.to_bytes();
+            assert_eq!(bytes.len(), 0x18);
+            assert_eq!(bytes[0x01], 0x01);
+            assert_eq!(bytes[0x02], 0x0E);
+            assert_eq!(bytes[0x13], 0x03);
+
+            let mut loaded = CodeDataLogger::new(0x10, 0x08);
+            assert!(loaded.load_bytes(&bytes));
+            assert_eq!(loaded.to_bytes(), bytes);
+            assert!(!loaded.load_bytes(&bytes[..0x10]));
+            assert_eq!(loaded.to_bytes(), bytes);
+        }
+    }
+
+    mod palette {
+        use crate::tests::program_rom;
+        use crate::{Nes, PixelFormat};
+
+        fn color(nes: &Nes, index: usize) -> u32 {
+            unsafe { *nes.colors().add(index) }
+        }
+
+        #[test]
+        fn test_load_palette() {
+            let mut nes = Nes::default();
+            assert!(nes.load_palette(&[0xFF; 64 * 3]));
+            assert_eq!(color(&nes, 0x3F), 0x00FF_FFFF);
+            // Emphasizing red attenuates green and blue.
+            assert_eq!(color(&nes, 0x7F), 0x00FF_D0D0);
+            // Emphasizing blue attenuates red and green.
+            assert_eq!(color(&nes, 0x13F), 0x00D0_D0FF);
+
+            let pal_data: Vec<u8> = (0..512 * 3).map(|i| i as u8).collect();
+            assert!(nes.load_palette(&pal_data));
+            assert_eq!(color(&nes, 0x1FF), 0x00FD_FEFF);
+
+            assert!(!nes.load_palette(&pal_data[1..]));
+            assert_eq!(color(&nes, 0x1FF), 0x00FD_FEFF);
+
+            nes.reset_palette();
+            assert_eq!(color(&nes, 0x00), 0x007C_7C7C);
+        }
+
+        #[test]
+        fn test_index_buffer() {
+            // Write $2A to $3F00, move the VRAM address out of palette RAM, and enable greyscale
+            // and all three emphasis bits with rendering disabled.
+            #[rustfmt::skip]
+            let buffer = program_rom(&[
+                0xA9, 0x3F, 0x8D, 0x06, 0x20, // LDA #$3F; STA $2006
+                0xA9, 0x00, 0x8D, 0x06, 0x20, // LDA #$00; STA $2006
+                0xA9, 0x2A, 0x8D, 0x07, 0x20, // LDA #$2A; STA $2007
+                0xA9, 0x00, 0x8D, 0x06, 0x20, // LDA #$00; STA $2006
+                0x8D, 0x06, 0x20,             // STA $2006
+                0xA9, 0xE1, 0x8D, 0x01, 0x20, // LDA #$E1; STA $2001
+                0x02,                         // KIL
+            ]);
+            let mut nes = Nes::default();
+            nes.load_rom(&buffer);
+            nes.set_image_output_enabled(false);
+            nes.step_frame();
+            nes.step_frame();
+
+            let index_buffer = unsafe { std::slice::from_raw_parts(nes.index_buffer(), 256 * 240) };
+            assert!(index_buffer.iter().all(|index| *index == 0x1E0));
+            assert!(nes.ppu.buffer.iter().all(|val| *val == 0));
+
+            nes.set_image_output_enabled(true);
+            nes.step_frame();
+            let color = color(&nes, 0x1E0);
+            assert_eq!(
+                nes.ppu.buffer[0..4],
+                [(color >> 16) as u8, (color >> 8) as u8, color as u8, 0xFF]
+            );
+        }
+
+        #[test]
+        fn test_pixel_formats() {
+            // The backdrop is $3F00, which is $09 at power up.
+            let buffer = program_rom(&[0x02]);
+            let mut nes = Nes::default();
+            nes.load_rom(&buffer);
+            let formats = [
+                (PixelFormat::Rgba8888, vec![0x00, 0x78, 0x00, 0xFF]),
+                (PixelFormat::Bgra8888, vec![0x00, 0x78, 0x00, 0xFF]),
+                (PixelFormat::Argb8888, 0xFF00_7800u32.to_ne_bytes().to_vec()),
+                (PixelFormat::Rgb565, 0x03C0u16.to_ne_bytes().to_vec()),
+                (PixelFormat::Rgb888, vec![0x00, 0x78, 0x00]),
+            ];
+            for (format, pixel) in formats.iter() {
+                nes.set_pixel_format(*format);
+                nes.step_frame();
+                assert_eq!(nes.image_buffer_len(), 256 * 240 * pixel.len());
+                let image_buffer = unsafe {
+                    std::slice::from_raw_parts(nes.image_buffer(), nes.image_buffer_len())
+                };
+                for val in image_buffer.chunks(pixel.len()) {
+                    assert_eq!(val, &pixel[..]);
+                }
+            }
+        }
+    }
+
+    mod overscan {
+        use crate::tests::program_rom;
+        use crate::Nes;
+
+        #[test]
+        fn test_overscan() {
+            let buffer =
+                std::fs::read("./tests/ppu/color_test.nes").expect("Expected test rom to exist.");
+            let mut nes = Nes::default();
+            nes.load_rom(&buffer);
+            for _ in 0..30 {
+                nes.step_frame();
+            }
+            let frame = nes.ppu.buffer.to_vec();
+
+            nes.set_overscan(8, 16, 4, 12);
+            nes.step_frame();
+            assert_eq!(nes.image_width(), 240);
+            assert_eq!(nes.image_height(), 216);
+            assert_eq!(nes.image_buffer_len(), 240 * 216 * 4);
+            let image_buffer =
+                unsafe { std::slice::from_raw_parts(nes.image_buffer(), nes.image_buffer_len()) };
+            for (y, row) in image_buffer.chunks(240 * 4).enumerate() {
+                let offset = ((y + 8) * 256 + 4) * 4;
+                assert_eq!(row, &frame[offset..offset + 240 * 4]);
+            }
+        }
+
+        #[test]
+        #[should_panic(expected = "[NES] Overscan crops the whole frame.")]
+        fn test_invalid_overscan() {
+            Nes::default().set_overscan(120, 120, 0, 0);
+        }
+
+        #[test]
+        fn test_left_column_masked() {
+            // Enable the background in the left 8 pixels with every pixel of the pattern tables
+            // set to color 3.
+            #[rustfmt::skip]
+            let mut buffer = program_rom(&[
+                0xA9, 0x0A, 0x8D, 0x01, 0x20, // LDA #$0A; STA $2001
+                0x02,                         // KIL
+            ]);
+            for val in buffer[0x10 + 0x4000..].iter_mut() {
+                *val = 0xFF;
+            }
+            let mut nes = Nes::default();
+            nes.load_rom(&buffer);
+            nes.step_frame();
+            nes.step_frame();
+            // $3F03 is $01 and the backdrop is $09 at power up.
+            assert!(nes.ppu.index_buffer.iter().all(|index| *index == 0x01));
+
+            nes.set_left_column_masked(true);
+            nes.step_frame();
+            for row in nes.ppu.index_buffer.chunks(256) {
+                assert!(row[..8].iter().all(|index| *index == 0x09));
+                assert!(row[8..].iter().all(|index| *index == 0x01));
+            }
+        }
+    }
+
+    mod sprites {
+        use crate::tests::program_rom;
+        use crate::Nes;
+
+        // Builds a ROM that draws 10 sprites 16 pixels apart on scanlines 100-107 over a
+        // background with every pixel set. Every pixel of the pattern tables is color 3.
+        fn sprite_line_rom() -> Vec<u8> {
+            #[rustfmt::skip]
+            let mut buffer = program_rom(&[
+                0xA2, 0x00,             // LDX #$00
+                0xA0, 0x00,             // LDY #$00
+                0xA9, 0x63,             // LDA #$63
+                0x9D, 0x00, 0x02,       // STA $0200,X
+                0xA9, 0x00,             // LDA #$00
+                0x9D, 0x01, 0x02,       // STA $0201,X
+                0x9D, 0x02, 0x02,       // STA $0202,X
+                0x98,                   // TYA
+                0x9D, 0x03, 0x02,       // STA $0203,X
+                0x18,                   // CLC
+                0x69, 0x10,             // ADC #$10
+                0xA8,                   // TAY
+                0x8A,                   // TXA
+                0x18,                   // CLC
+                0x69, 0x04,             // ADC #$04
+                0xAA,                   // TAX
+                0xE0, 0x28,             // CPX #$28
+                0xD0, 0xE2,             // BNE -30
+                0xA9, 0x02, 0x8D, 0x14, 0x40, // LDA #$02; STA $4014
+                0xA9, 0x1E, 0x8D, 0x01, 0x20, // LDA #$1E; STA $2001
+                0x02,                         // KIL
+            ]);
+            for val in buffer[0x10 + 0x4000..].iter_mut() {
+                *val = 0xFF;
+            }
+            buffer
+        }
+
+        // Returns the expected index of each pixel of a scanline when `sprite_count` sprites are
+        // drawn and sprites and the background have the given indexes.
+        fn expected_line(sprite_count: usize, sprite: u16, background: u16) -> Vec<u16> {
+            (0..256)
+                .map(|x| {
+                    if x < sprite_count * 16 && x % 16 < 8 {
+                        sprite
+                    } else {
+                        background
+                    }
+                })
+                .collect()
+        }
+
+        // Runs to the middle of scanline 120 and returns scanline 104 of the index buffer.
+        fn run_frame(nes: &mut Nes) -> Vec<u16> {
+            nes.step_frame();
+            nes.apu.buffer_index = 0;
+            while nes.ppu.scanline != 120 {
+                nes.step();
+            }
+            nes.ppu.index_buffer[104 * 256..105 * 256].to_vec()
+        }
+
+        #[test]
+        fn test_sprite_limit_removed() {
+            let mut nes = Nes::default();
+            nes.load_rom(&sprite_line_rom());
+            nes.step_frame();
+            // $3F03 is $01 and $3F13 is $03 at power up.
+            assert_eq!(run_frame(&mut nes), expected_line(8, 0x03, 0x01));
+            assert!(nes.ppu.r.sprite_overflow);
+            assert!(nes.ppu.r.sprite_0_hit);
+
+            nes.set_sprite_limit_removed(true);
+            assert_eq!(run_frame(&mut nes), expected_line(10, 0x03, 0x01));
+            assert!(nes.ppu.r.sprite_overflow);
+            assert!(nes.ppu.r.sprite_0_hit);
+        }
+
+        #[test]
+        fn test_sprite_0_from_oam_addr() {
+            // JMP $8000
+            let mut buffer = program_rom(&[0x4C, 0x00, 0x80]);
+            for val in buffer[0x10 + 0x4000..].iter_mut() {
+                *val = 0xFF;
+            }
+            let mut nes = Nes::default();
+            nes.load_rom(&buffer);
+            // Only the sprite in slot 1 is on screen, on scanlines 100-107.
+            nes.ppu.write_register(0x2003, 0x00);
+            for n in 0..64 {
+                let sprite = if n == 1 {
+                    [0x63, 0x00, 0x00, 0x80]
+                } else {
+                    [0xF0; 4]
+                };
+                for val in sprite.iter() {
+                    nes.ppu.write_register(0x2004, *val);
+                }
+            }
+            nes.ppu.write_register(0x2001, 0x1E);
+            nes.step_frame();
+
+            // OAMADDR is 0 when sprites are evaluated, so the sprite is not sprite 0.
+            nes.step_frame();
+            nes.apu.buffer_index = 0;
+            while nes.ppu.scanline != 120 {
+                nes.step();
+            }
+            assert!(!nes.ppu.r.sprite_0_hit);
+
+            // The first sprite evaluated is sprite 0, so starting sprite evaluation at slot 1 makes
+            // the sprite sprite 0.
+            nes.step_frame();
+            nes.apu.buffer_index = 0;
+            while nes.ppu.scanline != 99 {
+                nes.step();
+            }
+            nes.ppu.write_register(0x2003, 0x04);
+            while nes.ppu.scanline != 120 {
+                nes.step();
+            }
+            assert!(nes.ppu.r.sprite_0_hit);
+        }
+
+        #[test]
+        fn test_hidden_layers() {
+            let mut nes = Nes::default();
+            nes.load_rom(&sprite_line_rom());
+            nes.step_frame();
+            // The backdrop is $09 at power up.
+            nes.set_background_hidden(true);
+            assert_eq!(run_frame(&mut nes), expected_line(8, 0x03, 0x09));
+            assert!(nes.ppu.r.sprite_0_hit);
+
+            nes.set_sprites_hidden(true);
+            assert_eq!(run_frame(&mut nes), expected_line(0, 0x03, 0x09));
+            assert!(nes.ppu.r.sprite_0_hit);
+        }
+    }
+
+    mod viewer {
+        use crate::tests::run_rom;
+        use crate::SpriteInfo;
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::Hasher;
+
+        fn hash(image: &[u8]) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            hasher.write(image);
+            hasher.finish()
+        }
+
+        #[test]
+        fn test_pattern_table_image() {
+            let nes = run_rom("./tests/ppu/color_test.nes", 30);
+            let image = nes.pattern_table_image(0, 4);
+            assert_eq!(image.len(), 128 * 128 * 4);
+            assert_eq!(hash(&image), 0x98AB_48EC_5973_129E);
+        }
+
+        #[test]
+        fn test_nametable_image() {
+            let nes = run_rom("./tests/ppu/sprite_hit/01-basics.nes", 100);
+            let image = nes.nametable_image(false);
+            assert_eq!(image.len(), 512 * 480 * 4);
+            assert_eq!(hash(&image), 0xFCC2_E5C2_0F57_560B);
+            // Only the outline of the scroll window is highlighted.
+            let highlighted_image = nes.nametable_image(true);
+            let highlighted_count = image
+                .chunks(4)
+                .zip(highlighted_image.chunks(4))
+                .filter(|(a, b)| a != b)
+                .count();
+            assert_eq!(highlighted_count, 2 * 256 + 2 * 238);
+        }
+
+        #[test]
+        fn test_sprite_sheet_image() {
+            let nes = run_rom("./tests/ppu/color_test.nes", 30);
+            let image = nes.sprite_sheet_image();
+            assert_eq!(image.len(), 64 * 128 * 4);
+            assert_eq!(hash(&image), 0x84BD_146D_8ED0_EE50);
+            assert_eq!(
+                nes.sprite_info(0),
+                SpriteInfo {
+                    x: 32,
+                    y: 23,
+                    tile_index: 0xB8,
+                    palette: 0,
+                    behind_background: false,
+                    flip_horizontal: false,
+                    flip_vertical: false,
+                },
+            );
+        }
+
+        #[test]
+        fn test_palette_image() {
+            let nes = run_rom("./tests/ppu/color_test.nes", 30);
+            let image = nes.palette_image();
+            assert_eq!(image.len(), 256 * 32 * 4);
+            assert_eq!(hash(&image), 0x0388_40D4_FD35_E793);
+        }
+    }
+
+    mod event_log {
+        use crate::tests::run_rom;
+        use crate::{MirroringMode, Nes, RegisterWrite};
+
+        fn run(path: &str, frames: usize, enabled: bool) -> Box<Nes> {
+            let mut nes = run_rom(path, 0);
+            nes.set_event_log_enabled(enabled);
+            for _ in 0..frames {
+                nes.step_frame();
+            }
+            nes
+        }
+
+        #[test]
+        fn test_register_writes() {
+            let nes = run("./tests/ppu/color_test.nes", 60, true);
+            assert_eq!(nes.register_write_count(), 10);
+            assert_eq!(
+                nes.register_write(0),
+                RegisterWrite {
+                    scanline: 241,
+                    dot: 43,
+                    addr: 0x2003,
+                    val: 0x00,
+                },
+            );
+            assert_eq!(
+                nes.register_write(9),
+                RegisterWrite {
+                    scanline: 246,
+                    dot: 120,
+                    addr: 0x2001,
+                    val: 0x1E,
+                },
+            );
+        }
+
+        #[test]
+        fn test_mid_frame_writes() {
+            // The test switches the greyscale bit during scanlines 48-95 and the background
+            // pattern table during scanlines 120-167, and switches them back later in the same
+            // scanline.
+            let nes = run("./tests/ppu/scanline.nes", 60, true);
+            let writes: Vec<_> = (0..nes.register_write_count())
+                .map(|index| nes.register_write(index))
+                .take_while(|write| write.scanline < 168)
+                .collect();
+            let scanlines: Vec<_> = (48..96).chain(120..168).collect();
+            assert_eq!(writes.len(), 2 * scanlines.len());
+            for (pair, &scanline) in writes.chunks_exact(2).zip(scanlines.iter()) {
+                let (addr, vals) = if scanline < 120 {
+                    (0x2001, (0x16, 0x1E))
+                } else {
+                    (0x2000, (0x80, 0x90))
+                };
+                assert_eq!((pair[0].scanline, pair[1].scanline), (scanline, scanline));
+                assert_eq!((pair[0].addr, pair[1].addr), (addr, addr));
+                assert_eq!((pair[0].val, pair[1].val), vals);
+                assert!(pair[0].dot < pair[1].dot);
+            }
+            // The registers are switched back by the start of each scanline.
+            for scanline in 0..168 {
+                let state = nes.scanline_state(scanline);
+                assert_eq!((state.ppu_ctrl, state.ppu_mask), (0x90, 0x1E));
+            }
+        }
+
+        #[test]
+        fn test_scanline_states() {
+            let nes = run("./tests/ppu/color_test.nes", 60, true);
+            for scanline in 0..262 {
+                let state = nes.scanline_state(scanline);
+                assert_eq!(state.ppu_ctrl, 0x80);
+                assert_eq!(state.ppu_mask, 0x1E);
+                assert_eq!(state.mirroring_mode(), MirroringMode::Horizontal);
+                for index in 0..8 {
+                    assert_eq!(state.chr_bank(index), index);
+                }
+            }
+            // The palette is written during vertical blank, which changes t.
+            for scanline in 0..=240 {
+                assert_eq!(nes.scanline_state(scanline).t, 0x0C00);
+            }
+            // The coarse y scroll of v is incremented on each visible scanline.
+            assert_eq!((nes.scanline_state(100).v >> 5) & 0x1F, 12);
+        }
+
+        #[test]
+        fn test_disabled() {
+            let nes = run("./tests/ppu/color_test.nes", 60, false);
+            assert_eq!(nes.register_write_count(), 0);
+            assert_eq!(nes.scanline_state(0), Default::default());
+        }
+
+        #[test]
+        #[should_panic]
+        fn test_invalid_scanline() {
+            let nes = run("./tests/ppu/color_test.nes", 1, true);
+            nes.scanline_state(262);
+        }
+    }
+
+    mod hd_pack {
+        use crate::tests::run_rom;
+        use crate::Nes;
+
+        const RED: [u8; 4] = [0xFF, 0x00, 0x00, 0xFF];
+        const BLUE: [u8; 4] = [0x00, 0x00, 0xFF, 0xFF];
+        const SPRITE_HIT_ROM: &str = "./tests/ppu/sprite_hit/01-basics.nes";
+
+        // Runs a ROM with an HD pack whose images are each a single color.
+        fn run(path: &str, hires: &str, images: &[(usize, usize, [u8; 4])]) -> Box<Nes> {
+            let mut nes = run_rom(path, 0);
+            nes.load_hd_pack(hires);
+            for (index, (width, height, color)) in images.iter().enumerate() {
+                let pixels: Vec<_> = color
+                    .iter()
+                    .cycle()
+                    .take(width * height * 4)
+                    .cloned()
+                    .collect();
+                nes.load_hd_pack_image(index, *width, *height, &pixels);
+            }
+            for _ in 0..60 {
+                nes.step_frame();
+            }
+            nes
+        }
+
+        fn color_count(nes: &Nes, color: [u8; 4]) -> usize {
+            let hd_pack = nes.hd_pack.as_ref().unwrap();
+            hd_pack
+                .buffer
+                .chunks_exact(4)
+                .filter(|pixel| *pixel == color)
+                .count()
+        }
+
+        fn background_tile_count(nes: &Nes, tile_index: usize) -> usize {
+            nes.ppu
+                .pixel_sources
+                .pixels
+                .iter()
+                .filter(|pixel| pixel.background.map(|tile| tile.tile_index) == Some(tile_index))
+                .count()
+        }
+
+        #[test]
+        fn test_unreplaced_tiles() {
+            let nes = run(SPRITE_HIT_ROM, "<ver>106\n<scale>3\n", &[]);
+            assert_eq!((nes.hd_image_width(), nes.hd_image_height()), (768, 720));
+            let hd_pack = nes.hd_pack.as_ref().unwrap();
+            assert_eq!(hd_pack.buffer.len(), nes.hd_image_buffer_len());
+            for y in 0..720 {
+                for x in 0..768 {
+                    let offset = ((y / 3) * 256 + x / 3) * 4;
+                    let hd_offset = (y * 768 + x) * 4;
+                    assert_eq!(
+                        nes.ppu.buffer[offset..offset + 4],
+                        hd_pack.buffer[hd_offset..hd_offset + 4],
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn test_tile_rules() {
+            let hires = "<scale>2\n<img>red.png\n<tile>0,73,0F303030,0,0,1,N\n";
+            let nes = run(SPRITE_HIT_ROM, hires, &[(16, 16, RED)]);
+            assert_eq!(nes.hd_pack_image_count(), 1);
+            assert_eq!(nes.hd_pack_image_name(0), "red.png");
+            assert_eq!(
+                color_count(&nes, RED),
+                background_tile_count(&nes, 0x73) * 4
+            );
+            assert!(color_count(&nes, RED) > 0);
+
+            // The palette has to match unless the rule is a default tile.
+            let hires = "<scale>2\n<img>red.png\n<tile>0,73,0F161616,0,0,1,N\n";
+            let nes = run(SPRITE_HIT_ROM, hires, &[(16, 16, RED)]);
+            assert_eq!(color_count(&nes, RED), 0);
+            let hires = "<scale>2\n<img>red.png\n<tile>0,73,0F161616,0,0,1,Y\n";
+            let nes = run(SPRITE_HIT_ROM, hires, &[(16, 16, RED)]);
+            assert_eq!(
+                color_count(&nes, RED),
+                background_tile_count(&nes, 0x73) * 4
+            );
+        }
+
+        #[test]
+        fn test_tile_data_rules() {
+            let nes = run(SPRITE_HIT_ROM, "<scale>2\n", &[]);
+            let tile = nes
+                .ppu
+                .pixel_sources
+                .pixels
+                .iter()
+                .filter_map(|pixel| pixel.background)
+                .find(|tile| tile.tile_index == 0x73)
+                .unwrap();
+            let tile_data: String = tile
+                .tile_data
+                .iter()
+                .map(|val| format!("{:02X}", val))
+                .collect();
+            let hires = format!(
+                "<scale>2\n<img>red.png\n<tile>0,{},0F303030,0,0,1,N\n",
+                tile_data
+            );
+            let nes = run(SPRITE_HIT_ROM, &hires, &[(16, 16, RED)]);
+            assert_eq!(
+                color_count(&nes, RED),
+                background_tile_count(&nes, 0x73) * 4
+            );
+        }
+
+        #[test]
+        fn test_conditions() {
+            let nes = run(SPRITE_HIT_ROM, "<scale>2\n", &[]);
+            let val = nes.cpu.memory().peek_byte(0x0010);
+            for (condition, replaced) in [("[isSet]", true), ("[!isSet]", false)].iter() {
+                let hires = format!(
+                    "<scale>2\n<img>red.png\n\
+                     <condition>isSet,memoryCheckConstant,0010,==,{:02X}\n\
+                     {}<tile>0,73,0F303030,0,0,1,N\n",
+                    val, condition,
+                );
+                let nes = run(SPRITE_HIT_ROM, &hires, &[(16, 16, RED)]);
+                assert_eq!(color_count(&nes, RED) > 0, *replaced);
+            }
+
+            // Tiles that are not next to the tile are not replaced.
+            let hires = "<scale>2\n<img>red.png\n\
+                         <condition>nextToSelf,tileNearby,8,0,73,0F303030\n\
+                         [nextToSelf]<tile>0,73,0F303030,0,0,1,N\n";
+            let nes = run(SPRITE_HIT_ROM, hires, &[(16, 16, RED)]);
+            assert!(color_count(&nes, RED) < background_tile_count(&nes, 0x73) * 4);
+        }
+
+        #[test]
+        fn test_sprite_conditions() {
+            // None of the sprites are flipped or behind the background.
+            let hires = "<scale>2\n<img>red.png\n[hmirror]<tile>0,A0,00162D30,0,0,1,N\n";
+            let nes = run("./tests/ppu/color_test.nes", hires, &[(16, 16, RED)]);
+            assert_eq!(color_count(&nes, RED), 0);
+            let hires = "<scale>2\n<img>red.png\n[!bgpriority]<tile>0,A0,00162D30,0,0,1,N\n";
+            let nes = run("./tests/ppu/color_test.nes", hires, &[(16, 16, RED)]);
+            assert!(color_count(&nes, RED) > 0);
+        }
+
+        #[test]
+        fn test_background() {
+            let hires = "<scale>2\n<background>blue.png,1.0\n";
+            let nes = run("./tests/ppu/color_test.nes", hires, &[(512, 480, BLUE)]);
+            // The background image replaces the backdrop everywhere except for the sprites.
+            let sprite_count = nes
+                .ppu
+                .pixel_sources
+                .pixels
+                .iter()
+                .filter(|pixel| pixel.sprite.is_some())
+                .count();
+            assert_eq!(color_count(&nes, BLUE), (256 * 240 - sprite_count) * 4);
+        }
+
+        #[test]
+        #[should_panic]
+        fn test_invalid_image() {
+            let mut nes = Nes::default();
+            nes.load_hd_pack("<img>red.png\n");
+            nes.load_hd_pack_image(0, 16, 16, &[0; 16 * 15 * 4]);
+        }
+    }
+
+    mod ntsc {
+        use crate::tests::run_rom;
+        use crate::Nes;
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::Hasher;
+
+        fn ntsc_image(nes: &Nes) -> &[u8] {
+            unsafe {
+                std::slice::from_raw_parts(nes.ntsc_image_buffer(), nes.ntsc_image_buffer_len())
+            }
+        }
+
+        fn ntsc_hash(path: &str, frames: usize) -> u64 {
+            let mut nes = run_rom(path, 0);
+            nes.set_ntsc_filter_enabled(true);
+            for _ in 0..frames {
+                nes.step_frame();
+            }
+            let mut hasher = DefaultHasher::new();
+            hasher.write(ntsc_image(&nes));
+            hasher.finish()
+        }
+
+        #[test]
+        fn test_color_test() {
+            assert_eq!(
+                ntsc_hash("./tests/ppu/color_test.nes", 30),
+                0x6D6D_E67E_54A0_BB8E
+            );
+        }
+
+        #[test]
+        fn test_full_palette() {
+            assert_eq!(
+                ntsc_hash("./tests/ppu/full_palette.nes", 30),
+                0x783D_7A33_AD88_E36D
+            );
+        }
+
+        #[test]
+        fn test_merge_fields() {
+            let mut nes = run_rom("./tests/ppu/color_test.nes", 0);
+            nes.set_ntsc_filter_enabled(true);
+            for _ in 0..30 {
+                nes.step_frame();
+            }
+            // The image is static, but the phase of the subcarrier alternates between frames.
+            let image = ntsc_image(&nes).to_vec();
+            nes.step_frame();
+            assert_ne!(ntsc_image(&nes), &image[..]);
+
+            nes.set_ntsc_merge_fields(true);
+            nes.step_frame();
+            let image = ntsc_image(&nes).to_vec();
+            nes.step_frame();
+            assert_eq!(ntsc_image(&nes), &image[..]);
+        }
+
+        #[test]
+        fn test_saturation() {
+            let mut nes = run_rom("./tests/ppu/full_palette.nes", 0);
+            nes.set_ntsc_filter_enabled(true);
+            nes.set_ntsc_saturation(0.0);
+            for _ in 0..30 {
+                nes.step_frame();
+            }
+            assert_eq!(nes.ntsc_image_buffer_len(), 602 * 240 * 4);
+            for pixel in ntsc_image(&nes).chunks(4) {
+                assert_eq!(pixel[0], pixel[1]);
+                assert_eq!(pixel[1], pixel[2]);
+            }
+        }
+    }
+
+    mod scale {
+        use crate::tests::run_rom;
+        use crate::{scale_image, Scaler};
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::Hasher;
+
+        const SCALERS: [Scaler; 8] = [
+            Scaler::Scale2x,
+            Scaler::Scale3x,
+            Scaler::EdgeBlend2x,
+            Scaler::EdgeBlend3x,
+            Scaler::EdgeBlend4x,
+            Scaler::Xbr2x,
+            Scaler::Xbr3x,
+            Scaler::Xbr4x,
+        ];
+
+        fn scale(scaler: Scaler, src: &[u8], width: usize, height: usize) -> Vec<u8> {
+            let factor = scaler.factor();
+            let mut dst = vec![0; src.len() * factor * factor];
+            scale_image(scaler, src, width, height, &mut dst);
+            dst
+        }
+
+        #[test]
+        fn test_scale2x() {
+            // Two white pixels that meet diagonally, whose corners are filled in towards each other.
+            let (b, w) = ([0x00, 0x00, 0x00, 0xFF], [0xFF, 0xFF, 0xFF, 0xFF]);
+            let src: Vec<u8> = [w, b, b, w].iter().flatten().copied().collect();
+            let dst = scale(Scaler::Scale2x, &src, 2, 2);
+            let expected: Vec<u8> = [w, w, b, b, w, b, w, b, b, w, b, w, b, b, w, w]
+                .iter()
+                .flatten()
+                .copied()
+                .collect();
+            assert_eq!(dst, expected);
+        }
+
+        #[test]
+        fn test_edge_blend() {
+            // Two white pixels that meet diagonally. Only the subpixels at the corners where a pixel
+            // meets two neighbors of the other color are blended towards that color.
+            let (b, w) = ([0x00, 0x00, 0x00, 0xFF], [0xFF, 0xFF, 0xFF, 0xFF]);
+            let src: Vec<u8> = [w, b, b, w].iter().flatten().copied().collect();
+            let dst = scale(Scaler::EdgeBlend2x, &src, 2, 2);
+            let (dark, light) = ([0x60, 0x60, 0x60, 0xFF], [0x9F, 0x9F, 0x9F, 0xFF]);
+            let expected: Vec<u8> = [w, w, b, b, w, light, dark, b, b, dark, light, w, b, b, w, w]
+                .iter()
+                .flatten()
+                .copied()
+                .collect();
+            assert_eq!(dst, expected);
+        }
+
+        #[test]
+        fn test_flat_image() {
+            let src = [0x12, 0x34, 0x56, 0xFF].repeat(8 * 8);
+            for scaler in SCALERS.iter() {
+                let dst = scale(*scaler, &src, 8, 8);
+                assert_eq!(dst.len(), 8 * 8 * 4 * scaler.factor().pow(2));
+                assert_eq!(dst, [0x12, 0x34, 0x56, 0xFF].repeat(dst.len() / 4));
+            }
+        }
+
+        #[test]
+        fn test_color_test() {
+            // The hashes catch regressions in the scalers, whose behavior is checked by the tests
+            // above.
+            let nes = run_rom("./tests/ppu/color_test.nes", 30);
+            let hashes = [
+                0x75CC_02BE_6412_D72B,
+                0xBB70_4647_E0DF_27AE,
+                0xC15B_674A_B676_10E6,
+                0x59A3_6AA4_4DD6_5C63,
+                0x6797_2F54_A2C4_E6F0,
+                0x6C04_40A3_D889_BA0E,
+                0x739B_CF55_FEFD_11F3,
+                0x7DC1_8AEF_E8D7_86E5,
+            ];
+            for (scaler, hash) in SCALERS.iter().zip(hashes.iter()) {
+                let mut hasher = DefaultHasher::new();
+                hasher.write(&scale(*scaler, &nes.ppu.buffer, 256, 240));
+                assert_eq!(hasher.finish(), *hash, "{:?}", scaler);
+            }
+        }
+
+        #[test]
+        #[should_panic(expected = "[SCALE] Expected destination image to be 64 bytes.")]
+        fn test_invalid_destination() {
+            scale_image(Scaler::Scale2x, &[0; 16], 2, 2, &mut [0; 16]);
+        }
+    }
+
+    mod symbols {
+        use crate::Nes;
+
+        // Builds a UxROM image with four 16K PRG ROM banks. The fixed bank at $C000 switches
+        // bank 2 into $8000.
+        fn uxrom_rom() -> Vec<u8> {
+            let mut buffer = vec![0; 0x10 + 4 * 0x4000];
+            buffer[0..4].copy_from_slice(b"NES\x1A");
+            buffer[4] = 4;
+            buffer[6] = 0x20;
+            // LDA #$02; STA $8000; KIL
+            let program = [0xA9, 0x02, 0x8D, 0x00, 0x80, 0x02];
+            let fixed_bank = 0x10 + 3 * 0x4000;
+            buffer[fixed_bank..fixed_bank + program.len()].copy_from_slice(&program);
+            buffer[fixed_bank + 0x3FFD] = 0xC0;
+            buffer
+        }
+
+        #[test]
+        fn test_nl_symbols() {
+            let mut nes = Nes::default();
+            nes.load_rom(&uxrom_rom());
+            assert_eq!(nes.load_nl_symbols("$8000#bank_0_start#\n", Some(0)), 1);
+            assert_eq!(
+                nes.load_nl_symbols("$8000#bank_2_start#\n$8010##\n", Some(2)),
+                1
+            );
+            assert_eq!(
+                nes.load_nl_symbols("$C000#reset_handler#Entry point\n", Some(3)),
+                1
+            );
+            assert_eq!(nes.load_nl_symbols("$0010#player_x#\n", None), 1);
+
+            assert_eq!(nes.symbol_at(0x8000).as_deref(), Some("bank_0_start"));
+            assert_eq!(nes.symbol_address("bank_2_start"), None);
+            assert_eq!(nes.symbol_address("reset_handler"), Some(0xC000));
+            assert_eq!(nes.symbol_address("player_x"), Some(0x0010));
+            assert_eq!(nes.symbol_at(0x0810).as_deref(), Some("player_x"));
+
+            nes.step_frame();
+            assert_eq!(nes.symbol_at(0x8000).as_deref(), Some("bank_2_start"));
+            assert_eq!(nes.symbol_address("bank_0_start"), None);
+            assert_eq!(nes.symbol_address("bank_2_start"), Some(0x8000));
+        }
+
+        #[test]
+        fn test_dbg_symbols() {
+            let dbg = concat!(
+                "version\tmajor=2,minor=0\n",
+                "seg\tid=0,name=\"HEADER\",start=0x000000,size=0x0010,addrsize=absolute,type=ro,oname=\"game.nes\",ooffs=0\n",
+                "seg\tid=1,name=\"BANK2\",start=0x008000,size=0x4000,addrsize=absolute,type=ro,oname=\"game.nes\",ooffs=32784\n",
+                "seg\tid=2,name=\"FIXED\",start=0x00C000,size=0x4000,addrsize=absolute,type=ro,oname=\"game.nes\",ooffs=49168\n",
+                "seg\tid=3,name=\"ZEROPAGE\",start=0x000000,size=0x0010,addrsize=zeropage,type=rw\n",
+                "sym\tid=0,name=\"bank_2_start\",addrsize=absolute,scope=0,def=1,val=0x8000,seg=1,type=lab\n",
+                "sym\tid=1,name=\"reset_handler\",addrsize=absolute,scope=0,def=2,val=0xC000,seg=2,type=lab\n",
+                "sym\tid=2,name=\"player_x\",addrsize=zeropage,scope=0,def=3,val=0x10,seg=3,type=lab\n",
+                "sym\tid=3,name=\"SPEED\",addrsize=zeropage,scope=0,def=4,val=0x3,type=equ\n",
+            );
+            let mut nes = Nes::default();
+            nes.load_rom(&uxrom_rom());
+            assert_eq!(nes.load_dbg_symbols(dbg), 3);
+
+            assert_eq!(nes.symbol_address("reset_handler"), Some(0xC000));
+            assert_eq!(nes.symbol_address("player_x"), Some(0x0010));
+            assert_eq!(nes.symbol_address("SPEED"), None);
+            assert_eq!(nes.symbol_address("bank_2_start"), None);
+            nes.step_frame();
+            assert_eq!(nes.symbol_address("bank_2_start"), Some(0x8000));
+            assert_eq!(nes.symbol_at(0xC000).as_deref(), Some("reset_handler"));
+
+            nes.clear_symbols();
+            assert_eq!(nes.symbol_at(0xC000), None);
+        }
     }
 }
@@ -26,28 +26,56 @@ impl CNROM {
 
 impl Mapper for CNROM {
     fn read_byte(&self, addr: u16) -> u8 {
-        let addr = addr as usize;
         match addr {
-            0x0000..=0x1FFF => {
-                let addr = self.chr_rom_bank as usize * 0x2000 + addr;
-                self.cartridge.read_chr_rom(addr)
-            }
-            0x8000..=0xFFFF if self.cartridge.prg_rom_len() == 0x8000 => {
-                self.cartridge.read_prg_rom(addr - 0x8000)
+            0x0000..=0x1FFF => self.cartridge.read_chr_rom(self.chr_rom_address(addr)),
+            0x6000..=0x7FFF if self.cartridge.has_prg_ram() => {
+                let addr = (addr as usize - 0x6000) % self.cartridge.prg_ram_len();
+                self.cartridge.read_prg_ram(addr)
             }
-            0x8000..=0xFFFF => self.cartridge.read_prg_rom((addr - 0x8000) % 0x4000),
+            0x8000..=0xFFFF => match self.prg_rom_address(addr) {
+                Some(addr) => self.cartridge.read_prg_rom(addr),
+                None => 0,
+            },
             _ => 0,
         }
     }
 
     fn write_byte(&mut self, addr: u16, val: u8) {
         let addr = addr as usize;
-        if let 0x8000..=0xFFFF = addr {
-            self.chr_rom_bank = val & 0x03;
-            debug!("[CNROM] Write chr rom bank: {}.", self.chr_rom_bank);
+        match addr {
+            0x6000..=0x7FFF if self.cartridge.has_prg_ram() => {
+                let addr = (addr - 0x6000) % self.cartridge.prg_ram_len();
+                self.cartridge.write_prg_ram(addr, val);
+            }
+            0x8000..=0xFFFF => {
+                self.chr_rom_bank = val & 0x03;
+                debug!("[CNROM] Write chr rom bank: {}.", self.chr_rom_bank);
+            }
+            _ => {}
+        }
+    }
+
+    fn is_prg_mapped(&self, addr: u16) -> bool {
+        match addr {
+            0x6000..=0x7FFF => self.cartridge.has_prg_ram(),
+            0x8000..=0xFFFF => true,
+            _ => false,
+        }
+    }
+
+    fn prg_rom_address(&self, addr: u16) -> Option<usize> {
+        let addr = addr as usize;
+        match addr {
+            0x8000..=0xFFFF if self.cartridge.prg_rom_len() == 0x8000 => Some(addr - 0x8000),
+            0x8000..=0xFFFF => Some((addr - 0x8000) % 0x4000),
+            _ => None,
         }
     }
 
+    fn chr_rom_address(&self, addr: u16) -> usize {
+        self.chr_rom_bank as usize * 0x2000 + addr as usize
+    }
+
     fn chr_bank(&self, index: usize) -> *const u8 {
         self.cartridge
             .chr_bank(self.chr_rom_bank as usize * 8 + index)
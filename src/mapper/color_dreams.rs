@@ -28,16 +28,12 @@ impl ColorDreams {
 
 impl Mapper for ColorDreams {
     fn read_byte(&self, addr: u16) -> u8 {
-        let addr = addr as usize;
         match addr {
-            0x0000..=0x1FFF => {
-                let addr = self.chr_rom_bank as usize * 0x2000 + addr;
-                self.cartridge.read_chr_rom(addr)
-            }
-            0x8000..=0xFFFF => {
-                let addr = self.prg_rom_bank as usize * 0x8000 + addr - 0x8000;
-                self.cartridge.read_prg_rom(addr)
-            }
+            0x0000..=0x1FFF => self.cartridge.read_chr_rom(self.chr_rom_address(addr)),
+            0x8000..=0xFFFF => match self.prg_rom_address(addr) {
+                Some(addr) => self.cartridge.read_prg_rom(addr),
+                None => 0,
+            },
             _ => 0,
         }
     }
@@ -59,6 +55,18 @@ impl Mapper for ColorDreams {
         }
     }
 
+    fn prg_rom_address(&self, addr: u16) -> Option<usize> {
+        let addr = addr as usize;
+        match addr {
+            0x8000..=0xFFFF => Some(self.prg_rom_bank as usize * 0x8000 + addr - 0x8000),
+            _ => None,
+        }
+    }
+
+    fn chr_rom_address(&self, addr: u16) -> usize {
+        self.chr_rom_bank as usize * 0x2000 + addr as usize
+    }
+
     fn chr_bank(&self, index: usize) -> *const u8 {
         self.cartridge
             .chr_bank(self.chr_rom_bank as usize * 8 + index)
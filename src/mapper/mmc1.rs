@@ -164,42 +164,16 @@ impl MMC1 {
 // TODO: Handle differences between variants.
 impl Mapper for MMC1 {
     fn read_byte(&self, addr: u16) -> u8 {
-        let addr = addr as usize;
         match addr {
-            0x0000..=0x0FFF => {
-                let bank = match self.r.chr_rom_bank_mode {
-                    ChrRomBankMode::Switch8K => self.r.chr_rom_bank_0 as usize & !0x01,
-                    ChrRomBankMode::Switch4K => self.r.chr_rom_bank_0 as usize,
-                } as usize;
-                self.cartridge.read_chr_rom(bank * 0x1000 + addr)
-            }
-            0x1000..=0x1FFF => {
-                let bank = match self.r.chr_rom_bank_mode {
-                    ChrRomBankMode::Switch8K => self.r.chr_rom_bank_0 as usize | 0x01,
-                    ChrRomBankMode::Switch4K => self.r.chr_rom_bank_1 as usize,
-                };
-                self.cartridge.read_chr_rom(bank * 0x1000 + addr - 0x1000)
-            }
+            0x0000..=0x1FFF => self.cartridge.read_chr_rom(self.chr_rom_address(addr)),
             0x6000..=0x7FFF if self.r.prg_ram_enabled => {
-                let addr = (addr - 0x6000) % self.cartridge.prg_ram_len();
+                let addr = (addr as usize - 0x6000) % self.cartridge.prg_ram_len();
                 self.cartridge.read_prg_ram(addr)
             }
-            0x8000..=0xBFFF => {
-                let bank = match self.r.prg_rom_bank_mode {
-                    PrgRomBankMode::Switch32K => self.r.prg_rom_bank as usize & !0x01,
-                    PrgRomBankMode::FixFirstBank => 0,
-                    PrgRomBankMode::FixLastBank => self.r.prg_rom_bank as usize,
-                };
-                self.cartridge.read_prg_rom(bank * 0x4000 + addr - 0x8000)
-            }
-            0xC000..=0xFFFF => {
-                let bank = match self.r.prg_rom_bank_mode {
-                    PrgRomBankMode::Switch32K => self.r.prg_rom_bank as usize | 0x01,
-                    PrgRomBankMode::FixFirstBank => self.r.prg_rom_bank as usize,
-                    PrgRomBankMode::FixLastBank => self.cartridge.prg_rom_len() / 0x4000 - 1,
-                };
-                self.cartridge.read_prg_rom(bank * 0x4000 + addr - 0xC000)
-            }
+            0x8000..=0xFFFF => match self.prg_rom_address(addr) {
+                Some(addr) => self.cartridge.read_prg_rom(addr),
+                None => 0,
+            },
             _ => 0,
         }
     }
@@ -252,6 +226,57 @@ impl Mapper for MMC1 {
         }
     }
 
+    fn is_prg_mapped(&self, addr: u16) -> bool {
+        match addr {
+            0x6000..=0x7FFF => self.r.prg_ram_enabled && self.cartridge.has_prg_ram(),
+            0x8000..=0xFFFF => true,
+            _ => false,
+        }
+    }
+
+    fn prg_rom_address(&self, addr: u16) -> Option<usize> {
+        let addr = addr as usize;
+        match addr {
+            0x8000..=0xBFFF => {
+                let bank = match self.r.prg_rom_bank_mode {
+                    PrgRomBankMode::Switch32K => self.r.prg_rom_bank as usize & !0x01,
+                    PrgRomBankMode::FixFirstBank => 0,
+                    PrgRomBankMode::FixLastBank => self.r.prg_rom_bank as usize,
+                };
+                Some(bank * 0x4000 + addr - 0x8000)
+            }
+            0xC000..=0xFFFF => {
+                let bank = match self.r.prg_rom_bank_mode {
+                    PrgRomBankMode::Switch32K => self.r.prg_rom_bank as usize | 0x01,
+                    PrgRomBankMode::FixFirstBank => self.r.prg_rom_bank as usize,
+                    PrgRomBankMode::FixLastBank => self.cartridge.prg_rom_len() / 0x4000 - 1,
+                };
+                Some(bank * 0x4000 + addr - 0xC000)
+            }
+            _ => None,
+        }
+    }
+
+    fn chr_rom_address(&self, addr: u16) -> usize {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x0FFF => {
+                let bank = match self.r.chr_rom_bank_mode {
+                    ChrRomBankMode::Switch8K => self.r.chr_rom_bank_0 as usize & !0x01,
+                    ChrRomBankMode::Switch4K => self.r.chr_rom_bank_0 as usize,
+                };
+                bank * 0x1000 + addr
+            }
+            _ => {
+                let bank = match self.r.chr_rom_bank_mode {
+                    ChrRomBankMode::Switch8K => self.r.chr_rom_bank_0 as usize | 0x01,
+                    ChrRomBankMode::Switch4K => self.r.chr_rom_bank_1 as usize,
+                };
+                bank * 0x1000 + addr - 0x1000
+            }
+        }
+    }
+
     fn chr_bank(&self, mut index: usize) -> *const u8 {
         index = if index < 4 {
             let bank = match self.r.chr_rom_bank_mode {
@@ -193,18 +193,15 @@ impl MMC3 {
 
 impl Mapper for MMC3 {
     fn read_byte(&self, addr: u16) -> u8 {
-        let addr = addr as usize;
         match addr {
-            0x0000..=0x1FFF => {
-                let addr = self.r.get_chr_rom_address(addr);
-                self.cartridge.read_chr_rom(addr)
-            }
-            0x6000..=0x7FFF if self.r.prg_ram_enabled => self.cartridge.read_prg_ram(addr - 0x6000),
-            0x8000..=0xFFFF => {
-                let prg_rom_banks = self.cartridge.prg_rom_len() / 0x2000;
-                let addr = self.r.get_prg_rom_address(addr, prg_rom_banks);
-                self.cartridge.read_prg_rom(addr)
+            0x0000..=0x1FFF => self.cartridge.read_chr_rom(self.chr_rom_address(addr)),
+            0x6000..=0x7FFF if self.r.prg_ram_enabled => {
+                self.cartridge.read_prg_ram(addr as usize - 0x6000)
             }
+            0x8000..=0xFFFF => match self.prg_rom_address(addr) {
+                Some(addr) => self.cartridge.read_prg_rom(addr),
+                None => 0,
+            },
             _ => 0,
         }
     }
@@ -231,6 +228,28 @@ impl Mapper for MMC3 {
         }
     }
 
+    fn is_prg_mapped(&self, addr: u16) -> bool {
+        match addr {
+            0x6000..=0x7FFF => self.r.prg_ram_enabled && self.cartridge.has_prg_ram(),
+            0x8000..=0xFFFF => true,
+            _ => false,
+        }
+    }
+
+    fn prg_rom_address(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xFFFF => {
+                let prg_rom_banks = self.cartridge.prg_rom_len() / 0x2000;
+                Some(self.r.get_prg_rom_address(addr as usize, prg_rom_banks))
+            }
+            _ => None,
+        }
+    }
+
+    fn chr_rom_address(&self, addr: u16) -> usize {
+        self.r.get_chr_rom_address(addr as usize)
+    }
+
     fn chr_bank(&self, mut index: usize) -> *const u8 {
         index = match self.r.chr_rom_bank_mode {
             ChrRomBankMode::Two2KFour1K => match index {
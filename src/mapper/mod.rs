@@ -35,6 +35,16 @@ pub fn from_cartridge(cartridge: Cartridge) -> Box<dyn Mapper> {
 pub trait Mapper {
     fn read_byte(&self, addr: u16) -> u8;
     fn write_byte(&mut self, addr: u16, val: u8);
+    // Returns `true` if the cartridge drives the CPU data bus when `addr` is read. Reads from
+    // unmapped addresses return open bus.
+    fn is_prg_mapped(&self, addr: u16) -> bool {
+        addr >= 0x8000
+    }
+    // Returns the PRG ROM address that the CPU address `addr` is mapped to by the current banking,
+    // or `None` if `addr` is not mapped to PRG ROM.
+    fn prg_rom_address(&self, addr: u16) -> Option<usize>;
+    // Returns the CHR address that the PPU address `addr` is mapped to by the current banking.
+    fn chr_rom_address(&self, addr: u16) -> usize;
     fn chr_bank(&self, index: usize) -> *const u8;
     fn mirroring_mode(&self) -> MirroringMode;
     fn attach_bus(&mut self, _bus: Bus) {}
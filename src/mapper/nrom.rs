@@ -21,17 +21,16 @@ impl NROM {
 
 impl Mapper for NROM {
     fn read_byte(&self, addr: u16) -> u8 {
-        let addr = addr as usize;
         match addr {
-            0x0000..=0x1FFF => self.cartridge.read_chr_rom(addr),
+            0x0000..=0x1FFF => self.cartridge.read_chr_rom(self.chr_rom_address(addr)),
             0x6000..=0x7FFF => {
-                let addr = (addr - 0x6000) % self.cartridge.prg_ram_len();
+                let addr = (addr as usize - 0x6000) % self.cartridge.prg_ram_len();
                 self.cartridge.read_prg_ram(addr)
             }
-            0x8000..=0xFFFF => {
-                let addr = (addr - 0x8000) % self.cartridge.prg_rom_len();
-                self.cartridge.read_prg_rom(addr)
-            }
+            0x8000..=0xFFFF => match self.prg_rom_address(addr) {
+                Some(addr) => self.cartridge.read_prg_rom(addr),
+                None => 0,
+            },
             _ => 0,
         }
     }
@@ -48,6 +47,25 @@ impl Mapper for NROM {
         }
     }
 
+    fn is_prg_mapped(&self, addr: u16) -> bool {
+        match addr {
+            0x6000..=0x7FFF => self.cartridge.has_prg_ram(),
+            0x8000..=0xFFFF => true,
+            _ => false,
+        }
+    }
+
+    fn prg_rom_address(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xFFFF => Some((addr as usize - 0x8000) % self.cartridge.prg_rom_len()),
+            _ => None,
+        }
+    }
+
+    fn chr_rom_address(&self, addr: u16) -> usize {
+        addr as usize
+    }
+
     fn chr_bank(&self, index: usize) -> *const u8 {
         self.cartridge.chr_bank(index)
     }
@@ -35,23 +35,12 @@ impl UxROM {
 
 impl Mapper for UxROM {
     fn read_byte(&self, addr: u16) -> u8 {
-        let addr = addr as usize;
         match addr {
-            0x0000..=0x1FFF => self.cartridge.read_chr_rom(addr),
-            0x8000..=0xBFFF => {
-                let bank = match self.variant {
-                    Variant::UNROM | Variant::UN1ROM => self.prg_rom_bank as usize,
-                    Variant::Mapper180 => 0,
-                };
-                self.cartridge.read_prg_rom(bank * 0x4000 + addr - 0x8000)
-            }
-            0xC000..=0xFFFF => {
-                let bank = match self.variant {
-                    Variant::UNROM | Variant::UN1ROM => self.cartridge.prg_rom_len() / 0x4000 - 1,
-                    Variant::Mapper180 => self.prg_rom_bank as usize,
-                };
-                self.cartridge.read_prg_rom(bank * 0x4000 + addr - 0xC000)
-            }
+            0x0000..=0x1FFF => self.cartridge.read_chr_rom(self.chr_rom_address(addr)),
+            0x8000..=0xFFFF => match self.prg_rom_address(addr) {
+                Some(addr) => self.cartridge.read_prg_rom(addr),
+                None => 0,
+            },
             _ => 0,
         }
     }
@@ -71,6 +60,31 @@ impl Mapper for UxROM {
         }
     }
 
+    fn prg_rom_address(&self, addr: u16) -> Option<usize> {
+        let addr = addr as usize;
+        match addr {
+            0x8000..=0xBFFF => {
+                let bank = match self.variant {
+                    Variant::UNROM | Variant::UN1ROM => self.prg_rom_bank as usize,
+                    Variant::Mapper180 => 0,
+                };
+                Some(bank * 0x4000 + addr - 0x8000)
+            }
+            0xC000..=0xFFFF => {
+                let bank = match self.variant {
+                    Variant::UNROM | Variant::UN1ROM => self.cartridge.prg_rom_len() / 0x4000 - 1,
+                    Variant::Mapper180 => self.prg_rom_bank as usize,
+                };
+                Some(bank * 0x4000 + addr - 0xC000)
+            }
+            _ => None,
+        }
+    }
+
+    fn chr_rom_address(&self, addr: u16) -> usize {
+        addr as usize
+    }
+
     fn chr_bank(&self, index: usize) -> *const u8 {
         self.cartridge.chr_bank(index)
     }
@@ -0,0 +1,112 @@
+// Records the state of the PPU at the start of each scanline and the CPU writes to the PPU
+// registers, which shows how raster effects such as scroll splits and CHR bank switches change
+// the PPU during a frame.
+use super::MirroringMode;
+use std::mem;
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+const SCANLINE_COUNT: usize = 262;
+
+/// The state of the PPU at the start of a scanline.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub struct ScanlineState {
+    /// The current VRAM address.
+    pub v: u16,
+    /// The temporary VRAM address.
+    pub t: u16,
+    /// The fine x scroll.
+    pub x: u8,
+    /// The value of PPUCTRL.
+    pub ppu_ctrl: u8,
+    /// The value of PPUMASK.
+    pub ppu_mask: u8,
+    pub(crate) chr_banks: [usize; 8],
+    pub(crate) mirroring_mode: MirroringMode,
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+impl ScanlineState {
+    /// Returns the 1K bank of CHR memory that is mapped to the PPU address `index * 0x400`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than `8`.
+    pub fn chr_bank(&self, index: usize) -> usize {
+        self.chr_banks[index]
+    }
+
+    /// Returns the nametable mirroring mode.
+    pub fn mirroring_mode(&self) -> MirroringMode {
+        self.mirroring_mode
+    }
+}
+
+/// A CPU write to a PPU register.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub struct RegisterWrite {
+    /// The scanline that the write happened on.
+    pub scanline: u16,
+    /// The dot of the scanline that the write happened on.
+    pub dot: u16,
+    /// The address of the register, from `0x2000` to `0x2007`.
+    pub addr: u16,
+    /// The value that was written.
+    pub val: u8,
+}
+
+#[derive(Default)]
+pub struct EventLog {
+    pub enabled: bool,
+    // The log of the frame that is being drawn.
+    current_scanline_states: Vec<ScanlineState>,
+    current_register_writes: Vec<RegisterWrite>,
+    // The log of the last complete frame.
+    scanline_states: Vec<ScanlineState>,
+    register_writes: Vec<RegisterWrite>,
+}
+
+impl EventLog {
+    pub fn log_scanline(&mut self, scanline: u16, state: ScanlineState) {
+        if self.current_scanline_states.is_empty() {
+            self.current_scanline_states = vec![ScanlineState::default(); SCANLINE_COUNT];
+        }
+        self.current_scanline_states[usize::from(scanline)] = state;
+    }
+
+    pub fn log_register_write(&mut self, write: RegisterWrite) {
+        self.current_register_writes.push(write);
+    }
+
+    // Keeps the log of the frame that just ended and starts the log of the next frame.
+    pub fn end_frame(&mut self) {
+        self.scanline_states = mem::take(&mut self.current_scanline_states);
+        self.register_writes = mem::take(&mut self.current_register_writes);
+    }
+
+    pub fn clear(&mut self) {
+        *self = EventLog {
+            enabled: self.enabled,
+            ..EventLog::default()
+        };
+    }
+
+    // Returns the state of the PPU at the start of `scanline` in the last complete frame, or the
+    // default state if the scanline was not logged.
+    pub fn scanline_state(&self, scanline: usize) -> ScanlineState {
+        if scanline >= SCANLINE_COUNT {
+            panic!("[PPU] Invalid scanline {}.", scanline);
+        }
+        self.scanline_states
+            .get(scanline)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // Returns the register writes of the last complete frame in the order they happened.
+    pub fn register_writes(&self) -> &[RegisterWrite] {
+        &self.register_writes
+    }
+}
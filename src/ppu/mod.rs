@@ -1,37 +1,46 @@
+mod event_log;
+mod palette;
+mod pixel_source;
 mod registers;
+mod viewer;
 
+pub use self::event_log::{EventLog, RegisterWrite, ScanlineState};
+pub use self::palette::Palette;
+use self::pixel_source::TileRef;
+pub use self::pixel_source::{PixelSource, PixelSources, TileSource};
 use self::registers::Registers;
+pub use self::viewer::SpriteInfo;
 use crate::bus::Bus;
-use crate::cpu::Interrupt;
+use crate::cdl;
 #[cfg(not(target_arch = "wasm32"))]
 use crate::BigArray;
 #[cfg(not(target_arch = "wasm32"))]
 use serde_derive::{Deserialize, Serialize};
 use std::mem;
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
 
-const SCREEN_WIDTH: usize = 256;
-const SCREEN_HEIGHT: usize = 240;
-
-// http://www.thealmightyguru.com/Games/Hacking/Wiki/index.php/NES_Palette
-#[rustfmt::skip]
-pub const COLORS: [u32; 64] = [
-    0x007C_7C7C, 0x0000_00FC, 0x0000_00BC, 0x0044_28BC, 0x0094_0084, 0x00A8_0020, 0x00A8_1000, 0x0088_1400, //
-    0x0050_3000, 0x0000_7800, 0x0000_6800, 0x0000_5800, 0x0000_4058, 0x0000_0000, 0x0000_0000, 0x0000_0000, //
-    0x00BC_BCBC, 0x0000_78F8, 0x0000_58F8, 0x0068_44FC, 0x00D8_00CC, 0x00E4_0058, 0x00F8_3800, 0x00E4_5C10, //
-    0x00AC_7C00, 0x0000_B800, 0x0000_A800, 0x0000_A844, 0x0000_8888, 0x0000_0000, 0x0000_0000, 0x0000_0000, //
-    0x00F8_F8F8, 0x003C_BCFC, 0x0068_88FC, 0x0098_78F8, 0x00F8_78F8, 0x00F8_5898, 0x00F8_7858, 0x00FC_A044, //
-    0x00F8_B800, 0x00B8_F818, 0x0058_D854, 0x0058_F898, 0x0000_E8D8, 0x0078_7878, 0x0000_0000, 0x0000_0000, //
-    0x00FC_FCFC, 0x00A4_E4FC, 0x00B8_B8F8, 0x00D8_B8F8, 0x00F8_B8F8, 0x00F8_A4C0, 0x00F0_D0B0, 0x00FC_E0A8, //
-    0x00F8_D878, 0x00D8_F878, 0x00B8_F8B8, 0x00B8_F8D8, 0x0000_FCFC, 0x00F8_D8F8, 0x0000_0000, 0x0000_0000, //
-];
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+const DOTS_PER_SCANLINE: u64 = 341;
+const DOTS_PER_FRAME: u64 = DOTS_PER_SCANLINE * 262;
+// Bits of the PPU I/O latch decay to 0 about 600 ms after they were last refreshed.
+const OPEN_BUS_DECAY_DOTS: u64 = 3_200_000;
 
+/// The arrangement of the nametables in PPU memory.
 #[derive(Clone, Copy, Debug, PartialEq)]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Deserialize, Serialize))]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
 pub enum MirroringMode {
+    /// The top two and the bottom two nametables are mirrors of each other.
     Horizontal = 0,
+    /// The left two and the right two nametables are mirrors of each other.
     Vertical = 1,
+    /// All four nametables are the first nametable.
     Lower = 2,
+    /// All four nametables are the second nametable.
     Upper = 3,
+    /// Each nametable is separate.
     None = 4,
 }
 
@@ -41,6 +50,95 @@ impl Default for MirroringMode {
     }
 }
 
+/// The pixel format of the image buffer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub enum PixelFormat {
+    /// Four bytes per pixel in the order red, green, blue, and alpha.
+    Rgba8888,
+    /// Four bytes per pixel in the order blue, green, red, and alpha.
+    Bgra8888,
+    /// A native endian `u32` per pixel with alpha in the high byte followed by red, green, and
+    /// blue.
+    Argb8888,
+    /// A native endian `u16` per pixel with 5 bits of red in the high bits, 6 bits of green, and 5
+    /// bits of blue.
+    Rgb565,
+    /// Three bytes per pixel in the order red, green, and blue.
+    Rgb888,
+}
+
+impl PixelFormat {
+    /// Returns the number of bytes used by each pixel.
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgba8888 | PixelFormat::Bgra8888 | PixelFormat::Argb8888 => 4,
+            PixelFormat::Rgb565 => 2,
+            PixelFormat::Rgb888 => 3,
+        }
+    }
+
+    // Writes each RGB color to `buffer` in this format.
+    pub(crate) fn write_pixels<I: Iterator<Item = u32>>(self, buffer: &mut [u8], colors: I) {
+        let pixels = buffer.chunks_exact_mut(self.bytes_per_pixel());
+        match self {
+            PixelFormat::Rgba8888 => {
+                for (pixel, color) in pixels.zip(colors) {
+                    let [_, r, g, b] = color.to_be_bytes();
+                    pixel.copy_from_slice(&[r, g, b, 0xFF]);
+                }
+            }
+            PixelFormat::Bgra8888 => {
+                for (pixel, color) in pixels.zip(colors) {
+                    let [_, r, g, b] = color.to_be_bytes();
+                    pixel.copy_from_slice(&[b, g, r, 0xFF]);
+                }
+            }
+            PixelFormat::Argb8888 => {
+                for (pixel, color) in pixels.zip(colors) {
+                    pixel.copy_from_slice(&(0xFF00_0000 | color).to_ne_bytes());
+                }
+            }
+            PixelFormat::Rgb565 => {
+                for (pixel, color) in pixels.zip(colors) {
+                    let [_, r, g, b] = color.to_be_bytes();
+                    let val =
+                        (u16::from(r >> 3) << 11) | (u16::from(g >> 2) << 5) | u16::from(b >> 3);
+                    pixel.copy_from_slice(&val.to_ne_bytes());
+                }
+            }
+            PixelFormat::Rgb888 => {
+                for (pixel, color) in pixels.zip(colors) {
+                    let [_, r, g, b] = color.to_be_bytes();
+                    pixel.copy_from_slice(&[r, g, b]);
+                }
+            }
+        }
+    }
+}
+
+// The number of pixels cropped from each edge of the image buffer.
+#[derive(Clone, Copy, Default)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Overscan {
+    pub fn width(&self) -> usize {
+        SCREEN_WIDTH.saturating_sub(self.left + self.right)
+    }
+
+    pub fn height(&self) -> usize {
+        SCREEN_HEIGHT.saturating_sub(self.top + self.bottom)
+    }
+}
+
+// The number of sprites that can be drawn on a scanline when the sprite limit is removed.
+const MAX_SPRITES: usize = 64;
+
 const MIRRORING_MODE_TABLE: [usize; 20] = [
     0, 0, 1, 1, // Horizontal
     0, 1, 0, 1, // Vertical
@@ -53,6 +151,13 @@ const MIRRORING_MODE_TABLE: [usize; 20] = [
 pub struct Ppu {
     pub r: Registers,
     pub buffer_index: usize,
+    // The 9 bit output of the PPU for each pixel, which is the 6 bit palette index with the 3
+    // emphasis bits of PPUMASK above it.
+    #[cfg_attr(
+        not(target_arch = "wasm32"),
+        serde(skip, default = "Ppu::empty_index_buffer")
+    )]
+    pub index_buffer: [u16; SCREEN_WIDTH * SCREEN_HEIGHT],
     #[cfg_attr(
         not(target_arch = "wasm32"),
         serde(skip, default = "Ppu::empty_buffer")
@@ -61,14 +166,79 @@ pub struct Ppu {
     pub cycle: u16,    // [0, 340]
     pub scanline: u16, // [0, 261]
     pub frame: u64,
+    // Set when $2002 is read one dot before the vblank flag is set, which suppresses the flag for
+    // the frame.
+    v_blank_suppressed: bool,
+    // The level of the NMI output at the end of each of the last 8 dots, most recent first.
+    nmi_line_history: u8,
+    // Set when $2002 is read just after the vblank flag is set, which cancels an NMI that the CPU
+    // has already detected.
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    nmi_cancelled: bool,
+    // Whether the last dot of the pre-render scanline is skipped. This is latched at the end of
+    // dot 338, so enabling rendering after that point does not skip the dot.
+    skip_last_dot: bool,
+    // The phase of the NTSC color subcarrier at the current dot in twelfths of a cycle. Each dot
+    // lasts 8 twelfths of a cycle.
+    phase: u8,
+    // The phase of the color subcarrier at the first pixel of the frame being drawn.
+    drawing_frame_phase: u8,
+    // The phase of the color subcarrier at the first pixel of the last complete frame.
+    pub frame_phase: u8,
+    // The I/O latch between the CPU and the PPU. Reading a write-only register returns the
+    // latch, and each bit decays to 0 if it is not refreshed by a read or a write.
+    open_bus: u8,
+    open_bus_refresh_dots: [u64; 8],
     #[cfg_attr(not(target_arch = "wasm32"), serde(with = "BigArray"))]
     pub primary_oam: [u8; 0x100],
     secondary_oam: [u8; 0x20],
-    is_sprite_0: [bool; 8],
+    // The last byte read from OAM during sprite evaluation, which is returned by $2004 reads
+    // during rendering.
+    oam_buffer: u8,
+    // Sprite evaluation state. `sprite_eval_n` is the index of the sprite in primary OAM and
+    // `sprite_eval_m` is the index of the byte in the sprite.
+    sprite_eval_n: u8,
+    sprite_eval_m: u8,
+    secondary_oam_addr: usize,
+    sprite_in_range: bool,
+    sprite_eval_done: bool,
+    sprite_overflow_counter: u8,
+    sprite_0_in_range: bool,
+    // The sprite in primary OAM that sprite evaluation started at.
+    sprite_eval_start_n: u8,
+    // Sprite output units for the current scanline, which are loaded during dots 257-320 of the
+    // previous scanline.
+    sprite_count: usize,
+    sprite_0_visible: bool,
+    // Only the first 8 are used unless the sprite limit is removed.
+    #[cfg_attr(not(target_arch = "wasm32"), serde(with = "BigArray"))]
+    sprite_low_tile_bytes: [u8; MAX_SPRITES],
+    #[cfg_attr(not(target_arch = "wasm32"), serde(with = "BigArray"))]
+    sprite_high_tile_bytes: [u8; MAX_SPRITES],
+    #[cfg_attr(not(target_arch = "wasm32"), serde(with = "BigArray"))]
+    sprite_attributes: [u8; MAX_SPRITES],
+    #[cfg_attr(not(target_arch = "wasm32"), serde(with = "BigArray"))]
+    sprite_xs: [u8; MAX_SPRITES],
     #[cfg_attr(not(target_arch = "wasm32"), serde(with = "BigArray"))]
     vram: [u8; 0x2000],
     palette_ram: [u8; 0x20],
     #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    pub palette: Palette,
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    pub left_column_masked: bool,
+    // Draws every sprite in range of a scanline instead of the first 8. Sprite evaluation is
+    // unchanged, so the sprite overflow flag and sprite 0 hits are the same as on hardware.
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    pub sprite_limit_removed: bool,
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    pub background_hidden: bool,
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    pub sprites_hidden: bool,
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    pub event_log: EventLog,
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
+    pub pixel_sources: PixelSources,
+    #[cfg_attr(not(target_arch = "wasm32"), serde(skip))]
     bus: Option<Bus>,
 }
 
@@ -78,6 +248,11 @@ impl Ppu {
         [0; SCREEN_WIDTH * SCREEN_HEIGHT * 4]
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    fn empty_index_buffer() -> [u16; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        [0; SCREEN_WIDTH * SCREEN_HEIGHT]
+    }
+
     pub fn new() -> Ppu {
         #[rustfmt::skip]
         let palette_ram = [
@@ -94,15 +269,46 @@ impl Ppu {
         Ppu {
             r: Registers::new(),
             buffer_index: 0,
+            index_buffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
             buffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT * 4],
             cycle: 0,
             scanline: 0,
             frame: 0,
+            v_blank_suppressed: false,
+            nmi_line_history: 0,
+            nmi_cancelled: false,
+            skip_last_dot: false,
+            phase: 0,
+            drawing_frame_phase: 0,
+            frame_phase: 0,
+            open_bus: 0,
+            open_bus_refresh_dots: [0; 8],
             primary_oam: [0; 0x100],
             secondary_oam: [0; 0x20],
-            is_sprite_0: [false; 8],
+            oam_buffer: 0,
+            sprite_eval_n: 0,
+            sprite_eval_m: 0,
+            secondary_oam_addr: 0,
+            sprite_in_range: false,
+            sprite_eval_done: false,
+            sprite_overflow_counter: 0,
+            sprite_0_in_range: false,
+            sprite_eval_start_n: 0,
+            sprite_count: 0,
+            sprite_0_visible: false,
+            sprite_low_tile_bytes: [0; MAX_SPRITES],
+            sprite_high_tile_bytes: [0; MAX_SPRITES],
+            sprite_attributes: [0; MAX_SPRITES],
+            sprite_xs: [0; MAX_SPRITES],
             vram: [0; 0x2000],
             palette_ram,
+            palette: Palette::default(),
+            left_column_masked: false,
+            sprite_limit_removed: false,
+            background_hidden: false,
+            sprites_hidden: false,
+            event_log: EventLog::default(),
+            pixel_sources: PixelSources::default(),
             bus: None,
         }
     }
@@ -118,6 +324,7 @@ impl Ppu {
         self.cycle = 0;
         self.scanline = 0;
         self.frame = 0;
+        self.event_log.clear();
     }
 
     pub fn attach_bus(&mut self, bus: Bus) {
@@ -172,12 +379,23 @@ impl Ppu {
 
             0x3F00..=0x3FFF => {
                 let modulus = if addr % 0x04 == 0 { 0x10 } else { 0x20 };
-                self.palette_ram[((addr - 0x3F00) % modulus) as usize] = val;
+                self.palette_ram[((addr - 0x3F00) % modulus) as usize] = val & 0x3F;
             }
             _ => panic!("[PPU] Invalid write with memory address: {:#06x}.", addr),
         }
     }
 
+    // The NMI output of the PPU, which is connected to the NMI input of the CPU. The CPU samples
+    // its input two dots before the end of its cycle.
+    pub fn nmi_line(&self) -> bool {
+        self.nmi_line_history & 0x04 != 0
+    }
+
+    // Returns `true` if an NMI has been cancelled since the last call.
+    pub fn take_nmi_cancelled(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_cancelled)
+    }
+
     pub fn palettes(&self) -> *const u8 {
         self.palette_ram.as_ptr()
     }
@@ -190,38 +408,76 @@ impl Ppu {
     }
 
     pub fn read_register(&mut self, addr: u16) -> u8 {
+        self.decay_open_bus();
         match addr {
-            // PPUCTRL
-            0x2000 => self.r.last_written_byte,
-            // PPUMASK
-            0x2001 => self.r.last_written_byte,
+            // PPUCTRL, PPUMASK, OAMADDR, PPUSCROLL, and PPUADDR are write-only
+            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 => {}
             // PPUSTATUS
-            0x2002 => self.r.read_ppu_status(),
-            // OAMADDR
-            0x2003 => self.r.last_written_byte,
+            0x2002 => {
+                // https://wiki.nesdev.com/w/index.php/PPU_frame_timing#VBL_Flag_Timing
+                if self.scanline == 241 {
+                    match self.cycle {
+                        0 => self.v_blank_suppressed = true,
+                        1 | 2 => {
+                            self.nmi_line_history = 0;
+                            self.nmi_cancelled = true;
+                        }
+                        _ => {}
+                    }
+                }
+                let val = self.r.read_ppu_status();
+                self.refresh_open_bus(val, 0xE0);
+            }
             // OAMDATA
-            0x2004 => self.primary_oam[self.r.oam_addr as usize],
-            // PPUSCROLL
-            0x2005 => self.r.last_written_byte,
-            // PPUADDR
-            0x2006 => self.r.last_written_byte,
+            0x2004 => {
+                let val = if self.scanline <= 239 && self.r.rendering_enabled {
+                    self.oam_buffer
+                } else {
+                    self.primary_oam[self.r.oam_addr as usize]
+                };
+                self.refresh_open_bus(val, 0xFF);
+            }
             // PPUDATA
             0x2007 => {
-                let mut ret = self.read_byte(self.r.bus_address);
-                if self.r.bus_address < 0x3F00 {
-                    mem::swap(&mut ret, &mut self.r.buffer);
+                let addr = self.r.v & 0x3FFF;
+                if addr < 0x2000 {
+                    self.log_chr_rom(addr, cdl::CHR_READ);
+                }
+                if addr < 0x3F00 {
+                    let buffer = self.read_byte(addr);
+                    let val = mem::replace(&mut self.r.buffer, buffer);
+                    self.refresh_open_bus(val, 0xFF);
                 } else {
-                    self.r.buffer = self.read_byte(self.r.bus_address - 0x1000);
+                    // Palette reads return the palette entry directly and fill the buffer with
+                    // the nametable byte underneath it. The high 2 bits are open bus.
+                    let mut val = self.read_byte(addr);
+                    if self.r.greyscale_enabled {
+                        val &= 0x30;
+                    }
+                    self.r.buffer = self.read_byte(addr - 0x1000);
+                    self.refresh_open_bus(val, 0x3F);
                 }
-                self.r.bus_address += self.r.vram_address_increment;
-                ret
+                self.increment_vram_address();
             }
             _ => panic!("[PPU] Invalid ppu register to read: {:#06x}.", addr),
         }
+        self.open_bus
+    }
+
+    // Logs a write by the CPU to a PPU register. Writes by OAM DMA are not logged.
+    pub fn log_register_write(&mut self, addr: u16, val: u8) {
+        if self.event_log.enabled {
+            self.event_log.log_register_write(RegisterWrite {
+                scanline: self.scanline,
+                dot: self.cycle,
+                addr,
+                val,
+            });
+        }
     }
 
     pub fn write_register(&mut self, addr: u16, val: u8) {
-        self.r.last_written_byte = val;
+        self.refresh_open_bus(val, 0xFF);
         match addr {
             // PPUCTRL
             0x2000 => self.r.write_ppu_ctrl(val),
@@ -233,8 +489,20 @@ impl Ppu {
             0x2003 => self.r.oam_addr = val,
             // OAMDATA
             0x2004 => {
-                self.primary_oam[self.r.oam_addr as usize] = val;
-                self.r.oam_addr = self.r.oam_addr.wrapping_add(1);
+                if self.is_rendering_scanline() && self.r.rendering_enabled {
+                    // Writes during rendering do not modify OAM, but they increment the high 6
+                    // bits of OAMADDR.
+                    self.r.oam_addr = self.r.oam_addr.wrapping_add(4);
+                } else {
+                    // Bits 2-4 of the attribute byte are not implemented.
+                    let val = if self.r.oam_addr & 0x03 == 0x02 {
+                        val & 0xE3
+                    } else {
+                        val
+                    };
+                    self.primary_oam[self.r.oam_addr as usize] = val;
+                    self.r.oam_addr = self.r.oam_addr.wrapping_add(1);
+                }
             }
             // PPUSCROLL
             0x2005 => self.r.write_ppu_scroll(val),
@@ -242,14 +510,52 @@ impl Ppu {
             0x2006 => self.r.write_ppu_addr(val),
             // PPUDATA
             0x2007 => {
-                let addr = self.r.bus_address;
+                let addr = self.r.v & 0x3FFF;
                 self.write_byte(addr, val);
-                self.r.bus_address += self.r.vram_address_increment;
+                self.increment_vram_address();
             }
             _ => panic!("[PPU] Invalid ppu register to write: {:#06x}.", addr),
         }
     }
 
+    // The number of dots since power on, ignoring skipped dots.
+    fn dot(&self) -> u64 {
+        self.frame * DOTS_PER_FRAME
+            + u64::from(self.scanline) * DOTS_PER_SCANLINE
+            + u64::from(self.cycle)
+    }
+
+    // Sets the bits of the I/O latch in `mask` to `val` and refreshes their decay timers.
+    fn refresh_open_bus(&mut self, val: u8, mask: u8) {
+        let dot = self.dot();
+        self.open_bus = (self.open_bus & !mask) | (val & mask);
+        for (bit, refresh_dot) in self.open_bus_refresh_dots.iter_mut().enumerate() {
+            if mask & (1 << bit) != 0 {
+                *refresh_dot = dot;
+            }
+        }
+    }
+
+    fn decay_open_bus(&mut self) {
+        let dot = self.dot();
+        for (bit, refresh_dot) in self.open_bus_refresh_dots.iter().enumerate() {
+            if dot.saturating_sub(*refresh_dot) > OPEN_BUS_DECAY_DOTS {
+                self.open_bus &= !(1 << bit);
+            }
+        }
+    }
+
+    // Accessing $2007 during rendering increments both coarse X and Y of `v` instead of adding the
+    // VRAM address increment.
+    fn increment_vram_address(&mut self) {
+        if self.is_rendering_scanline() && self.r.rendering_enabled {
+            self.r.increment_scroll_x();
+            self.r.increment_scroll_y();
+        } else {
+            self.r.v = (self.r.v + self.r.vram_address_increment) & 0x7FFF;
+        }
+    }
+
     fn fetch_nametable_byte(&mut self) {
         let addr = 0x2000 | (self.r.v & 0x0FFF);
         self.r.nametable_byte = self.read_byte(addr);
@@ -268,11 +574,22 @@ impl Ppu {
         let fine_y = (self.r.v >> 12) & 0x07;
         let tile_offset = u16::from(self.r.nametable_byte) * 16;
         let addr = self.r.background_pattern_table_address + tile_offset + fine_y;
+        let addr = if high { addr + 8 } else { addr };
+        let val = self.read_byte(addr);
         if high {
-            self.r.high_tile_byte = self.read_byte(addr + 8);
+            self.r.high_tile_byte = val;
         } else {
-            self.r.low_tile_byte = self.read_byte(addr);
+            self.r.low_tile_byte = val;
         }
+        if self.r.show_background {
+            self.log_chr_rom(addr, cdl::CHR_RENDERED);
+        }
+    }
+
+    fn log_chr_rom(&mut self, addr: u16, flags: u8) {
+        let bus = self.bus_mut();
+        let chr_rom_addr = bus.mapper().chr_rom_address(addr);
+        bus.cdl_mut().log_chr_rom(chr_rom_addr, flags);
     }
 
     fn load_tile(&mut self) {
@@ -286,6 +603,28 @@ impl Ppu {
             curr_tile |= ((u64::from(self.r.palette)) << 2) | u64::from(color);
         }
         self.r.tile |= curr_tile;
+
+        if self.pixel_sources.enabled {
+            let fine_y = (self.r.v >> 12) & 0x07;
+            let tile_offset = u16::from(self.r.nametable_byte) * 16;
+            let addr = self.r.background_pattern_table_address + tile_offset + fine_y;
+            let tile = self.tile_ref(addr);
+            self.pixel_sources.load_background_tile(tile);
+        }
+    }
+
+    // Returns the tile of the pattern table row at `addr`.
+    fn tile_ref(&self, addr: u16) -> TileRef {
+        let tile_addr = addr & !0x0F;
+        let mut tile_data = [0; 16];
+        for (offset, val) in tile_data.iter_mut().enumerate() {
+            *val = self.read_byte(tile_addr + offset as u16);
+        }
+        TileRef {
+            tile_index: self.bus().mapper().chr_rom_address(tile_addr) / 16,
+            tile_data,
+            row: (addr & 0x07) as u8,
+        }
     }
 
     fn compute_background_pixel(&self) -> u16 {
@@ -298,89 +637,321 @@ impl Ppu {
         ((self.r.tile >> 32 >> ((7 - self.r.x) * 4)) & 0x0F) as u16
     }
 
-    fn compute_sprite_pixel(&self) -> (u16, bool, bool) {
-        let y = self.scanline as u8;
+    // Returns the first opaque sprite pixel at the current dot and the sprite output unit that it
+    // is from.
+    fn compute_sprite_pixel(&self) -> Option<(u16, usize)> {
         let x = (self.cycle - 1) as u8;
 
         if (x < 8 && !self.r.show_left_sprites) || !self.r.show_sprites {
-            return (0, false, false);
+            return None;
         }
 
-        for i in 0..8 {
-            let sprite_y = self.secondary_oam[i * 4].wrapping_add(1);
-            let sprite_x = self.secondary_oam[i * 4 + 3];
-            let mut tile_index = self.secondary_oam[i * 4 + 1];
-            let attributes = self.secondary_oam[i * 4 + 2];
-
-            if sprite_y & tile_index & attributes & sprite_x == 0xFF {
-                break;
-            }
-
+        for i in 0..self.sprite_count {
+            let sprite_x = self.sprite_xs[i];
             if !(sprite_x <= x && x <= sprite_x.saturating_add(7)) {
                 continue;
             }
+            let offset = x - sprite_x;
 
-            if !(1 <= sprite_y && sprite_y <= 239) {
+            let px = 7 - offset;
+            let low_tile_bit = (self.sprite_low_tile_bytes[i] >> px) & 0x01;
+            let high_tile_bit = (self.sprite_high_tile_bytes[i] >> px) & 0x01;
+            let palette = self.sprite_attributes[i] & 0x03;
+            let color = low_tile_bit | (high_tile_bit << 1);
+
+            if color == 0 {
                 continue;
             }
 
-            let mut py = y - sprite_y;
-            let mut px = 7 - (x - sprite_x);
-            let mut pattern_table_address = self.r.sprite_pattern_table_address;
+            return Some((u16::from((palette << 2) | color), i));
+        }
 
-            if attributes & 0x40 != 0 {
-                px = self.r.sprite_size.0 - 1 - px;
+        None
+    }
+
+    fn is_rendering_scanline(&self) -> bool {
+        self.scanline <= 239 || self.scanline == 261
+    }
+
+    // Secondary OAM is cleared to $FF during dots 1-64.
+    fn clear_secondary_oam(&mut self) {
+        // Reads from $2004 return $FF while secondary OAM is being cleared.
+        self.oam_buffer = 0xFF;
+        if self.cycle & 0x01 == 0 {
+            self.secondary_oam[(self.cycle as usize - 1) / 2] = self.oam_buffer;
+        }
+    }
+
+    // Sprites for the next scanline are copied to secondary OAM during dots 65-256. Odd dots read
+    // from primary OAM and even dots write to secondary OAM.
+    // https://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
+    fn evaluate_sprites(&mut self) {
+        if self.cycle == 65 {
+            self.sprite_eval_n = self.r.oam_addr >> 2;
+            self.sprite_eval_start_n = self.sprite_eval_n;
+            self.sprite_eval_m = self.r.oam_addr & 0x03;
+            self.secondary_oam_addr = 0;
+            self.sprite_in_range = false;
+            self.sprite_eval_done = false;
+            self.sprite_overflow_counter = 0;
+            self.sprite_0_in_range = false;
+        }
+
+        if self.cycle & 0x01 == 1 {
+            self.oam_buffer = self.primary_oam[self.r.oam_addr as usize];
+            return;
+        }
+
+        if self.sprite_eval_done {
+            self.sprite_eval_n = (self.sprite_eval_n + 1) & 0x3F;
+            if self.secondary_oam_addr >= 0x20 {
+                // Writes to a full secondary OAM are turned into reads.
+                self.oam_buffer = self.secondary_oam[self.secondary_oam_addr & 0x1F];
+            }
+        } else {
+            let y = u16::from(self.oam_buffer);
+            let height = u16::from(self.r.sprite_size.1);
+            if y <= self.scanline && self.scanline < y + height {
+                self.sprite_in_range = true;
             }
 
-            if attributes & 0x80 != 0 {
-                py = self.r.sprite_size.1 - 1 - py;
+            if self.secondary_oam_addr < 0x20 {
+                self.secondary_oam[self.secondary_oam_addr] = self.oam_buffer;
+                if self.sprite_in_range {
+                    // The first sprite evaluated is treated as sprite 0, even if OAMADDR did not
+                    // start at 0.
+                    if self.sprite_eval_n == self.sprite_eval_start_n {
+                        self.sprite_0_in_range = true;
+                    }
+                    self.sprite_eval_m += 1;
+                    self.secondary_oam_addr += 1;
+                    if self.secondary_oam_addr & 0x03 == 0 {
+                        self.sprite_in_range = false;
+                        self.increment_sprite_eval_n();
+                    }
+                } else {
+                    self.increment_sprite_eval_n();
+                }
+            } else {
+                self.oam_buffer = self.secondary_oam[self.secondary_oam_addr & 0x1F];
+                if self.sprite_in_range {
+                    self.r.sprite_overflow = true;
+                    self.sprite_eval_m += 1;
+                    if self.sprite_eval_m == 4 {
+                        self.increment_sprite_eval_n();
+                    }
+                    // The remaining three bytes of the overflowing sprite are read before
+                    // evaluation stops.
+                    if self.sprite_overflow_counter == 0 {
+                        self.sprite_overflow_counter = 3;
+                    } else {
+                        self.sprite_overflow_counter -= 1;
+                        if self.sprite_overflow_counter == 0 {
+                            self.sprite_eval_done = true;
+                            self.sprite_eval_m = 0;
+                        }
+                    }
+                } else {
+                    // Hardware bug: both n and m are incremented, so the PPU checks the tile
+                    // index, attributes, or x position of the following sprites as if they were y
+                    // positions.
+                    self.sprite_eval_m = (self.sprite_eval_m + 1) & 0x03;
+                    self.sprite_eval_n = (self.sprite_eval_n + 1) & 0x3F;
+                    if self.sprite_eval_n == 0 {
+                        self.sprite_eval_done = true;
+                    }
+                }
             }
+        }
+
+        self.r.oam_addr = (self.sprite_eval_n << 2) | (self.sprite_eval_m & 0x03);
+    }
+
+    fn increment_sprite_eval_n(&mut self) {
+        self.sprite_eval_m = 0;
+        self.sprite_eval_n = (self.sprite_eval_n + 1) & 0x3F;
+        if self.sprite_eval_n == 0 {
+            self.sprite_eval_done = true;
+        }
+    }
+
+    // The tiles of the sprites in secondary OAM are fetched during dots 257-320. Each sprite takes
+    // 8 dots: two garbage nametable fetches followed by the low and high tile bytes.
+    fn fetch_sprite(&mut self) {
+        let index = (self.cycle as usize - 257) / 8;
+        let step = (self.cycle as usize - 257) % 8;
+        self.oam_buffer = self.secondary_oam[index * 4 + step.min(3)];
+
+        if self.cycle == 257 {
+            self.sprite_count = if self.scanline == 261 {
+                0
+            } else {
+                self.secondary_oam_addr.div_ceil(4)
+            };
+            self.sprite_0_visible = self.sprite_0_in_range;
+        }
 
-            if self.r.sprite_size.1 == 16 {
-                pattern_table_address = (u16::from(tile_index) & 0x01) * 0x1000;
-                tile_index &= 0xFE;
-                if py >= 8 {
-                    py -= 8;
-                    tile_index += 1;
+        match step {
+            0 | 2 => {
+                self.read_byte(0x2000 | (self.r.v & 0x0FFF));
+            }
+            4 | 6 => {
+                let sprite_y = self.secondary_oam[index * 4];
+                let tile_index = self.secondary_oam[index * 4 + 1];
+                let attributes = self.secondary_oam[index * 4 + 2];
+                let addr = self.sprite_tile_address(sprite_y, tile_index, attributes);
+                let addr = if step == 6 { addr + 8 } else { addr };
+                let mut val = self.read_byte(addr);
+                if index < self.sprite_count && self.r.show_sprites {
+                    self.log_chr_rom(addr, cdl::CHR_RENDERED);
+                }
+
+                // Empty slots are fetched as well, but they are transparent.
+                if index >= self.sprite_count {
+                    val = 0;
+                } else if attributes & 0x40 != 0 {
+                    val = val.reverse_bits();
+                }
+
+                if step == 4 {
+                    self.sprite_low_tile_bytes[index] = val;
+                    if self.pixel_sources.enabled && index < self.sprite_count {
+                        let tile = self.tile_ref(addr);
+                        self.pixel_sources.load_sprite_tile(index, tile);
+                    }
+                } else {
+                    self.sprite_high_tile_bytes[index] = val;
+                    self.sprite_attributes[index] = attributes;
+                    self.sprite_xs[index] = self.secondary_oam[index * 4 + 3];
                 }
             }
+            _ => {}
+        }
 
-            let addr = pattern_table_address + u16::from(tile_index) * 16 + u16::from(py);
-            let low_tile_bit = (self.read_byte(addr) >> px) & 0x01;
-            let high_tile_bit = (self.read_byte(addr + 8) >> px) & 0x01;
-            let palette = (attributes & 0x03) as u8;
-            let color = low_tile_bit | (high_tile_bit << 1);
+        if self.cycle == 320 && self.sprite_limit_removed && self.sprite_count == 8 {
+            self.fetch_extra_sprites();
+        }
+    }
 
-            if color == 0 {
+    // Returns the address of the low tile byte of a sprite for the current scanline.
+    fn sprite_tile_address(&self, sprite_y: u8, mut tile_index: u8, attributes: u8) -> u16 {
+        let height = self.r.sprite_size.1;
+        let mut py = (self.scanline as u8).wrapping_sub(sprite_y) & (height - 1);
+        let mut pattern_table_address = self.r.sprite_pattern_table_address;
+
+        if attributes & 0x80 != 0 {
+            py = height - 1 - py;
+        }
+
+        if height == 16 {
+            pattern_table_address = (u16::from(tile_index) & 0x01) * 0x1000;
+            tile_index &= 0xFE;
+            if py >= 8 {
+                py -= 8;
+                tile_index += 1;
+            }
+        }
+
+        pattern_table_address + u16::from(tile_index) * 16 + u16::from(py)
+    }
+
+    // Loads the sprites in range of the scanline after the 8 that were copied to secondary OAM
+    // into the remaining sprite output units. Primary OAM is searched in the same order as sprite
+    // evaluation.
+    fn fetch_extra_sprites(&mut self) {
+        let height = u16::from(self.r.sprite_size.1);
+        let mut in_range_count = 0;
+        for i in 0..64 {
+            let n = (usize::from(self.sprite_eval_start_n) + i) & 0x3F;
+            let sprite = &self.primary_oam[n * 4..n * 4 + 4];
+            let (sprite_y, tile_index, attributes, sprite_x) =
+                (sprite[0], sprite[1], sprite[2], sprite[3]);
+            let y = u16::from(sprite_y);
+            if !(y <= self.scanline && self.scanline < y + height) {
                 continue;
             }
+            in_range_count += 1;
+            if in_range_count <= 8 {
+                continue;
+            }
+
+            let addr = self.sprite_tile_address(sprite_y, tile_index, attributes);
+            let mut low_tile_byte = self.read_byte(addr);
+            let mut high_tile_byte = self.read_byte(addr + 8);
+            if self.r.show_sprites {
+                self.log_chr_rom(addr, cdl::CHR_RENDERED);
+                self.log_chr_rom(addr + 8, cdl::CHR_RENDERED);
+            }
+            if attributes & 0x40 != 0 {
+                low_tile_byte = low_tile_byte.reverse_bits();
+                high_tile_byte = high_tile_byte.reverse_bits();
+            }
 
-            return (
-                u16::from((palette << 2) | color),
-                (attributes & 0x20) != 0,
-                self.is_sprite_0[i],
-            );
+            let index = self.sprite_count;
+            if self.pixel_sources.enabled {
+                let tile = self.tile_ref(addr);
+                self.pixel_sources.load_sprite_tile(index, tile);
+            }
+            self.sprite_low_tile_bytes[index] = low_tile_byte;
+            self.sprite_high_tile_bytes[index] = high_tile_byte;
+            self.sprite_attributes[index] = attributes;
+            self.sprite_xs[index] = sprite_x;
+            self.sprite_count += 1;
         }
+    }
 
-        (0, false, false)
+    fn log_scanline(&mut self) {
+        let mapper = self.bus().mapper();
+        let mut chr_banks = [0; 8];
+        for (index, bank) in chr_banks.iter_mut().enumerate() {
+            *bank = mapper.chr_rom_address(index as u16 * 0x400) / 0x400;
+        }
+        let state = ScanlineState {
+            v: self.r.v,
+            t: self.r.t,
+            x: self.r.x,
+            ppu_ctrl: self.r.ppu_ctrl(),
+            ppu_mask: self.r.ppu_mask(),
+            chr_banks,
+            mirroring_mode: mapper.mirroring_mode(),
+        };
+        self.event_log.log_scanline(self.scanline, state);
     }
 
     fn draw_pixel(&mut self) {
         let background_pixel = self.compute_background_pixel();
-        let (sprite_pixel, sprite_priority, is_sprite_0) = self.compute_sprite_pixel();
+        let sprite = self.compute_sprite_pixel();
+        let (sprite_pixel, sprite_priority, is_sprite_0) = match sprite {
+            Some((pixel, i)) => (
+                pixel,
+                self.sprite_attributes[i] & 0x20 != 0,
+                i == 0 && self.sprite_0_visible,
+            ),
+            None => (0, false, false),
+        };
 
         let background_on = background_pixel & 0x03 != 0;
         let sprite_on = sprite_pixel & 0x03 != 0;
+        if background_on && sprite_on && self.cycle < 256 && is_sprite_0 {
+            self.r.sprite_0_hit = true;
+        }
 
-        let addr = match (background_on, sprite_on) {
-            (false, false) => 0x3F00,
+        // Hidden layers are only left out of the output, so sprite 0 hits are unchanged.
+        let background_on = background_on && !self.background_hidden;
+        let sprite_on = sprite_on && !self.sprites_hidden;
+
+        // When rendering is disabled and the VRAM address points to palette RAM, the backdrop is
+        // the color at the VRAM address.
+        let backdrop = if !self.r.rendering_enabled && self.r.v & 0x3F00 == 0x3F00 {
+            self.r.v & 0x3FFF
+        } else {
+            0x3F00
+        };
+        let mut addr = match (background_on, sprite_on) {
+            (false, false) => backdrop,
             (false, true) => 0x3F10 + sprite_pixel,
             (true, false) => 0x3F00 + background_pixel,
             (true, true) => {
-                if self.cycle < 256 && is_sprite_0 {
-                    self.r.sprite_0_hit = true;
-                }
-
                 if !sprite_priority {
                     0x3F10 + sprite_pixel
                 } else {
@@ -389,16 +960,135 @@ impl Ppu {
             }
         };
 
-        let color = COLORS[self.read_byte(addr) as usize & 0x3F];
-        self.buffer[self.buffer_index] = ((color >> 16) & 0xFF) as u8;
-        self.buffer[self.buffer_index + 1] = ((color >> 8) & 0xFF) as u8;
-        self.buffer[self.buffer_index + 2] = (color & 0xFF) as u8;
-        self.buffer[self.buffer_index + 3] = 0xFF;
-        self.buffer_index += 4;
+        // The left 8 pixels can be masked regardless of PPUMASK to hide the artifacts that games
+        // leave there. This only affects the output, so sprite 0 hits are unchanged.
+        if self.left_column_masked && self.cycle <= 8 {
+            addr = backdrop;
+        }
+
+        if self.buffer_index == 0 {
+            self.drawing_frame_phase = self.phase;
+        }
+        if self.pixel_sources.enabled {
+            self.record_pixel_source(background_pixel, sprite, backdrop);
+        }
+        self.index_buffer[self.buffer_index] = self.output_index(addr);
+        self.buffer_index += 1;
+    }
+
+    // Returns the 9 bit output of the PPU for the color at the palette RAM address `addr`.
+    fn output_index(&self, addr: u16) -> u16 {
+        let mut index = self.read_byte(addr);
+        if self.r.greyscale_enabled {
+            index &= 0x30;
+        }
+        self.r.emphasis() | u16::from(index)
+    }
+
+    // Returns the four palette RAM values of the palette at `addr` packed into a `u32`. The first
+    // color of each palette is the backdrop color.
+    fn packed_palette(&self, addr: u16) -> u32 {
+        let colors = [
+            self.read_byte(0x3F00),
+            self.read_byte(addr + 1),
+            self.read_byte(addr + 2),
+            self.read_byte(addr + 3),
+        ];
+        u32::from_be_bytes(colors)
+    }
+
+    fn record_pixel_source(
+        &mut self,
+        background_pixel: u16,
+        sprite: Option<(u16, usize)>,
+        backdrop: u16,
+    ) {
+        let x = usize::from(self.cycle - 1);
+        let masked = self.left_column_masked && x < 8;
+        let background_shown = self.r.show_background
+            && (x >= 8 || self.r.show_left_background)
+            && !self.background_hidden
+            && !masked;
+
+        let background = if background_shown {
+            let offset = x % 8 + usize::from(self.r.x);
+            let tile = self.pixel_sources.background_tile(offset);
+            let addr = 0x3F00 + (background_pixel & 0x0C);
+            let color = (background_pixel & 0x03) as u8;
+            let index = self.output_index(0x3F00 + background_pixel);
+            Some(TileSource::new(
+                tile,
+                self.packed_palette(addr),
+                (offset % 8) as u8,
+                color,
+                index,
+            ))
+        } else {
+            None
+        };
+
+        let sprite = match sprite {
+            Some((sprite_pixel, i)) if !self.sprites_hidden && !masked => {
+                let attributes = self.sprite_attributes[i];
+                let offset = (x - usize::from(self.sprite_xs[i])) as u8;
+                let flip_horizontal = attributes & 0x40 != 0;
+                let tile = self.pixel_sources.sprite_tile(i);
+                let addr = 0x3F10 + (sprite_pixel & 0x0C);
+                let mut source = TileSource::new(
+                    tile,
+                    self.packed_palette(addr),
+                    if flip_horizontal { 7 - offset } else { offset },
+                    (sprite_pixel & 0x03) as u8,
+                    self.output_index(0x3F10 + sprite_pixel),
+                );
+                source.flip_horizontal = flip_horizontal;
+                source.flip_vertical = attributes & 0x80 != 0;
+                source.behind_background = attributes & 0x20 != 0;
+                Some(source)
+            }
+            _ => None,
+        };
+
+        if self.buffer_index == 0 {
+            // The scroll position is taken from v, which is two tiles ahead of the first pixel.
+            let v = usize::from(self.r.v);
+            let coarse_x = (v & 0x1F) * 8 + ((v >> 10) & 0x01) * 256;
+            let coarse_y = ((v >> 5) & 0x1F) * 8 + ((v >> 11) & 0x01) * 240;
+            self.pixel_sources.scroll_x = (coarse_x + usize::from(self.r.x) + 512 - 16) % 512;
+            self.pixel_sources.scroll_y = coarse_y + ((v >> 12) & 0x07);
+        }
+
+        self.pixel_sources.pixels[self.buffer_index] = PixelSource {
+            background,
+            sprite,
+            backdrop: self.output_index(backdrop),
+        };
+    }
+
+    // Converts the index buffer to colors in the image buffer in `format`, leaving out the pixels
+    // cropped by `overscan`. The image buffer is sized for four bytes per pixel of the full
+    // frame, so smaller formats and cropped images only fill the start of it.
+    pub fn render_image(&mut self, format: PixelFormat, overscan: Overscan) {
+        let palette = &self.palette;
+        let colors = self
+            .index_buffer
+            .chunks_exact(SCREEN_WIDTH)
+            .skip(overscan.top)
+            .take(overscan.height())
+            .flat_map(|row| &row[overscan.left..SCREEN_WIDTH - overscan.right])
+            .map(|index| palette.color(usize::from(*index)));
+        format.write_pixels(&mut self.buffer, colors);
     }
 
     pub fn step(&mut self) {
         self.cycle += 1;
+        self.phase = (self.phase + 8) % 12;
+        // The last dot of the pre-render scanline is skipped on odd frames when rendering is
+        // enabled.
+        if self.scanline == 261 && self.cycle == 340 && self.skip_last_dot {
+            self.skip_last_dot = false;
+            self.cycle = 341;
+        }
         if self.cycle == 341 {
             self.cycle = 0;
             self.scanline += 1;
@@ -406,96 +1096,93 @@ impl Ppu {
                 self.scanline = 0;
                 self.frame += 1;
                 self.buffer_index = 0;
+                self.frame_phase = self.drawing_frame_phase;
+                if self.event_log.enabled {
+                    self.event_log.end_frame();
+                }
+            }
+            if self.event_log.enabled {
+                self.log_scanline();
             }
         }
 
         let visible_scanline = self.scanline <= 239;
         let visible_cycle = 1 <= self.cycle && self.cycle <= 256;
         let prefetch_cycle = 321 <= self.cycle && self.cycle <= 336;
-        let _sprite_clear_cycle = 1 <= self.cycle && self.cycle <= 64;
-        let _sprite_evaluation_cycle = 65 <= self.cycle && self.cycle <= 256;
-        let _sprite_fetch_cycle = 257 <= self.cycle && self.cycle <= 320;
+        let sprite_clear_cycle = 1 <= self.cycle && self.cycle <= 64;
+        let sprite_evaluation_cycle = 65 <= self.cycle && self.cycle <= 256;
+        let sprite_fetch_cycle = 257 <= self.cycle && self.cycle <= 320;
 
         if visible_scanline || self.scanline == 261 {
             if visible_scanline && visible_cycle {
                 self.draw_pixel();
             }
 
-            if self.scanline == 261 && 280 <= self.cycle && self.cycle <= 304 {
-                self.r.copy_scroll_y();
-            }
+            if self.r.rendering_enabled {
+                // background pipeline
+                if self.scanline == 261 && 280 <= self.cycle && self.cycle <= 304 {
+                    self.r.copy_scroll_y();
+                }
 
-            if self.cycle == 257 {
-                self.r.copy_scroll_x();
-            }
+                if self.cycle == 257 {
+                    self.r.copy_scroll_x();
+                }
 
-            // background pipeline
-            if visible_cycle || prefetch_cycle {
-                self.r.tile <<= 4;
-                match self.cycle & 0x07 {
-                    1 => self.fetch_nametable_byte(),
-                    3 => self.fetch_attribute_table_byte(),
-                    5 => self.fetch_tile_byte(false),
-                    7 => self.fetch_tile_byte(true),
-                    0 => {
-                        self.load_tile();
-                        if self.cycle == 256 {
-                            self.r.increment_scroll_y();
-                        } else {
-                            self.r.increment_scroll_x();
+                if visible_cycle || prefetch_cycle {
+                    self.r.tile <<= 4;
+                    match self.cycle & 0x07 {
+                        1 => self.fetch_nametable_byte(),
+                        3 => self.fetch_attribute_table_byte(),
+                        5 => self.fetch_tile_byte(false),
+                        7 => self.fetch_tile_byte(true),
+                        0 => {
+                            self.load_tile();
+                            if self.cycle == 256 {
+                                self.r.increment_scroll_y();
+                            } else {
+                                self.r.increment_scroll_x();
+                            }
                         }
+                        _ => {}
                     }
-                    _ => {}
                 }
-            }
 
-            // sprite pipeline
-            // TODO: make fetches cycle accurate, add sprite data
-            // if sprite_clear_cycle && self.cycle & 0x01 != 0 {
-            //     self.secondary_oam[self.cycle as usize / 2] = 0xFF;
-            // }
+                // sprite pipeline
+                if visible_scanline && sprite_clear_cycle {
+                    self.clear_secondary_oam();
+                }
 
-            if self.cycle == 257 {
-                for i in 0..0x20 {
-                    self.secondary_oam[i] = 0xFF;
+                if visible_scanline && sprite_evaluation_cycle {
+                    self.evaluate_sprites();
                 }
-                let mut secondary_oam_index = 0;
-                for i in 0..64 {
-                    let y = i16::from(self.primary_oam[i * 4]) + 1;
-                    let lo = y;
-                    let hi = y + i16::from(self.r.sprite_size.1) - 1;
-                    let curr = self.scanline as i16 + 1;
-                    if !(lo <= curr && curr <= hi) || y >= 241 {
-                        continue;
-                    }
 
-                    if secondary_oam_index < 0x20 {
-                        self.secondary_oam[secondary_oam_index] = self.primary_oam[i * 4];
-                        self.secondary_oam[secondary_oam_index + 1] = self.primary_oam[i * 4 + 1];
-                        self.secondary_oam[secondary_oam_index + 2] = self.primary_oam[i * 4 + 2];
-                        self.secondary_oam[secondary_oam_index + 3] = self.primary_oam[i * 4 + 3];
-                        self.is_sprite_0[secondary_oam_index / 4] = i == 0;
-                        secondary_oam_index += 4;
-                    } else if self.r.show_sprites || self.r.show_background {
-                        self.r.sprite_overflow = true;
-                    }
+                if sprite_fetch_cycle {
+                    self.r.oam_addr = 0;
+                    self.fetch_sprite();
                 }
             }
         }
 
         if self.scanline == 241 && self.cycle == 1 {
-            self.r.v_blank_started = true;
-            if self.r.nmi_enabled {
-                let cpu = self.bus_mut().cpu_mut();
-                cpu.trigger_interrupt(Interrupt::NMI);
-            }
+            self.r.v_blank_started = !self.v_blank_suppressed;
+            self.v_blank_suppressed = false;
         }
 
         if self.scanline == 261 && self.cycle == 1 {
             self.r.v_blank_started = false;
+        }
+        // The sprite flags are seen as cleared by $2002 reads a dot before the vblank flag is.
+        if self.scanline == 261 && self.cycle == 0 {
             self.r.sprite_0_hit = false;
             self.r.sprite_overflow = false;
         }
+
+        let nmi_output = self.r.v_blank_started && self.r.nmi_enabled;
+        self.nmi_line_history = (self.nmi_line_history << 1) | nmi_output as u8;
+
+        if self.scanline == 261 && self.cycle == 338 {
+            self.skip_last_dot = self.frame & 0x01 != 0 && self.r.rendering_enabled;
+        }
     }
 }
 
@@ -0,0 +1,78 @@
+// http://www.thealmightyguru.com/Games/Hacking/Wiki/index.php/NES_Palette
+#[rustfmt::skip]
+const COLORS: [u32; 64] = [
+    0x007C_7C7C, 0x0000_00FC, 0x0000_00BC, 0x0044_28BC, 0x0094_0084, 0x00A8_0020, 0x00A8_1000, 0x0088_1400, //
+    0x0050_3000, 0x0000_7800, 0x0000_6800, 0x0000_5800, 0x0000_4058, 0x0000_0000, 0x0000_0000, 0x0000_0000, //
+    0x00BC_BCBC, 0x0000_78F8, 0x0000_58F8, 0x0068_44FC, 0x00D8_00CC, 0x00E4_0058, 0x00F8_3800, 0x00E4_5C10, //
+    0x00AC_7C00, 0x0000_B800, 0x0000_A800, 0x0000_A844, 0x0000_8888, 0x0000_0000, 0x0000_0000, 0x0000_0000, //
+    0x00F8_F8F8, 0x003C_BCFC, 0x0068_88FC, 0x0098_78F8, 0x00F8_78F8, 0x00F8_5898, 0x00F8_7858, 0x00FC_A044, //
+    0x00F8_B800, 0x00B8_F818, 0x0058_D854, 0x0058_F898, 0x0000_E8D8, 0x0078_7878, 0x0000_0000, 0x0000_0000, //
+    0x00FC_FCFC, 0x00A4_E4FC, 0x00B8_B8F8, 0x00D8_B8F8, 0x00F8_B8F8, 0x00F8_A4C0, 0x00F0_D0B0, 0x00FC_E0A8, //
+    0x00F8_D878, 0x00D8_F878, 0x00B8_F8B8, 0x00B8_F8D8, 0x0000_FCFC, 0x00F8_D8F8, 0x0000_0000, 0x0000_0000, //
+];
+
+const PALETTE_LEN: usize = 512;
+
+// Each emphasis bit attenuates the color channels other than its own.
+// https://wiki.nesdev.com/w/index.php/NTSC_video#Color_Tint_Bits
+const EMPHASIS_ATTENUATION: f32 = 0.816_328;
+
+// The 512 colors that the PPU can output. A color is indexed by the three emphasis bits of PPUMASK
+// followed by the six bit palette index.
+#[derive(Clone)]
+pub struct Palette {
+    colors: Vec<u32>,
+}
+
+impl Palette {
+    // Parses a `.pal` file, which is a list of RGB triples with either 64 or 512 entries. The
+    // emphasized colors of a 64 entry palette are generated from the base colors.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Palette> {
+        if bytes.len() != 64 * 3 && bytes.len() != PALETTE_LEN * 3 {
+            return None;
+        }
+        let colors: Vec<u32> = bytes
+            .chunks(3)
+            .map(|rgb| (u32::from(rgb[0]) << 16) | (u32::from(rgb[1]) << 8) | u32::from(rgb[2]))
+            .collect();
+        if colors.len() == PALETTE_LEN {
+            Some(Palette { colors })
+        } else {
+            Some(Palette::with_emphasis(&colors))
+        }
+    }
+
+    fn with_emphasis(base_colors: &[u32]) -> Palette {
+        let mut colors = Vec::with_capacity(PALETTE_LEN);
+        for emphasis in 0..8 {
+            for &color in base_colors {
+                // Red is emphasized by bit 0, green by bit 1, and blue by bit 2.
+                let channels = [(16, 0x01), (8, 0x02), (0, 0x04)];
+                let mut ret = 0;
+                for &(shift, mask) in channels.iter() {
+                    let mut channel = ((color >> shift) & 0xFF) as f32;
+                    if emphasis & !mask != 0 {
+                        channel *= EMPHASIS_ATTENUATION;
+                    }
+                    ret |= (channel.round() as u32) << shift;
+                }
+                colors.push(ret);
+            }
+        }
+        Palette { colors }
+    }
+
+    pub fn color(&self, index: usize) -> u32 {
+        self.colors[index]
+    }
+
+    pub fn as_ptr(&self) -> *const u32 {
+        self.colors.as_ptr()
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::with_emphasis(&COLORS)
+    }
+}
@@ -0,0 +1,115 @@
+// Records the tile that each pixel of a frame was drawn from, which HD packs use to replace the
+// tiles with higher resolution images.
+use super::{MAX_SPRITES, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// A tile in a pattern table as it was fetched by the PPU.
+#[derive(Clone, Copy, Default)]
+pub struct TileRef {
+    // The CHR address of the tile divided by 16.
+    pub tile_index: usize,
+    pub tile_data: [u8; 16],
+    // The row of the tile that was fetched.
+    pub row: u8,
+}
+
+// The tile that a pixel of the background or of a sprite was drawn from.
+#[derive(Clone, Copy, Default)]
+pub struct TileSource {
+    pub tile_index: usize,
+    pub tile_data: [u8; 16],
+    // The four palette RAM values of the palette of the tile, with the backdrop color in the high
+    // byte.
+    pub palette: u32,
+    // The position of the pixel in the tile before the tile is flipped.
+    pub x: u8,
+    pub y: u8,
+    // The 2 bit color of the pixel, where 0 is transparent.
+    pub color: u8,
+    // The 9 bit output of the PPU for the pixel.
+    pub index: u16,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    pub behind_background: bool,
+}
+
+impl TileSource {
+    pub fn new(tile: &TileRef, palette: u32, x: u8, color: u8, index: u16) -> Self {
+        TileSource {
+            tile_index: tile.tile_index,
+            tile_data: tile.tile_data,
+            palette,
+            x,
+            y: tile.row,
+            color,
+            index,
+            flip_horizontal: false,
+            flip_vertical: false,
+            behind_background: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct PixelSource {
+    // The background tile is recorded whenever the background is shown at the pixel, even if the
+    // pixel is transparent. The sprite is only recorded if it has an opaque pixel.
+    pub background: Option<TileSource>,
+    pub sprite: Option<TileSource>,
+    // The 9 bit output of the PPU for the backdrop.
+    pub backdrop: u16,
+}
+
+pub struct PixelSources {
+    pub enabled: bool,
+    pub pixels: Vec<PixelSource>,
+    // The scroll position of the first pixel of the frame in the 512x480 nametable map.
+    pub scroll_x: usize,
+    pub scroll_y: usize,
+    // The tiles in the background shift registers, oldest first.
+    background_tiles: [TileRef; 2],
+    // The tiles in the sprite output units.
+    sprite_tiles: Vec<TileRef>,
+}
+
+impl PixelSources {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.pixels = if enabled {
+            vec![PixelSource::default(); SCREEN_WIDTH * SCREEN_HEIGHT]
+        } else {
+            Vec::new()
+        };
+    }
+
+    pub fn load_background_tile(&mut self, tile: TileRef) {
+        self.background_tiles[0] = self.background_tiles[1];
+        self.background_tiles[1] = tile;
+    }
+
+    // Returns the background tile of a pixel, where `offset` is the position of the pixel in the
+    // 16 pixels of the shift registers.
+    pub fn background_tile(&self, offset: usize) -> &TileRef {
+        &self.background_tiles[offset / 8]
+    }
+
+    pub fn load_sprite_tile(&mut self, index: usize, tile: TileRef) {
+        self.sprite_tiles[index] = tile;
+    }
+
+    pub fn sprite_tile(&self, index: usize) -> &TileRef {
+        &self.sprite_tiles[index]
+    }
+}
+
+impl Default for PixelSources {
+    fn default() -> Self {
+        PixelSources {
+            enabled: false,
+            pixels: Vec::new(),
+            scroll_x: 0,
+            scroll_y: 0,
+            background_tiles: [TileRef::default(); 2],
+            sprite_tiles: vec![TileRef::default(); MAX_SPRITES],
+        }
+    }
+}
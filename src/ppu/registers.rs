@@ -47,10 +47,7 @@ pub struct Registers {
     pub scroll_y: u8,
 
     // PPUDATA
-    pub bus_address: u16,
     pub buffer: u8,
-
-    pub last_written_byte: u8,
 }
 
 const NAMETABLE_ADDRESSES: [u16; 4] = [0x2000, 0x2400, 0x2800, 0x2C00];
@@ -102,10 +99,7 @@ impl Registers {
             scroll_y: 0,
 
             // PPUDATA
-            bus_address: 0,
             buffer: 0,
-
-            last_written_byte: 0,
         }
     }
 
@@ -145,8 +139,8 @@ impl Registers {
     }
 
     pub fn read_ppu_status(&mut self) -> u8 {
-        let ret = (self.last_written_byte & 0x1F)
-            | if self.sprite_overflow { 0x20 } else { 0 }
+        // The low 5 bits are open bus.
+        let ret = if self.sprite_overflow { 0x20 } else { 0 }
             | if self.sprite_0_hit { 0x40 } else { 0 }
             | if self.v_blank_started { 0x80 } else { 0 };
         self.v_blank_started = false;
@@ -177,6 +171,38 @@ impl Registers {
         self.rendering_enabled = self.show_background || self.show_sprites;
     }
 
+    pub fn ppu_ctrl(&self) -> u8 {
+        let nametable = NAMETABLE_ADDRESSES
+            .iter()
+            .position(|addr| *addr == self.nametable_address)
+            .unwrap_or(0) as u8;
+        nametable
+            | (u8::from(self.vram_address_increment == 32) << 2)
+            | (u8::from(self.sprite_pattern_table_address == 0x1000) << 3)
+            | (u8::from(self.background_pattern_table_address == 0x1000) << 4)
+            | (u8::from(self.sprite_size.1 == 16) << 5)
+            | (u8::from(self.is_master) << 6)
+            | (u8::from(self.nmi_enabled) << 7)
+    }
+
+    pub fn ppu_mask(&self) -> u8 {
+        u8::from(self.greyscale_enabled)
+            | (u8::from(self.show_left_background) << 1)
+            | (u8::from(self.show_left_sprites) << 2)
+            | (u8::from(self.show_background) << 3)
+            | (u8::from(self.show_sprites) << 4)
+            | (u8::from(self.emphasize_red) << 5)
+            | (u8::from(self.emphasize_green) << 6)
+            | (u8::from(self.emphasize_blue) << 7)
+    }
+
+    // The emphasis bits of PPUMASK as an offset into the 512 color palette.
+    pub fn emphasis(&self) -> u16 {
+        (u16::from(self.emphasize_blue) << 8)
+            | (u16::from(self.emphasize_green) << 7)
+            | (u16::from(self.emphasize_red) << 6)
+    }
+
     pub fn write_ppu_scroll(&mut self, val: u8) {
         if self.w == 0 {
             self.t = (self.t & !0x001F) | (u16::from(val) >> 3);
@@ -197,7 +223,6 @@ impl Registers {
         } else {
             self.t = (self.t & !0x00FF) | u16::from(val);
             self.v = self.t;
-            self.bus_address = self.t & 0x3FFF;
             self.w = 0;
         }
     }
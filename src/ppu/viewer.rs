@@ -0,0 +1,238 @@
+// Renders the contents of the PPU's memory to RGBA8888 images for debugging.
+use super::Ppu;
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+pub const PATTERN_TABLE_SIZE: usize = 128;
+pub const NAMETABLE_MAP_WIDTH: usize = 512;
+pub const NAMETABLE_MAP_HEIGHT: usize = 480;
+// The sprite sheet is a grid of 8x8 cells that are 8x16 pixels so that tall sprites fit.
+pub const SPRITE_SHEET_WIDTH: usize = 64;
+pub const SPRITE_SHEET_HEIGHT: usize = 128;
+// The palette image is a grid of 16x2 swatches that are 16x16 pixels. The top row is the
+// background palettes and the bottom row is the sprite palettes.
+pub const PALETTE_IMAGE_WIDTH: usize = 256;
+pub const PALETTE_IMAGE_HEIGHT: usize = 32;
+const SWATCH_SIZE: usize = 16;
+
+/// The attributes of a sprite in OAM.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub struct SpriteInfo {
+    /// The x position of the left edge of the sprite.
+    pub x: u8,
+    /// The y position of the top edge of the sprite minus one.
+    pub y: u8,
+    /// The tile index byte of the sprite. For 8x16 sprites, bit 0 selects the pattern table.
+    pub tile_index: u8,
+    /// The sprite palette, from 0 to 3.
+    pub palette: u8,
+    /// Whether the sprite is drawn behind the background.
+    pub behind_background: bool,
+    /// Whether the sprite is flipped horizontally.
+    pub flip_horizontal: bool,
+    /// Whether the sprite is flipped vertically.
+    pub flip_vertical: bool,
+}
+
+struct Image {
+    pixels: Vec<u8>,
+    width: usize,
+}
+
+impl Image {
+    fn new(width: usize, height: usize) -> Self {
+        Image {
+            pixels: vec![0; width * height * 4],
+            width,
+        }
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, color: u32) {
+        let [_, r, g, b] = color.to_be_bytes();
+        let offset = (y * self.width + x) * 4;
+        self.pixels[offset..offset + 4].copy_from_slice(&[r, g, b, 0xFF]);
+    }
+
+    // Flips the high bit of each channel of a pixel, which changes every channel by 128 so that
+    // overlays are visible on any background.
+    fn highlight_pixel(&mut self, x: usize, y: usize) {
+        let offset = (y * self.width + x) * 4;
+        for val in self.pixels[offset..offset + 3].iter_mut() {
+            *val ^= 0x80;
+        }
+    }
+}
+
+impl Ppu {
+    // Returns the color of entry `index` of palette RAM.
+    fn palette_color(&self, index: u16) -> u32 {
+        let index = self.read_byte(0x3F00 + (index & 0x1F)) & 0x3F;
+        self.palette.color(usize::from(index))
+    }
+
+    // Returns the 2 bit color of each pixel of a row of the tile at `addr`, from left to right.
+    fn tile_row(&self, addr: u16, row: u16) -> [u8; 8] {
+        let low_tile_byte = self.read_byte(addr + row);
+        let high_tile_byte = self.read_byte(addr + row + 8);
+        let mut ret = [0; 8];
+        for (px, color) in ret.iter_mut().enumerate() {
+            let shift = 7 - px;
+            *color = ((low_tile_byte >> shift) & 0x01) | (((high_tile_byte >> shift) & 0x01) << 1);
+        }
+        ret
+    }
+
+    // Renders pattern table `table` as a 16x16 grid of tiles colored with palette `palette`,
+    // where palettes 0-3 are the background palettes and 4-7 are the sprite palettes.
+    pub fn render_pattern_table(&self, table: usize, palette: usize) -> Vec<u8> {
+        if table > 1 || palette > 7 {
+            panic!(
+                "[PPU] Invalid pattern table {} or palette {}.",
+                table, palette
+            );
+        }
+        let mut image = Image::new(PATTERN_TABLE_SIZE, PATTERN_TABLE_SIZE);
+        for tile in 0..256 {
+            let addr = table as u16 * 0x1000 + tile as u16 * 16;
+            for row in 0..8 {
+                let colors = self.tile_row(addr, row);
+                for (px, color) in colors.iter().enumerate() {
+                    let color = self.palette_color(palette as u16 * 4 + u16::from(*color));
+                    let x = (tile % 16) * 8 + px;
+                    let y = (tile / 16) * 8 + row as usize;
+                    image.set_pixel(x, y, color);
+                }
+            }
+        }
+        image.pixels
+    }
+
+    // Renders the four nametables as they are laid out in PPU memory with the background pattern
+    // table and attribute tables. When `show_scroll` is set, the outline of the 256x240 window
+    // that the next frame starts at is highlighted. The window wraps around the edges of the map.
+    pub fn render_nametables(&self, show_scroll: bool) -> Vec<u8> {
+        let mut image = Image::new(NAMETABLE_MAP_WIDTH, NAMETABLE_MAP_HEIGHT);
+        for nametable in 0..4 {
+            let base_addr = 0x2000 + nametable as u16 * 0x400;
+            let (base_x, base_y) = ((nametable % 2) * 256, (nametable / 2) * 240);
+            for tile_y in 0..30 {
+                for tile_x in 0..32 {
+                    let tile_index = self.read_byte(base_addr + tile_y * 32 + tile_x);
+                    let attribute_addr = base_addr + 0x3C0 + (tile_y / 4) * 8 + tile_x / 4;
+                    let shift = ((tile_y & 0x02) << 1) | (tile_x & 0x02);
+                    let palette = (self.read_byte(attribute_addr) >> shift) & 0x03;
+                    let addr = self.r.background_pattern_table_address + u16::from(tile_index) * 16;
+                    for row in 0..8 {
+                        let colors = self.tile_row(addr, row);
+                        for (px, color) in colors.iter().enumerate() {
+                            // Transparent pixels are the backdrop color.
+                            let index = if *color == 0 {
+                                0
+                            } else {
+                                u16::from(palette * 4 + color)
+                            };
+                            let x = base_x + tile_x as usize * 8 + px;
+                            let y = base_y + (tile_y * 8 + row) as usize;
+                            image.set_pixel(x, y, self.palette_color(index));
+                        }
+                    }
+                }
+            }
+        }
+
+        if show_scroll {
+            // t holds the scroll position until it is copied to v at the start of the frame.
+            let t = self.r.t;
+            let nametable = usize::from((t >> 10) & 0x03);
+            let scroll_x =
+                (nametable % 2) * 256 + usize::from(t & 0x1F) * 8 + usize::from(self.r.x);
+            let scroll_y = (nametable / 2) * 240
+                + usize::from((t >> 5) & 0x1F) * 8
+                + usize::from((t >> 12) & 0x07);
+            for dx in 0..256 {
+                let x = (scroll_x + dx) % NAMETABLE_MAP_WIDTH;
+                image.highlight_pixel(x, scroll_y % NAMETABLE_MAP_HEIGHT);
+                image.highlight_pixel(x, (scroll_y + 239) % NAMETABLE_MAP_HEIGHT);
+            }
+            for dy in 1..239 {
+                let y = (scroll_y + dy) % NAMETABLE_MAP_HEIGHT;
+                image.highlight_pixel(scroll_x % NAMETABLE_MAP_WIDTH, y);
+                image.highlight_pixel((scroll_x + 255) % NAMETABLE_MAP_WIDTH, y);
+            }
+        }
+        image.pixels
+    }
+
+    pub fn sprite_info(&self, index: usize) -> SpriteInfo {
+        if index > 63 {
+            panic!("[PPU] Invalid sprite index {}.", index);
+        }
+        let sprite = &self.primary_oam[index * 4..index * 4 + 4];
+        let attributes = sprite[2];
+        SpriteInfo {
+            x: sprite[3],
+            y: sprite[0],
+            tile_index: sprite[1],
+            palette: attributes & 0x03,
+            behind_background: attributes & 0x20 != 0,
+            flip_horizontal: attributes & 0x40 != 0,
+            flip_vertical: attributes & 0x80 != 0,
+        }
+    }
+
+    // Renders the 64 sprites in OAM as an 8x8 grid in OAM order with their palettes and flips
+    // applied. 8x8 sprites fill the top half of their cell and transparent pixels have an alpha
+    // of 0.
+    pub fn render_sprites(&self) -> Vec<u8> {
+        let mut image = Image::new(SPRITE_SHEET_WIDTH, SPRITE_SHEET_HEIGHT);
+        let height = u16::from(self.r.sprite_size.1);
+        for index in 0..64 {
+            let sprite = self.sprite_info(index);
+            for row in 0..height {
+                let py = if sprite.flip_vertical {
+                    height - 1 - row
+                } else {
+                    row
+                };
+                let addr = if height == 16 {
+                    let table = u16::from(sprite.tile_index & 0x01) * 0x1000;
+                    let tile = u16::from(sprite.tile_index & 0xFE) + py / 8;
+                    table + tile * 16
+                } else {
+                    self.r.sprite_pattern_table_address + u16::from(sprite.tile_index) * 16
+                };
+                let colors = self.tile_row(addr, py % 8);
+                for (px, color) in colors.iter().enumerate() {
+                    if *color == 0 {
+                        continue;
+                    }
+                    let px = if sprite.flip_horizontal { 7 - px } else { px };
+                    let color = self.palette_color(0x10 + u16::from(sprite.palette * 4 + color));
+                    let x = (index % 8) * 8 + px;
+                    let y = (index / 8) * 16 + row as usize;
+                    image.set_pixel(x, y, color);
+                }
+            }
+        }
+        image.pixels
+    }
+
+    // Renders the 32 entries of palette RAM as swatches.
+    pub fn render_palettes(&self) -> Vec<u8> {
+        let mut image = Image::new(PALETTE_IMAGE_WIDTH, PALETTE_IMAGE_HEIGHT);
+        for index in 0..32 {
+            let color = self.palette_color(index as u16);
+            for y in 0..SWATCH_SIZE {
+                for x in 0..SWATCH_SIZE {
+                    image.set_pixel(
+                        (index % 16) * SWATCH_SIZE + x,
+                        (index / 16) * SWATCH_SIZE + y,
+                        color,
+                    );
+                }
+            }
+        }
+        image.pixels
+    }
+}
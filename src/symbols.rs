@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+
+// The size of the iNES header that ld65 includes in the output file offsets.
+const INES_HEADER_LEN: usize = 0x10;
+// FCEUX name lists split PRG ROM into 16K banks.
+const NL_BANK_LEN: usize = 0x4000;
+
+// Where a symbol is located. Symbols in PRG ROM are keyed by their offset into PRG ROM, which is
+// the bank and address of the symbol, so that they can be resolved through the current banking.
+// Any other symbol is keyed by its CPU address.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Location {
+    Cpu(u16),
+    PrgRom(usize),
+}
+
+#[derive(Default)]
+pub struct SymbolTable {
+    labels: HashMap<Location, String>,
+    locations: HashMap<String, Location>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable::default()
+    }
+
+    pub fn insert(&mut self, location: Location, label: &str) {
+        if let Some(old_location) = self.locations.insert(label.to_owned(), location) {
+            if self.labels.get(&old_location).map(String::as_str) == Some(label) {
+                self.labels.remove(&old_location);
+            }
+        }
+        self.labels.insert(location, label.to_owned());
+    }
+
+    pub fn clear(&mut self) {
+        self.labels.clear();
+        self.locations.clear();
+    }
+
+    pub fn label(&self, location: Location) -> Option<&str> {
+        self.labels.get(&location).map(String::as_str)
+    }
+
+    pub fn location(&self, label: &str) -> Option<Location> {
+        self.locations.get(label).cloned()
+    }
+
+    // Loads an FCEUX name list. Each line is of the form `$C000#label#comment`. `bank` is the 16K
+    // PRG ROM bank of the name list, which is given by the file name (`game.nes.0.nl`), or `None`
+    // for the RAM name list (`game.nes.ram.nl`). Returns the number of symbols loaded.
+    // http://www.fceux.com/web/help/fceux.html?NLFilesFormat.html
+    pub fn load_nl(&mut self, contents: &str, bank: Option<usize>) -> usize {
+        let mut count = 0;
+        for line in contents.lines() {
+            let mut fields = line.trim().splitn(3, '#');
+            let addr = match fields.next().and_then(|field| field.strip_prefix('$')) {
+                Some(field) => field.split('/').next(),
+                None => continue,
+            };
+            let addr = match addr.and_then(|addr| u16::from_str_radix(addr, 16).ok()) {
+                Some(addr) => addr,
+                None => continue,
+            };
+            let label = match fields.next() {
+                Some(label) if !label.is_empty() => label,
+                _ => continue,
+            };
+            let location = match bank {
+                Some(bank) if addr >= 0x8000 => {
+                    Location::PrgRom(bank * NL_BANK_LEN + addr as usize % NL_BANK_LEN)
+                }
+                _ => Location::Cpu(addr),
+            };
+            self.insert(location, label);
+            count += 1;
+        }
+        count
+    }
+
+    // Loads the labels of a ca65/ld65 debug information file. Labels in segments that are written
+    // to the output file are keyed by their PRG ROM offset and all other labels are keyed by their
+    // CPU address. Returns the number of symbols loaded.
+    pub fn load_dbg(&mut self, contents: &str) -> usize {
+        // Maps segment ids to (start, PRG ROM offset).
+        let mut segments: HashMap<&str, (usize, Option<usize>)> = HashMap::new();
+        let mut symbols = Vec::new();
+        for line in contents.lines() {
+            let mut tokens = line.trim().splitn(2, |c: char| c.is_whitespace());
+            let kind = tokens.next().unwrap_or("");
+            let attributes = parse_dbg_attributes(tokens.next().unwrap_or(""));
+            match kind {
+                "seg" => {
+                    let id = attributes.get("id");
+                    let start = attributes
+                        .get("start")
+                        .and_then(|val| parse_dbg_number(val));
+                    let offset = attributes
+                        .get("ooffs")
+                        .and_then(|val| parse_dbg_number(val))
+                        .and_then(|val| val.checked_sub(INES_HEADER_LEN));
+                    if let (Some(id), Some(start)) = (id, start) {
+                        segments.insert(id, (start, offset));
+                    }
+                }
+                "sym" => symbols.push(attributes),
+                _ => {}
+            }
+        }
+
+        let mut count = 0;
+        for attributes in symbols {
+            if attributes.get("type") != Some(&"lab") {
+                continue;
+            }
+            let name = match attributes.get("name") {
+                Some(name) => name.trim_matches('"'),
+                None => continue,
+            };
+            let val = match attributes.get("val").and_then(|val| parse_dbg_number(val)) {
+                Some(val) if val <= 0xFFFF => val,
+                _ => continue,
+            };
+            let segment = attributes.get("seg").and_then(|id| segments.get(id));
+            let location = match segment {
+                Some(&(start, Some(offset))) if val >= 0x8000 && val >= start => {
+                    Location::PrgRom(offset + val - start)
+                }
+                _ => Location::Cpu(val as u16),
+            };
+            self.insert(location, name);
+            count += 1;
+        }
+        count
+    }
+}
+
+// Parses a comma separated list of `key=value` pairs. Quoted values may contain commas.
+fn parse_dbg_attributes(line: &str) -> HashMap<&str, &str> {
+    let mut ret = HashMap::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (index, c) in line.char_indices().chain(Some((line.len(), ','))) {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                let mut pair = line[start..index].splitn(2, '=');
+                if let (Some(key), Some(val)) = (pair.next(), pair.next()) {
+                    ret.insert(key.trim(), val.trim());
+                }
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    ret
+}
+
+fn parse_dbg_number(val: &str) -> Option<usize> {
+    match val.strip_prefix("0x") {
+        Some(val) => usize::from_str_radix(val, 16).ok(),
+        None => val.parse().ok(),
+    }
+}
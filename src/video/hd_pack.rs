@@ -0,0 +1,682 @@
+// HD packs in the Mesen format, which replace the 8x8 tiles of a game with higher resolution
+// images. A pack is a `hires.txt` file of rules and the PNG images that the rules refer to. The
+// images are decoded by the frontend and loaded as RGBA8888 pixels.
+// https://www.mesen.ca/docs/hdpacks.html
+use crate::cpu::MemoryMap;
+use crate::ppu::{PixelFormat, PixelSource, Ppu, TileSource, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::collections::HashMap;
+
+pub const MAX_SCALE: usize = 10;
+
+struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    // Returns the RGBA pixel at (x, y), or `None` if it is outside of the image.
+    fn get(&self, x: usize, y: usize) -> Option<[u8; 4]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let offset = (y * self.width + x) * 4;
+        let pixel = &self.pixels[offset..offset + 4];
+        Some([pixel[0], pixel[1], pixel[2], pixel[3]])
+    }
+}
+
+// A tile is identified by its index in CHR ROM or, for games with CHR RAM, by its contents.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum TileKey {
+    Index(usize),
+    Data([u8; 16]),
+}
+
+impl TileKey {
+    fn parse(field: &str) -> Option<Self> {
+        if field.len() == 32 {
+            let mut data = [0; 16];
+            for (i, val) in data.iter_mut().enumerate() {
+                *val = u8::from_str_radix(field.get(i * 2..i * 2 + 2)?, 16).ok()?;
+            }
+            Some(TileKey::Data(data))
+        } else {
+            parse_hex(field).map(TileKey::Index)
+        }
+    }
+
+    fn matches(&self, source: &TileSource) -> bool {
+        match self {
+            TileKey::Index(index) => *index == source.tile_index,
+            TileKey::Data(data) => *data == source.tile_data,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct TileMatch {
+    key: TileKey,
+    palette: Option<u32>,
+}
+
+impl TileMatch {
+    fn matches(&self, source: Option<&TileSource>) -> bool {
+        match source {
+            Some(source) => {
+                self.key.matches(source)
+                    && self
+                        .palette
+                        .map_or(true, |palette| palette == source.palette)
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+    GreaterOrEqual,
+    LessOrEqual,
+}
+
+impl Operator {
+    fn parse(field: &str) -> Option<Self> {
+        match field {
+            "==" => Some(Operator::Equal),
+            "!=" => Some(Operator::NotEqual),
+            ">" => Some(Operator::Greater),
+            "<" => Some(Operator::Less),
+            ">=" => Some(Operator::GreaterOrEqual),
+            "<=" => Some(Operator::LessOrEqual),
+            _ => None,
+        }
+    }
+
+    fn compare(self, a: u8, b: u8) -> bool {
+        match self {
+            Operator::Equal => a == b,
+            Operator::NotEqual => a != b,
+            Operator::Greater => a > b,
+            Operator::Less => a < b,
+            Operator::GreaterOrEqual => a >= b,
+            Operator::LessOrEqual => a <= b,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Address(u16),
+    Constant(u8),
+}
+
+#[derive(Clone, Copy)]
+enum Condition {
+    TileAtPosition(usize, usize, TileMatch),
+    SpriteAtPosition(usize, usize, TileMatch),
+    TileNearby(isize, isize, TileMatch),
+    SpriteNearby(isize, isize, TileMatch),
+    // Compares a byte of CPU or PPU memory, masked by the mask, to another byte or a constant.
+    MemoryCheck {
+        ppu: bool,
+        addr: u16,
+        operator: Operator,
+        operand: Operand,
+        mask: u8,
+    },
+    // True when the frame number modulo the divisor is at least the value.
+    FrameRange(u64, u64),
+    // The flips and the priority of the sprite being replaced.
+    HorizontalMirror,
+    VerticalMirror,
+    BackgroundPriority,
+}
+
+impl Condition {
+    fn parse(kind: &str, fields: &[&str]) -> Option<Self> {
+        let tile_match = |fields: &[&str]| -> Option<TileMatch> {
+            Some(TileMatch {
+                key: TileKey::parse(fields.get(2)?)?,
+                palette: match fields.get(3) {
+                    Some(field) => Some(u32::from_str_radix(field, 16).ok()?),
+                    None => None,
+                },
+            })
+        };
+        let position = |fields: &[&str]| -> Option<(isize, isize)> {
+            Some((fields.first()?.parse().ok()?, fields.get(1)?.parse().ok()?))
+        };
+        let memory_check = |ppu: bool, constant: bool| -> Option<Condition> {
+            let addr = parse_hex(fields.first()?)? as u16;
+            let operator = Operator::parse(fields.get(1)?)?;
+            let operand = parse_hex(fields.get(2)?)?;
+            let operand = if constant {
+                Operand::Constant(operand as u8)
+            } else {
+                Operand::Address(operand as u16)
+            };
+            let mask = match fields.get(3) {
+                Some(field) => parse_hex(field)? as u8,
+                None => 0xFF,
+            };
+            Some(Condition::MemoryCheck {
+                ppu,
+                addr,
+                operator,
+                operand,
+                mask,
+            })
+        };
+
+        match kind {
+            "tileAtPosition" | "spriteAtPosition" => {
+                let (x, y) = position(fields)?;
+                if x < 0 || y < 0 {
+                    return None;
+                }
+                let tile_match = tile_match(fields)?;
+                if kind == "tileAtPosition" {
+                    Some(Condition::TileAtPosition(
+                        x as usize, y as usize, tile_match,
+                    ))
+                } else {
+                    Some(Condition::SpriteAtPosition(
+                        x as usize, y as usize, tile_match,
+                    ))
+                }
+            }
+            "tileNearby" => {
+                let (x, y) = position(fields)?;
+                Some(Condition::TileNearby(x, y, tile_match(fields)?))
+            }
+            "spriteNearby" => {
+                let (x, y) = position(fields)?;
+                Some(Condition::SpriteNearby(x, y, tile_match(fields)?))
+            }
+            "memoryCheck" => memory_check(false, false),
+            "memoryCheckConstant" => memory_check(false, true),
+            "ppuMemoryCheck" => memory_check(true, false),
+            "ppuMemoryCheckConstant" => memory_check(true, true),
+            "frameRange" => {
+                let divisor: u64 = fields.first()?.parse().ok()?;
+                if divisor == 0 {
+                    return None;
+                }
+                Some(Condition::FrameRange(divisor, fields.get(1)?.parse().ok()?))
+            }
+            _ => None,
+        }
+    }
+}
+
+// A reference to a condition in the condition list of a rule, which is negated with `!`.
+#[derive(Clone, Copy)]
+struct ConditionRef {
+    index: usize,
+    negated: bool,
+}
+
+struct TileRule {
+    image: usize,
+    // The position of the top left corner of the replacement in the image.
+    x: usize,
+    y: usize,
+    brightness: f32,
+    conditions: Vec<ConditionRef>,
+}
+
+struct Background {
+    image: usize,
+    brightness: f32,
+    // How far the background moves for each pixel that the screen is scrolled.
+    horizontal_scroll_ratio: f32,
+    vertical_scroll_ratio: f32,
+    conditions: Vec<ConditionRef>,
+}
+
+// Everything needed to evaluate conditions for a frame.
+struct Frame<'a> {
+    sources: &'a [PixelSource],
+    // The value of each condition that is the same for every pixel.
+    frame_conditions: Vec<bool>,
+}
+
+pub struct HdPack {
+    scale: usize,
+    image_names: Vec<String>,
+    images: Vec<Option<Image>>,
+    conditions: Vec<Condition>,
+    rules: Vec<TileRule>,
+    // The rules of each tile, where rules for a specific palette come before default tiles and
+    // rules with conditions come before rules without conditions.
+    tile_rules: HashMap<TileKey, Vec<(Option<u32>, usize)>>,
+    backgrounds: Vec<Background>,
+    pub buffer: Vec<u8>,
+}
+
+impl HdPack {
+    // Parses the rules of a `hires.txt` file. Lines that are malformed, that refer to undefined
+    // conditions or images, or that are not supported are skipped.
+    pub fn parse(contents: &str) -> Self {
+        let mut pack = HdPack {
+            scale: 1,
+            image_names: Vec::new(),
+            images: Vec::new(),
+            conditions: Vec::new(),
+            rules: Vec::new(),
+            tile_rules: HashMap::new(),
+            backgrounds: Vec::new(),
+            buffer: Vec::new(),
+        };
+        let mut condition_names = HashMap::new();
+        let mut tile_images = Vec::new();
+
+        for line in contents.lines() {
+            let mut line = line.trim();
+            let mut condition_refs = Vec::new();
+            if let Some(rest) = line.strip_prefix('[') {
+                let end = match rest.find(']') {
+                    Some(end) => end,
+                    None => continue,
+                };
+                let refs: Option<Vec<_>> = rest[..end]
+                    .split('&')
+                    .map(|name| pack.condition_ref(&condition_names, name.trim()))
+                    .collect();
+                condition_refs = match refs {
+                    Some(refs) => refs,
+                    None => continue,
+                };
+                line = &rest[end + 1..];
+            }
+
+            let (tag, value) = match line.strip_prefix('<').and_then(|line| {
+                let end = line.find('>')?;
+                Some((&line[..end], line[end + 1..].trim()))
+            }) {
+                Some(tag) => tag,
+                None => continue,
+            };
+            let fields: Vec<_> = value.split(',').map(str::trim).collect();
+
+            match tag {
+                "scale" => {
+                    if let Ok(scale) = value.parse() {
+                        if (1..=MAX_SCALE).contains(&scale) {
+                            pack.scale = scale;
+                        }
+                    }
+                }
+                "img" => {
+                    let image = pack.image_index(value);
+                    tile_images.push(image);
+                }
+                "condition" => {
+                    if fields.len() < 2 {
+                        continue;
+                    }
+                    if let Some(condition) = Condition::parse(fields[1], &fields[2..]) {
+                        condition_names.insert(fields[0].to_owned(), pack.conditions.len());
+                        pack.conditions.push(condition);
+                    }
+                }
+                "tile" => {
+                    if let Some(rule) = pack.parse_tile(&fields, &tile_images, condition_refs) {
+                        pack.insert_tile_rule(rule);
+                    }
+                }
+                "background" => {
+                    if let Some(background) = pack.parse_background(&fields, condition_refs) {
+                        pack.backgrounds.push(background);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let rules = &pack.rules;
+        for tile_rules in pack.tile_rules.values_mut() {
+            tile_rules.sort_by_key(|(palette, rule)| {
+                (palette.is_none(), rules[*rule].conditions.is_empty())
+            });
+        }
+        pack
+    }
+
+    // Returns a reference to the condition `name`, or one of the built-in conditions.
+    fn condition_ref(
+        &mut self,
+        condition_names: &HashMap<String, usize>,
+        name: &str,
+    ) -> Option<ConditionRef> {
+        let (name, negated) = match name.strip_prefix('!') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        let index = match name {
+            "hmirror" | "vmirror" | "bgpriority" => {
+                let condition = match name {
+                    "hmirror" => Condition::HorizontalMirror,
+                    "vmirror" => Condition::VerticalMirror,
+                    _ => Condition::BackgroundPriority,
+                };
+                self.conditions.push(condition);
+                self.conditions.len() - 1
+            }
+            _ => *condition_names.get(name)?,
+        };
+        Some(ConditionRef { index, negated })
+    }
+
+    // Returns the index of the image with file name `name`, adding it if it is new.
+    fn image_index(&mut self, name: &str) -> usize {
+        match self.image_names.iter().position(|image| image == name) {
+            Some(index) => index,
+            None => {
+                self.image_names.push(name.to_owned());
+                self.images.push(None);
+                self.image_names.len() - 1
+            }
+        }
+    }
+
+    // `<tile>image,tile,palette,x,y,brightness,default`
+    fn parse_tile(
+        &self,
+        fields: &[&str],
+        tile_images: &[usize],
+        conditions: Vec<ConditionRef>,
+    ) -> Option<(TileKey, Option<u32>, TileRule)> {
+        let image = *tile_images.get(fields.first()?.parse::<usize>().ok()?)?;
+        let key = TileKey::parse(fields.get(1)?)?;
+        let palette = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+        let brightness = match fields.get(5) {
+            Some(field) => field.parse().ok()?,
+            None => 1.0,
+        };
+        let default_tile = fields.get(6).is_some_and(|field| *field == "Y");
+        let rule = TileRule {
+            image,
+            x: fields.get(3)?.parse().ok()?,
+            y: fields.get(4)?.parse().ok()?,
+            brightness,
+            conditions,
+        };
+        // Default tiles replace the tile regardless of its palette.
+        let palette = if default_tile { None } else { Some(palette) };
+        Some((key, palette, rule))
+    }
+
+    fn insert_tile_rule(&mut self, (key, palette, rule): (TileKey, Option<u32>, TileRule)) {
+        self.tile_rules
+            .entry(key)
+            .or_default()
+            .push((palette, self.rules.len()));
+        self.rules.push(rule);
+    }
+
+    // `<background>name,brightness,horizontal scroll ratio,vertical scroll ratio`
+    fn parse_background(
+        &mut self,
+        fields: &[&str],
+        conditions: Vec<ConditionRef>,
+    ) -> Option<Background> {
+        let parse_field = |index: usize, default: f32| -> Option<f32> {
+            match fields.get(index) {
+                Some(field) => field.parse().ok(),
+                None => Some(default),
+            }
+        };
+        let name = fields.first().filter(|name| !name.is_empty())?;
+        let brightness = parse_field(1, 1.0)?;
+        let horizontal_scroll_ratio = parse_field(2, 0.0)?;
+        let vertical_scroll_ratio = parse_field(3, 0.0)?;
+        Some(Background {
+            image: self.image_index(name),
+            brightness,
+            horizontal_scroll_ratio,
+            vertical_scroll_ratio,
+            conditions,
+        })
+    }
+
+    pub fn rule_count(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn image_count(&self) -> usize {
+        self.image_names.len()
+    }
+
+    pub fn image_name(&self, index: usize) -> &str {
+        &self.image_names[index]
+    }
+
+    pub fn load_image(&mut self, index: usize, width: usize, height: usize, pixels: &[u8]) {
+        if pixels.len() != width * height * 4 {
+            panic!("[HD] Expected image to be {} bytes.", width * height * 4);
+        }
+        self.images[index] = Some(Image {
+            width,
+            height,
+            pixels: pixels.to_vec(),
+        });
+    }
+
+    pub fn width(&self) -> usize {
+        SCREEN_WIDTH * self.scale
+    }
+
+    pub fn height(&self) -> usize {
+        SCREEN_HEIGHT * self.scale
+    }
+
+    fn evaluate(
+        &self,
+        frame: &Frame,
+        conditions: &[ConditionRef],
+        x: usize,
+        y: usize,
+        source: Option<&TileSource>,
+    ) -> bool {
+        conditions.iter().all(|condition_ref| {
+            let condition = &self.conditions[condition_ref.index];
+            let source_at = |x: usize, y: usize| -> Option<&PixelSource> {
+                if x < SCREEN_WIDTH && y < SCREEN_HEIGHT {
+                    Some(&frame.sources[y * SCREEN_WIDTH + x])
+                } else {
+                    None
+                }
+            };
+            let source_nearby = |dx: isize, dy: isize| {
+                source_at((x as isize + dx) as usize, (y as isize + dy) as usize)
+            };
+            let val = match condition {
+                Condition::TileAtPosition(x, y, tile_match) => tile_match
+                    .matches(source_at(*x, *y).and_then(|pixel| pixel.background.as_ref())),
+                Condition::SpriteAtPosition(x, y, tile_match) => {
+                    tile_match.matches(source_at(*x, *y).and_then(|pixel| pixel.sprite.as_ref()))
+                }
+                Condition::TileNearby(dx, dy, tile_match) => tile_match
+                    .matches(source_nearby(*dx, *dy).and_then(|pixel| pixel.background.as_ref())),
+                Condition::SpriteNearby(dx, dy, tile_match) => tile_match
+                    .matches(source_nearby(*dx, *dy).and_then(|pixel| pixel.sprite.as_ref())),
+                Condition::HorizontalMirror => source.is_some_and(|source| source.flip_horizontal),
+                Condition::VerticalMirror => source.is_some_and(|source| source.flip_vertical),
+                Condition::BackgroundPriority => {
+                    source.is_some_and(|source| source.behind_background)
+                }
+                Condition::MemoryCheck { .. } | Condition::FrameRange(..) => {
+                    frame.frame_conditions[condition_ref.index]
+                }
+            };
+            val != condition_ref.negated
+        })
+    }
+
+    // Returns the rule that replaces the tile of a pixel and its image.
+    fn find_rule(
+        &self,
+        frame: &Frame,
+        x: usize,
+        y: usize,
+        source: &TileSource,
+    ) -> Option<(&TileRule, &Image)> {
+        let keys = [
+            TileKey::Index(source.tile_index),
+            TileKey::Data(source.tile_data),
+        ];
+        keys.iter()
+            .filter_map(|key| self.tile_rules.get(key))
+            .flat_map(|rules| rules.iter())
+            .filter(|(palette, _)| palette.map_or(true, |palette| palette == source.palette))
+            .map(|(_, rule)| &self.rules[*rule])
+            .filter(|rule| self.evaluate(frame, &rule.conditions, x, y, Some(source)))
+            .find_map(|rule| Some((rule, self.images[rule.image].as_ref()?)))
+    }
+
+    // Renders the frame recorded in the pixel sources of `ppu` at the scale of the pack and writes
+    // it to the buffer in `format`. Tiles without a replacement are drawn with their original
+    // colors.
+    pub fn render(&mut self, ppu: &Ppu, memory: &MemoryMap, format: PixelFormat) {
+        let scale = self.scale;
+        let width = self.width();
+        let frame_conditions = self
+            .conditions
+            .iter()
+            .map(|condition| match condition {
+                Condition::MemoryCheck {
+                    ppu: is_ppu,
+                    addr,
+                    operator,
+                    operand,
+                    mask,
+                } => {
+                    let read = |addr: u16| {
+                        if *is_ppu {
+                            ppu.read_byte(addr & 0x3FFF)
+                        } else {
+                            memory.peek_byte(addr)
+                        }
+                    };
+                    let operand = match operand {
+                        Operand::Address(addr) => read(*addr),
+                        Operand::Constant(val) => *val,
+                    };
+                    operator.compare(read(*addr) & mask, operand & mask)
+                }
+                Condition::FrameRange(divisor, val) => ppu.frame % divisor >= *val,
+                _ => false,
+            })
+            .collect();
+        let frame = Frame {
+            sources: &ppu.pixel_sources.pixels,
+            frame_conditions,
+        };
+        let backgrounds: Vec<_> = self
+            .backgrounds
+            .iter()
+            // The conditions of backgrounds are evaluated once per frame at the top left pixel.
+            .filter(|background| self.evaluate(&frame, &background.conditions, 0, 0, None))
+            .filter_map(|background| {
+                let image = self.images[background.image].as_ref()?;
+                let offset_x = ppu.pixel_sources.scroll_x as f32
+                    * background.horizontal_scroll_ratio
+                    * scale as f32;
+                let offset_y = ppu.pixel_sources.scroll_y as f32
+                    * background.vertical_scroll_ratio
+                    * scale as f32;
+                Some((background, image, offset_x as usize, offset_y as usize))
+            })
+            .collect();
+
+        let mut colors = vec![0; width * self.height()];
+        let mut block = vec![0; scale * scale];
+        for (i, pixel) in frame.sources.iter().enumerate() {
+            let (x, y) = (i % SCREEN_WIDTH, i / SCREEN_WIDTH);
+            let backdrop = ppu.palette.color(usize::from(pixel.backdrop));
+            for val in block.iter_mut() {
+                *val = backdrop;
+            }
+
+            for (background, image, offset_x, offset_y) in backgrounds.iter() {
+                for (j, val) in block.iter_mut().enumerate() {
+                    let image_x = x * scale + j % scale + offset_x;
+                    let image_y = y * scale + j / scale + offset_y;
+                    if let Some(image_pixel) = image.get(image_x, image_y) {
+                        *val = blend(*val, image_pixel, background.brightness);
+                    }
+                }
+            }
+
+            let sprite = pixel.sprite.as_ref();
+            let sprite_behind = sprite.is_some_and(|sprite| sprite.behind_background);
+            let layers = [
+                sprite.filter(|_| sprite_behind),
+                pixel.background.as_ref(),
+                sprite.filter(|_| !sprite_behind),
+            ];
+            for source in layers.iter().flatten() {
+                match self.find_rule(&frame, x, y, source) {
+                    Some((rule, image)) => {
+                        for (j, val) in block.iter_mut().enumerate() {
+                            let (mut sx, mut sy) = (j % scale, j / scale);
+                            if source.flip_horizontal {
+                                sx = scale - 1 - sx;
+                            }
+                            if source.flip_vertical {
+                                sy = scale - 1 - sy;
+                            }
+                            let image_x = rule.x + usize::from(source.x) * scale + sx;
+                            let image_y = rule.y + usize::from(source.y) * scale + sy;
+                            if let Some(image_pixel) = image.get(image_x, image_y) {
+                                *val = blend(*val, image_pixel, rule.brightness);
+                            }
+                        }
+                    }
+                    None => {
+                        if source.color != 0 {
+                            let color = ppu.palette.color(usize::from(source.index));
+                            for val in block.iter_mut() {
+                                *val = color;
+                            }
+                        }
+                    }
+                }
+            }
+
+            for (row, pixels) in block.chunks_exact(scale).enumerate() {
+                let offset = (y * scale + row) * width + x * scale;
+                colors[offset..offset + scale].copy_from_slice(pixels);
+            }
+        }
+
+        self.buffer.resize(colors.len() * 4, 0);
+        format.write_pixels(&mut self.buffer, colors.into_iter());
+    }
+}
+
+fn parse_hex(field: &str) -> Option<usize> {
+    let field = field
+        .strip_prefix("0x")
+        .or_else(|| field.strip_prefix('$'))
+        .unwrap_or(field);
+    usize::from_str_radix(field, 16).ok()
+}
+
+// Draws an RGBA pixel of an image with its brightness scaled by `brightness` over an RGB color.
+fn blend(color: u32, pixel: [u8; 4], brightness: f32) -> u32 {
+    let [_, r, g, b] = color.to_be_bytes();
+    let alpha = f32::from(pixel[3]) / 255.0;
+    let mut ret = [0; 3];
+    for (val, (dst, src)) in ret.iter_mut().zip([r, g, b].iter().zip(pixel.iter())) {
+        let src = (f32::from(*src) * brightness).min(255.0);
+        *val = (f32::from(*dst) + (src - f32::from(*dst)) * alpha).round() as u8;
+    }
+    u32::from_be_bytes([0, ret[0], ret[1], ret[2]])
+}
@@ -0,0 +1,6 @@
+mod hd_pack;
+mod ntsc;
+pub mod scale;
+
+pub use self::hd_pack::HdPack;
+pub use self::ntsc::{NtscFilter, NTSC_HEIGHT, NTSC_WIDTH};
@@ -0,0 +1,265 @@
+// Simulates the composite video signal that the PPU generates and decodes it back to RGB, which
+// reproduces the artifact colors and dot crawl of an NTSC television.
+// https://wiki.nesdev.com/w/index.php/NTSC_video
+use crate::ppu::PixelFormat;
+use std::f32::consts::PI;
+
+pub const NTSC_WIDTH: usize = 602;
+pub const NTSC_HEIGHT: usize = 240;
+
+const SCREEN_WIDTH: usize = 256;
+// Each dot lasts 8 of the 12 phases of a cycle of the color subcarrier, and the signal is sampled
+// once per phase.
+const SAMPLES_PER_DOT: usize = 8;
+const SAMPLES_PER_LINE: usize = SCREEN_WIDTH * SAMPLES_PER_DOT;
+// A scanline is 341 dots, so each scanline starts 4 phases after the previous one.
+const PHASES_PER_SCANLINE: usize = 341 * SAMPLES_PER_DOT % 12;
+
+// The voltages of the low and high parts of the signal for each of the four luma levels, relative
+// to the sync voltage.
+const SIGNAL_LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const SIGNAL_HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+
+// The offset in phases between the signal and the color burst that the decoder is synchronized
+// to, which aligns the decoded hues with those of a TV.
+const BURST_OFFSET: f32 = 3.9;
+
+// The number of samples averaged for luma at full sharpness. Chroma is always averaged over a
+// full cycle of the subcarrier.
+const SHARP_LUMA_SAMPLES: usize = 4;
+
+fn in_color_phase(color: usize, phase: usize) -> bool {
+    (color + phase) % 12 < 6
+}
+
+// Returns the normalized level of the signal for a 9 bit pixel value at `phase`.
+fn signal_level(pixel: usize, phase: usize) -> f32 {
+    let color = pixel & 0x0F;
+    // Colors $xE and $xF are black.
+    let level = if color > 0x0D { 1 } else { (pixel >> 4) & 0x03 };
+    let emphasis = pixel >> 6;
+
+    let low = if color == 0x00 {
+        SIGNAL_HIGH[level]
+    } else {
+        SIGNAL_LOW[level]
+    };
+    let high = if color < 0x0D {
+        SIGNAL_HIGH[level]
+    } else {
+        SIGNAL_LOW[level]
+    };
+    let mut signal = if in_color_phase(color, phase) {
+        high
+    } else {
+        low
+    };
+
+    // Red, green, and blue emphasis attenuate the signal during the phases of colors $x0, $x4,
+    // and $x8 respectively.
+    if (emphasis & 0x01 != 0 && in_color_phase(0x00, phase))
+        || (emphasis & 0x02 != 0 && in_color_phase(0x04, phase))
+        || (emphasis & 0x04 != 0 && in_color_phase(0x08, phase))
+    {
+        signal *= EMPHASIS_ATTENUATION;
+    }
+
+    (signal - BLACK) / (WHITE - BLACK)
+}
+
+// Corrects for the difference between the gamma of a TV and that of a computer monitor.
+fn gamma_correct(val: f32) -> u32 {
+    if val <= 0.0 {
+        0
+    } else {
+        (val.powf(2.2 / 1.8) * 255.0).round().min(255.0) as u32
+    }
+}
+
+pub struct NtscFilter {
+    pub enabled: bool,
+    hue: f32,
+    saturation: f32,
+    sharpness: f32,
+    // Averages each frame with the frame that has the other phase of the subcarrier, which
+    // removes dot crawl.
+    pub merge_fields: bool,
+    // The signal level of each 9 bit pixel value at each of the 12 phases.
+    levels: Vec<[f32; 12]>,
+    // The decoder's reference waves for I and Q at each phase.
+    i_wave: [f32; 12],
+    q_wave: [f32; 12],
+    signal: Vec<f32>,
+    last_frame_phase: usize,
+    colors: Vec<[f32; 3]>,
+    pub buffer: Vec<u8>,
+}
+
+impl NtscFilter {
+    pub fn new() -> Self {
+        let levels = (0..512)
+            .map(|pixel| {
+                let mut levels = [0.0; 12];
+                for (phase, level) in levels.iter_mut().enumerate() {
+                    *level = signal_level(pixel, phase);
+                }
+                levels
+            })
+            .collect();
+        let mut filter = NtscFilter {
+            enabled: false,
+            hue: 0.0,
+            saturation: 1.0,
+            sharpness: 0.0,
+            merge_fields: false,
+            levels,
+            i_wave: [0.0; 12],
+            q_wave: [0.0; 12],
+            signal: vec![0.0; SAMPLES_PER_LINE],
+            last_frame_phase: 0,
+            colors: vec![[0.0; 3]; NTSC_WIDTH * NTSC_HEIGHT],
+            buffer: vec![0; NTSC_WIDTH * NTSC_HEIGHT * 4],
+        };
+        filter.update_waves();
+        filter
+    }
+
+    // Shifts the hue by `hue` degrees.
+    pub fn set_hue(&mut self, hue: f32) {
+        self.hue = hue;
+        self.update_waves();
+    }
+
+    pub fn hue(&self) -> f32 {
+        self.hue
+    }
+
+    // Scales the saturation, where 0 is greyscale and 1 is unchanged.
+    pub fn set_saturation(&mut self, saturation: f32) {
+        self.saturation = saturation.max(0.0);
+        self.update_waves();
+    }
+
+    pub fn saturation(&self) -> f32 {
+        self.saturation
+    }
+
+    // Sets the sharpness of luma in the range [0, 1], where 0 filters luma as much as chroma.
+    pub fn set_sharpness(&mut self, sharpness: f32) {
+        self.sharpness = sharpness.clamp(0.0, 1.0);
+    }
+
+    pub fn sharpness(&self) -> f32 {
+        self.sharpness
+    }
+
+    fn update_waves(&mut self) {
+        // A cycle of the subcarrier is 12 phases, or 360 degrees.
+        let offset = BURST_OFFSET + self.hue / 30.0;
+        for phase in 0..12 {
+            let angle = PI * (phase as f32 + offset) / 6.0;
+            self.i_wave[phase] = angle.cos() * self.saturation;
+            self.q_wave[phase] = angle.sin() * self.saturation;
+        }
+    }
+
+    // Filters a frame of 9 bit pixel values from the index buffer, where `frame_phase` is the
+    // phase of the subcarrier at the first pixel, and writes the image to the buffer in `format`.
+    pub fn render(&mut self, index_buffer: &[u16], frame_phase: u8, format: PixelFormat) {
+        let frame_phase = usize::from(frame_phase);
+        // On alternate frames, the phase is shifted by 4 when the pre-render scanline is a dot
+        // shorter. The fields are merged with the phase of the last frame or, if the phase did
+        // not change, with a phase that would have been used by the other field.
+        let other_phase = if self.last_frame_phase != frame_phase {
+            self.last_frame_phase
+        } else {
+            (frame_phase + 4) % 12
+        };
+        self.last_frame_phase = frame_phase;
+
+        for (y, line) in index_buffer.chunks_exact(SCREEN_WIDTH).enumerate() {
+            let row = y * NTSC_WIDTH..(y + 1) * NTSC_WIDTH;
+            let line_phase = (frame_phase + y * PHASES_PER_SCANLINE) % 12;
+            self.decode_line(line, line_phase, row.clone(), false);
+            if self.merge_fields {
+                let line_phase = (other_phase + y * PHASES_PER_SCANLINE) % 12;
+                self.decode_line(line, line_phase, row, true);
+            }
+        }
+
+        let merge_fields = self.merge_fields;
+        let colors = self.colors.iter().map(|&yiq| {
+            let [y, i, q] = if merge_fields {
+                [yiq[0] / 2.0, yiq[1] / 2.0, yiq[2] / 2.0]
+            } else {
+                yiq
+            };
+            let r = gamma_correct(y + 0.946_882 * i + 0.623_557 * q);
+            let g = gamma_correct(y - 0.274_788 * i - 0.635_691 * q);
+            let b = gamma_correct(y - 1.108_545 * i + 1.709_007 * q);
+            (r << 16) | (g << 8) | b
+        });
+        format.write_pixels(&mut self.buffer, colors);
+    }
+
+    // Generates the signal for a scanline starting at `line_phase` and decodes it to YIQ colors
+    // in `row` of the output. The colors are added to the existing colors when `accumulate` is
+    // set.
+    fn decode_line(
+        &mut self,
+        line: &[u16],
+        line_phase: usize,
+        row: std::ops::Range<usize>,
+        accumulate: bool,
+    ) {
+        for (x, &pixel) in line.iter().enumerate() {
+            let levels = &self.levels[usize::from(pixel & 0x01FF)];
+            for sample in 0..SAMPLES_PER_DOT {
+                let phase = (line_phase + x * SAMPLES_PER_DOT + sample) % 12;
+                self.signal[x * SAMPLES_PER_DOT + sample] = levels[phase];
+            }
+        }
+
+        let sharpness = self.sharpness;
+        for (x, color) in self.colors[row].iter_mut().enumerate() {
+            let center = (x * SAMPLES_PER_LINE + SAMPLES_PER_LINE / 2) / NTSC_WIDTH;
+            let begin = center.saturating_sub(6);
+            let end = (center + 6).min(SAMPLES_PER_LINE);
+
+            let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+            for p in begin..end {
+                let level = self.signal[p];
+                let phase = (line_phase + p) % 12;
+                y += level;
+                i += level * self.i_wave[phase];
+                q += level * self.q_wave[phase];
+            }
+            let len = (end - begin) as f32;
+            let (mut y, i, q) = (y / len, i / 12.0, q / 12.0);
+
+            if sharpness > 0.0 {
+                let begin = center.saturating_sub(SHARP_LUMA_SAMPLES / 2);
+                let end = (center + SHARP_LUMA_SAMPLES / 2).min(SAMPLES_PER_LINE);
+                let sharp_y = self.signal[begin..end].iter().sum::<f32>() / (end - begin) as f32;
+                y += sharpness * (sharp_y - y);
+            }
+
+            if accumulate {
+                color[0] += y;
+                color[1] += i;
+                color[2] += q;
+            } else {
+                *color = [y, i, q];
+            }
+        }
+    }
+}
+
+impl Default for NtscFilter {
+    fn default() -> Self {
+        NtscFilter::new()
+    }
+}
@@ -0,0 +1,323 @@
+// Pixel art scalers that upscale an RGBA8888 image by an integer factor.
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+/// A pixel art scaling algorithm for `scale_image`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub enum Scaler {
+    /// Scale2x (EPX), which doubles the size and rounds diagonal edges without blending.
+    Scale2x,
+    /// Scale3x, which triples the size and rounds diagonal edges without blending.
+    Scale3x,
+    /// Edge blending at 2x, which doubles the size and blends the corners of pixels on edges
+    /// between dissimilar colors.
+    EdgeBlend2x,
+    /// Edge blending at 3x, which triples the size and blends the corners of pixels on edges
+    /// between dissimilar colors.
+    EdgeBlend3x,
+    /// Edge blending at 4x, which quadruples the size and blends the corners of pixels on edges
+    /// between dissimilar colors.
+    EdgeBlend4x,
+    /// xBR at 2x, which detects edges from a 5x5 neighborhood and anti-aliases them.
+    Xbr2x,
+    /// xBR at 3x, which detects edges from a 5x5 neighborhood and anti-aliases them.
+    Xbr3x,
+    /// xBR at 4x, which detects edges from a 5x5 neighborhood and anti-aliases them.
+    Xbr4x,
+}
+
+impl Scaler {
+    /// Returns the factor that the width and height of the image are multiplied by.
+    pub fn factor(self) -> usize {
+        match self {
+            Scaler::Scale2x | Scaler::EdgeBlend2x | Scaler::Xbr2x => 2,
+            Scaler::Scale3x | Scaler::EdgeBlend3x | Scaler::Xbr3x => 3,
+            Scaler::EdgeBlend4x | Scaler::Xbr4x => 4,
+        }
+    }
+}
+
+/// Upscales `src`, an RGBA8888 image of `width` by `height` pixels in row-major order, with
+/// `scaler` and writes the result to `dst`. The scaled image is `width * scaler.factor()` by
+/// `height * scaler.factor()` pixels.
+///
+/// # Panics
+///
+/// Panics if `src` is not `width * height * 4` bytes or `dst` is not `src.len() *
+/// scaler.factor().pow(2)` bytes.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn scale_image(scaler: Scaler, src: &[u8], width: usize, height: usize, dst: &mut [u8]) {
+    let factor = scaler.factor();
+    if src.len() != width * height * 4 {
+        panic!(
+            "[SCALE] Expected source image to be {} bytes.",
+            width * height * 4
+        );
+    }
+    if dst.len() != src.len() * factor * factor {
+        panic!(
+            "[SCALE] Expected destination image to be {} bytes.",
+            src.len() * factor * factor
+        );
+    }
+
+    let image = Image {
+        pixels: src
+            .chunks_exact(4)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]])
+            .collect(),
+        width,
+        height,
+    };
+    let mut block = vec![[0; 4]; factor * factor];
+    for y in 0..height {
+        for x in 0..width {
+            match scaler {
+                Scaler::Scale2x | Scaler::Scale3x => scale_epx(&image, x, y, factor, &mut block),
+                Scaler::EdgeBlend2x | Scaler::EdgeBlend3x | Scaler::EdgeBlend4x => {
+                    scale_edge_blend(&image, x, y, factor, &mut block)
+                }
+                Scaler::Xbr2x | Scaler::Xbr3x | Scaler::Xbr4x => {
+                    scale_xbr(&image, x, y, factor, &mut block)
+                }
+            }
+            for (row, pixels) in block.chunks_exact(factor).enumerate() {
+                let offset = ((y * factor + row) * width * factor + x * factor) * 4;
+                for (pixel, val) in dst[offset..offset + factor * 4]
+                    .chunks_exact_mut(4)
+                    .zip(pixels)
+                {
+                    pixel.copy_from_slice(val);
+                }
+            }
+        }
+    }
+}
+
+type Pixel = [u8; 4];
+
+struct Image {
+    pixels: Vec<Pixel>,
+    width: usize,
+    height: usize,
+}
+
+impl Image {
+    // Returns the pixel at an offset from (x, y). Pixels outside of the image are clamped to the
+    // nearest edge.
+    fn get(&self, x: usize, y: usize, dx: isize, dy: isize) -> Pixel {
+        let x = (x as isize + dx).clamp(0, self.width as isize - 1) as usize;
+        let y = (y as isize + dy).clamp(0, self.height as isize - 1) as usize;
+        self.pixels[y * self.width + x]
+    }
+}
+
+// Blends two pixels, where `weight` is the fraction of `b`.
+fn blend(a: Pixel, b: Pixel, weight: f32) -> Pixel {
+    let mut ret = [0; 4];
+    for (val, (a, b)) in ret.iter_mut().zip(a.iter().zip(b.iter())) {
+        *val = (f32::from(*a) + (f32::from(*b) - f32::from(*a)) * weight).round() as u8;
+    }
+    ret
+}
+
+fn to_yuv(pixel: Pixel) -> [f32; 3] {
+    let [r, g, b] = [
+        f32::from(pixel[0]),
+        f32::from(pixel[1]),
+        f32::from(pixel[2]),
+    ];
+    [
+        0.299 * r + 0.587 * g + 0.114 * b,
+        -0.169 * r - 0.331 * g + 0.5 * b,
+        0.5 * r - 0.419 * g - 0.081 * b,
+    ]
+}
+
+// The YUV thresholds that hqx uses for two colors to be considered different.
+// https://en.wikipedia.org/wiki/Hqx
+fn is_different(a: Pixel, b: Pixel) -> bool {
+    let (a, b) = (to_yuv(a), to_yuv(b));
+    (a[0] - b[0]).abs() > 48.0 || (a[1] - b[1]).abs() > 7.0 || (a[2] - b[2]).abs() > 6.0
+}
+
+// The weighted distance between two colors that xBR uses to detect edges.
+fn distance(a: Pixel, b: Pixel) -> f32 {
+    let (a, b) = (to_yuv(a), to_yuv(b));
+    48.0 * (a[0] - b[0]).abs() + 7.0 * (a[1] - b[1]).abs() + 6.0 * (a[2] - b[2]).abs()
+}
+
+// The offsets of each of the corners of a pixel, which the neighborhood of a pixel is reflected
+// by so that each corner is handled as the bottom right corner.
+const CORNERS: [(isize, isize); 4] = [(1, 1), (-1, 1), (1, -1), (-1, -1)];
+
+// Scale2x and Scale3x.
+// https://www.scale2x.it/algorithm
+fn scale_epx(image: &Image, x: usize, y: usize, factor: usize, block: &mut [Pixel]) {
+    // A B C
+    // D E F
+    // G H I
+    let get = |dx, dy| image.get(x, y, dx, dy);
+    let (a, b, c) = (get(-1, -1), get(0, -1), get(1, -1));
+    let (d, e, f) = (get(-1, 0), get(0, 0), get(1, 0));
+    let (g, h, i) = (get(-1, 1), get(0, 1), get(1, 1));
+
+    for pixel in block.iter_mut() {
+        *pixel = e;
+    }
+    if b == h || d == f {
+        return;
+    }
+
+    if factor == 2 {
+        if d == b {
+            block[0] = d;
+        }
+        if b == f {
+            block[1] = f;
+        }
+        if d == h {
+            block[2] = d;
+        }
+        if h == f {
+            block[3] = f;
+        }
+    } else {
+        if d == b {
+            block[0] = d;
+        }
+        if (d == b && e != c) || (b == f && e != a) {
+            block[1] = b;
+        }
+        if b == f {
+            block[2] = f;
+        }
+        if (d == b && e != g) || (d == h && e != a) {
+            block[3] = d;
+        }
+        if (b == f && e != i) || (h == f && e != c) {
+            block[5] = f;
+        }
+        if d == h {
+            block[6] = d;
+        }
+        if (d == h && e != i) || (h == f && e != g) {
+            block[7] = h;
+        }
+        if h == f {
+            block[8] = f;
+        }
+    }
+}
+
+// Returns the weight of the corner triangle that a subpixel of a block is in. The triangle is
+// bounded by the line through the midpoints of the edges that meet at the bottom right corner, so
+// the weight is 0 for subpixels on the other side of the line and grows to 1 at the corner. The
+// triangles of adjacent corners overlap, so corners are blended on top of each other.
+fn corner_weight(factor: usize, sx: usize, sy: usize) -> f32 {
+    let u = (sx as f32 + 0.5) / factor as f32;
+    let v = (sy as f32 + 0.5) / factor as f32;
+    (u + v - 1.0).clamp(0.0, 1.0)
+}
+
+// Returns the index of the subpixel in a block after reflecting it to the corner `(cx, cy)`.
+fn reflect(factor: usize, sx: usize, sy: usize, (cx, cy): (isize, isize)) -> usize {
+    let x = if cx > 0 { sx } else { factor - 1 - sx };
+    let y = if cy > 0 { sy } else { factor - 1 - sy };
+    y * factor + x
+}
+
+// Edge blending, which blends each corner of a pixel from the neighbors that the YUV thresholds of
+// hqx consider different from the pixel. It does not use the pattern table of hqx. A corner whose
+// two adjacent neighbors are similar to each other and different from the pixel is on an edge and
+// is blended towards them, and a corner with only a different diagonal neighbor is blended
+// slightly towards it.
+fn scale_edge_blend(image: &Image, x: usize, y: usize, factor: usize, block: &mut [Pixel]) {
+    let e = image.get(x, y, 0, 0);
+    for pixel in block.iter_mut() {
+        *pixel = e;
+    }
+
+    for &(cx, cy) in CORNERS.iter() {
+        let horizontal = image.get(x, y, cx, 0);
+        let vertical = image.get(x, y, 0, cy);
+        let diagonal = image.get(x, y, cx, cy);
+
+        let (target, strength) = if !is_different(horizontal, vertical)
+            && is_different(e, horizontal)
+            && is_different(e, vertical)
+        {
+            (blend(horizontal, vertical, 0.5), 0.75)
+        } else if is_different(e, diagonal)
+            && !is_different(e, horizontal)
+            && !is_different(e, vertical)
+        {
+            (diagonal, 0.25)
+        } else {
+            continue;
+        };
+
+        for sy in 0..factor {
+            for sx in 0..factor {
+                let weight = corner_weight(factor, sx, sy) * strength;
+                if weight > 0.0 {
+                    let index = reflect(factor, sx, sy, (cx, cy));
+                    block[index] = blend(block[index], target, weight);
+                }
+            }
+        }
+    }
+}
+
+// The first level of xBR, which compares the weighted distances along the two diagonals of each
+// corner in a 5x5 neighborhood to find edges, and anti-aliases the corner along them.
+// https://forums.libretro.com/t/xbr-algorithm-tutorial/123
+fn scale_xbr(image: &Image, x: usize, y: usize, factor: usize, block: &mut [Pixel]) {
+    let e = image.get(x, y, 0, 0);
+    for pixel in block.iter_mut() {
+        *pixel = e;
+    }
+
+    for &(cx, cy) in CORNERS.iter() {
+        // The neighborhood reflected so that the corner is at the bottom right of E.
+        //    A1 B1 C1
+        // A0 A  B  C  C4
+        // D0 D  E  F  F4
+        // G0 G  H  I  I4
+        //    G5 H5 I5
+        let get = |dx: isize, dy: isize| image.get(x, y, dx * cx, dy * cy);
+        let (c, g, i) = (get(1, -1), get(-1, 1), get(1, 1));
+        let (b, d, f, h) = (get(0, -1), get(-1, 0), get(1, 0), get(0, 1));
+        let (f4, h5, i4, i5) = (get(2, 0), get(0, 2), get(2, 1), get(1, 2));
+
+        let edge_h_f = distance(e, c)
+            + distance(e, g)
+            + distance(i, f4)
+            + distance(i, h5)
+            + 4.0 * distance(h, f);
+        let edge_e_i = distance(h, d)
+            + distance(h, i5)
+            + distance(f, i4)
+            + distance(f, b)
+            + 4.0 * distance(e, i);
+        if edge_h_f >= edge_e_i {
+            continue;
+        }
+
+        let target = if distance(e, f) <= distance(e, h) {
+            f
+        } else {
+            h
+        };
+        for sy in 0..factor {
+            for sx in 0..factor {
+                let weight = corner_weight(factor, sx, sy);
+                if weight > 0.0 {
+                    let index = reflect(factor, sx, sy, (cx, cy));
+                    block[index] = blend(block[index], target, weight);
+                }
+            }
+        }
+    }
+}